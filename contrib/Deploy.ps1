@@ -94,8 +94,6 @@ try {
     else {
         New-Item C:\fxrecorder -ItemType Directory -Force > $null
         Copy-Item -Force -Path target\release\fxrecorder.exe -ToSession $session -Destination C:\fxrecorder
-        Copy-Item -Force -Path vendor -ToSession $session -Destination C:\fxrecorder
-        Copy-Item -Force -Path requirements.txt -ToSession $session -Destination C:\fxrecorder
     }
 
 }
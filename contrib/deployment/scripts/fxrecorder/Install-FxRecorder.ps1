@@ -16,7 +16,6 @@ Test-AdminRole
 
 $TASKCLUSTER_VERSION = "v44.0.0"
 $NSSM_VERSION = "2.24"
-$PYTHON_VERSION = "3.9.9"
 
 Set-ExecutionPolicy Unrestricted -Force -Scope Process
 
@@ -93,18 +92,3 @@ Set-Content -Path $CONFIG_FILE @"
 & $nssm set "Generic Worker" AppRotateFiles 1
 & $nssm set "Generic Worker" AppRotateSeconds 3600
 & $nssm set "Generic Worker" AppRotateBytes 0
-
-# Download and install Python
-$pyInstaller = "C:\python-installer.exe"
-$pythonDir = "C:\python-${PYTHON_VERSION}"
-
-$webClient.DownloadFile("https://www.python.org/ftp/python/${PYTHON_VERSION}/python-${PYTHON_VERSION}-amd64.exe", $pyInstaller)
-& $pyInstaller /quiet InstallAllUsers=1 PrependPath=1 Include_test=0 TargetDir=$pythonDir
-Remove-Item $pyInstaller
-
-# Install Python dependencies.
-# C:\fxrecorder\requirements.txt is deployed by Deploy.ps1
-
-$env:PATH += ";${pythonDir}"
-python -m pip install -U pip wheel
-python -m pip install --require-hashes -r C:\fxrecorder\requirements.txt
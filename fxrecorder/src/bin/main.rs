@@ -5,26 +5,39 @@
 use std::env::current_dir;
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use libfxrecord::config::read_config;
 use libfxrecord::error::ErrorMessage;
 use libfxrecord::logging::build_terminal_logger;
-use libfxrecord::net::Idle;
+use libfxrecord::net::{BuildSource, Idle, SessionState, DEFAULT_BRANCH};
 use libfxrecord::prefs::{parse_pref, PrefValue};
+use libfxrecorder::aggregate::ReplicateSet;
 use libfxrecorder::analysis::{compute_visual_metrics, crop_video, VisualMetrics};
+use libfxrecorder::compare::{compare, MetricComparison};
 use libfxrecorder::config::Config;
 use libfxrecorder::perfherder::generate_perfherder_metrics;
-use libfxrecorder::proto::RecorderProto;
+use libfxrecorder::proto::{Build, RecorderProto, RecorderProtoError};
 use libfxrecorder::recorder::FfmpegRecorder;
 use libfxrecorder::retry::delayed_exponential_retry;
-use slog::{error, info, Logger};
+use serde::Serialize;
+use slog::{error, info, warn, Logger};
 use structopt::StructOpt;
 use tempfile::TempDir;
 use tokio::net::TcpStream;
+use tokio::time::delay_for;
+
+/// The number of times to attempt sending a profile before giving up.
+const PROFILE_TRANSFER_ATTEMPTS: u32 = 3;
+
+/// How often to ask the runner whether a queued job has started.
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for a queued job to start by default, in minutes.
+const DEFAULT_QUEUE_TIMEOUT_MINS: &str = "240";
 
 /// Record and analyze videos of Firefox desktop startup.
 #[derive(Debug, StructOpt)]
@@ -53,17 +66,113 @@ enum Command {
 
     /// Analyze a recorded video and compute visual metrics.
     Analyze(AnalyzeOptions),
+
+    /// Compare the visual metrics of a base build and a new build.
+    ///
+    /// Each build is either recorded or loaded from the saved output of a
+    /// previous `record` command.
+    Compare(CompareOptions),
+
+    /// Inspect and manage the sessions on the runner.
+    Sessions(SessionsCommand),
+
+    /// Report the status of the runner.
+    ///
+    /// Exits with a non-zero status if the runner is unhealthy.
+    Status(StatusOptions),
+}
+
+/// Inspect and manage the sessions on the runner.
+#[derive(Debug, StructOpt)]
+enum SessionsCommand {
+    /// List the sessions on the runner.
+    List,
+
+    /// Show a single session on the runner.
+    Show {
+        /// The ID of the session.
+        session_id: String,
+    },
+
+    /// Delete a session on the runner.
+    Delete {
+        /// The ID of the session.
+        session_id: String,
+    },
+
+    /// Delete every session on the runner.
+    Purge,
+}
+
+/// Report the status of the runner.
+#[derive(Debug, StructOpt)]
+struct StatusOptions {
+    /// The space that must be free in the runner's session directory (in MiB)
+    /// for the runner to be considered healthy.
+    #[structopt(long, default_value = "1024")]
+    min_free_disk: u64,
 }
 
 /// Record a video from FxRunner and perform analysis.
 #[derive(Debug, StructOpt)]
 struct RecordOptions {
     /// The ID of a build task that will be used by the runner.
-    #[structopt(env = "FXRECORD_TASK_ID")]
-    task_id: String,
+    #[structopt(
+        env = "FXRECORD_TASK_ID",
+        required_unless_one = &[
+            "revision",
+            "latest",
+            "index-path",
+            "build-path",
+            "runner-build-path",
+        ],
+    )]
+    task_id: Option<String>,
+
+    /// The revision of a build that will be used by the runner.
+    ///
+    /// The runner will find the build in the Taskcluster index.
+    #[structopt(long, conflicts_with_all = &["task-id", "latest", "index-path"])]
+    revision: Option<String>,
+
+    /// Use the latest build on the branch.
+    #[structopt(long, conflicts_with_all = &["task-id", "revision", "index-path"])]
+    latest: bool,
+
+    /// The branch to find the build on when using `--revision` or `--latest`.
+    #[structopt(long, default_value = DEFAULT_BRANCH)]
+    branch: String,
+
+    /// A path in the Taskcluster index of a build task that will be used by
+    /// the runner.
+    #[structopt(long = "index", conflicts_with_all = &["task-id", "revision", "latest"])]
+    index_path: Option<String>,
+
+    /// The path to a build archive that will be sent to the runner.
+    ///
+    /// This allows recording a local build without pushing it to Taskcluster.
+    #[structopt(
+        long = "build",
+        conflicts_with_all = &["task-id", "revision", "latest", "index-path", "runner-build-path"],
+    )]
+    build_path: Option<PathBuf>,
+
+    /// The path to a build archive on the runner, such as on a shared
+    /// directory.
+    ///
+    /// The build must be inside the runner's configured build directory.
+    #[structopt(
+        long = "runner-build",
+        conflicts_with_all = &["task-id", "revision", "latest", "index-path"],
+    )]
+    runner_build_path: Option<String>,
 
     /// The path to a zipped Firefox profile for the runner to use.
     ///
+    /// Profiles archived as `.tar`, `.tar.gz`, `.tar.bz2` or `.tar.xz` are
+    /// extracted by the runner as they are sent, which avoids writing the
+    /// archive to disk on the runner.
+    ///
     /// If not provided, the runner will create a new profile.
     #[structopt(long = "profile")]
     profile_path: Option<PathBuf>,
@@ -79,9 +188,122 @@ struct RecordOptions {
     #[structopt(long)]
     skip_idle: bool,
 
+    /// The number of times to record Firefox starting.
+    ///
+    /// The runner will restart between each run and re-use the same build and
+    /// profile.
+    #[structopt(long, default_value = "1")]
+    runs: u32,
+
     /// Do not delete the video after analysis.
     #[structopt(long = "keep-video")]
     keep_video: bool,
+
+    /// Submit the recording to the runner's job queue instead of requiring
+    /// the runner to be free.
+    ///
+    /// The runner starts the job once it has finished any other sessions.
+    /// Queued jobs cannot use a build sent from the recorder or a streamed
+    /// profile.
+    #[structopt(long, conflicts_with = "build-path")]
+    queue: bool,
+
+    /// How long to wait for a queued job to start, in minutes.
+    #[structopt(long = "queue-timeout", default_value = DEFAULT_QUEUE_TIMEOUT_MINS)]
+    queue_timeout_mins: u64,
+}
+
+impl RecordOptions {
+    /// Create options that record the build from the given task.
+    fn with_task_id(task_id: &str, options: &CompareOptions) -> Self {
+        RecordOptions {
+            task_id: Some(task_id.into()),
+            revision: None,
+            latest: false,
+            branch: DEFAULT_BRANCH.into(),
+            index_path: None,
+            build_path: None,
+            runner_build_path: None,
+            profile_path: options.profile_path.clone(),
+            prefs: options.prefs.clone(),
+            skip_idle: options.skip_idle,
+            runs: options.runs,
+            keep_video: false,
+            queue: false,
+            queue_timeout_mins: DEFAULT_QUEUE_TIMEOUT_MINS.parse().unwrap(),
+        }
+    }
+
+    /// The build that the runner will use.
+    fn build(&self) -> Build {
+        if let Some(ref build_path) = self.build_path {
+            Build::Sideload(build_path.clone())
+        } else if let Some(ref runner_build_path) = self.runner_build_path {
+            BuildSource::RunnerPath(runner_build_path.clone()).into()
+        } else if let Some(ref revision) = self.revision {
+            BuildSource::revision(&self.branch, revision).into()
+        } else if self.latest {
+            BuildSource::latest(&self.branch).into()
+        } else if let Some(ref index_path) = self.index_path {
+            BuildSource::IndexPath(index_path.clone()).into()
+        } else {
+            BuildSource::TaskId(
+                self.task_id
+                    .clone()
+                    .expect("a task ID is required without a revision, index path, or build"),
+            )
+            .into()
+        }
+    }
+}
+
+/// Compare two builds.
+#[derive(Debug, StructOpt)]
+struct CompareOptions {
+    /// The ID of the base build task to record.
+    #[structopt(long, required_unless = "base-results")]
+    base: Option<String>,
+
+    /// The path to previously saved results for the base build.
+    #[structopt(long, conflicts_with = "base")]
+    base_results: Option<PathBuf>,
+
+    /// The ID of the new build task to record.
+    #[structopt(long, required_unless = "new-results")]
+    new: Option<String>,
+
+    /// The path to previously saved results for the new build.
+    #[structopt(long, conflicts_with = "new")]
+    new_results: Option<PathBuf>,
+
+    /// The confidence level of the reported confidence intervals.
+    #[structopt(long, default_value = "0.95")]
+    confidence: f64,
+
+    /// The path to a zipped Firefox profile for the runner to use.
+    ///
+    /// Profiles archived as `.tar`, `.tar.gz`, `.tar.bz2` or `.tar.xz` are
+    /// extracted by the runner as they are sent, which avoids writing the
+    /// archive to disk on the runner.
+    ///
+    /// If not provided, the runner will create a new profile.
+    #[structopt(long = "profile")]
+    profile_path: Option<PathBuf>,
+
+    /// Preferences that the runner should use.
+    ///
+    /// Preferences should be of the form `pref.name:value` where value is a
+    /// string, boolean, or number.
+    #[structopt(long = "pref", number_of_values(1), parse(try_from_str = parse_pref))]
+    prefs: Vec<(String, PrefValue)>,
+
+    /// Do not require the runner to become idle before running Firefox.
+    #[structopt(long)]
+    skip_idle: bool,
+
+    /// The number of times to record each build starting.
+    #[structopt(long, default_value = "5")]
+    runs: u32,
 }
 
 /// Analyze a pre-recorded video.
@@ -100,28 +322,47 @@ fn main() {
     let result = || -> Result<(), Box<dyn Error>> {
         let config: Config = read_config(&options.config_path, "fxrecorder")?;
 
-        let metrics = match options.command {
-            Command::Record(ref record_options) => record(log.clone(), config, record_options),
+        let visual_complete_thresholds = config.perfherder.visual_complete_thresholds.clone();
+
+        match options.command {
+            Command::Record(ref record_options) => {
+                let replicates = record(log.clone(), &config, record_options)?;
+                report_replicates(
+                    log.clone(),
+                    options.output_path.as_deref(),
+                    ReplicateSet::new(replicates, &visual_complete_thresholds),
+                )?;
+            }
             Command::Analyze(ref analyze_options) => {
-                analyze_video(log.clone(), config, &analyze_options)
+                let metrics = analyze_video(log.clone(), &analyze_options)?;
+                report_replicates(
+                    log.clone(),
+                    options.output_path.as_deref(),
+                    ReplicateSet::new(vec![metrics], &visual_complete_thresholds),
+                )?;
+            }
+            Command::Compare(ref compare_options) => {
+                let comparisons = compare_builds(log.clone(), &config, compare_options)?;
+                write_output(options.output_path.as_deref(), &comparisons)?;
+            }
+            Command::Sessions(ref command) => {
+                manage_sessions(
+                    log.clone(),
+                    &config,
+                    command,
+                    options.output_path.as_deref(),
+                )?;
+            }
+            Command::Status(ref status_options) => {
+                runner_status(
+                    log.clone(),
+                    &config,
+                    status_options,
+                    options.output_path.as_deref(),
+                )?;
             }
-        }?;
-
-        let metrics_json =
-            serde_json::to_string(&metrics).expect("could not serialize visual metrics");
-
-        let perfherder_metrics = serde_json::to_string(&generate_perfherder_metrics(&metrics))
-            .expect("could not serialize perfherder metrics");
-
-        if let Some(output_path) = options.output_path.as_deref() {
-            let mut f = File::create(output_path)?;
-            write!(f, "{}", metrics_json)?;
-        } else {
-            println!("{}", metrics_json);
         }
 
-        println!("PERFHERDER_DATA: {}", perfherder_metrics);
-
         Ok(())
     }();
 
@@ -132,14 +373,224 @@ fn main() {
     }
 }
 
+/// Log the aggregated metrics, write the replicate set, and print the
+/// Perfherder data.
+fn report_replicates(
+    log: Logger,
+    output_path: Option<&Path>,
+    replicate_set: ReplicateSet,
+) -> Result<(), Box<dyn Error>> {
+    for metric in &replicate_set.metrics {
+        info!(
+            log,
+            "aggregated metric";
+            "name" => &metric.name,
+            "value" => metric.value(),
+            "mean" => metric.summary.mean,
+            "stddev" => metric.summary.stddev,
+            "outliers" => metric.summary.count - metric.filtered.count,
+        );
+    }
+
+    write_output(output_path, &replicate_set)?;
+
+    let perfherder_metrics =
+        serde_json::to_string(&generate_perfherder_metrics(&replicate_set.metrics))
+            .expect("could not serialize perfherder metrics");
+
+    println!("PERFHERDER_DATA: {}", perfherder_metrics);
+
+    Ok(())
+}
+
+/// Write the value as JSON to the output path, or stdout if there is no output
+/// path.
+fn write_output<T: Serialize>(output_path: Option<&Path>, value: &T) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string(value).expect("could not serialize visual metrics");
+
+    if let Some(output_path) = output_path {
+        let mut f = File::create(output_path)?;
+        write!(f, "{}", json)?;
+    } else {
+        println!("{}", json);
+    }
+
+    Ok(())
+}
+
+/// Record or load the base and new builds and compare their metrics.
+fn compare_builds(
+    log: Logger,
+    config: &Config,
+    options: &CompareOptions,
+) -> Result<Vec<MetricComparison>, Box<dyn Error>> {
+    if !(options.confidence > 0.0 && options.confidence < 1.0) {
+        return Err(ErrorMessage("confidence must be between 0 and 1").into());
+    }
+
+    let base = record_or_load(
+        log.clone(),
+        config,
+        options,
+        options.base.as_deref(),
+        options.base_results.as_deref(),
+    )?;
+    let new = record_or_load(
+        log.clone(),
+        config,
+        options,
+        options.new.as_deref(),
+        options.new_results.as_deref(),
+    )?;
+
+    let comparisons = compare(&base, &new, options.confidence);
+
+    for comparison in &comparisons {
+        info!(
+            log,
+            "compared metric";
+            "name" => &comparison.name,
+            "base" => comparison.base.mean,
+            "new" => comparison.new.mean,
+            "delta" => comparison.delta,
+            "percent_change" => ?comparison.percent_change,
+            "t_test_p" => ?comparison.t_test.as_ref().map(|t| t.p_value),
+            "mann_whitney_p" => ?comparison.mann_whitney.as_ref().map(|u| u.p_value),
+            "confidence_interval" => ?comparison.confidence_interval,
+        );
+    }
+
+    Ok(comparisons)
+}
+
+/// Run a session command against the runner and write its result.
+#[tokio::main]
+async fn manage_sessions(
+    log: Logger,
+    config: &Config,
+    command: &SessionsCommand,
+    output_path: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect(&config.host).await?;
+    info!(log, "Connected"; "peer" => &config.host);
+
+    let mut proto = RecorderProto::new(
+        log.clone(),
+        stream,
+        FfmpegRecorder::new(log.clone(), &config.recording),
+    );
+
+    match command {
+        SessionsCommand::List => {
+            let sessions = proto.list_sessions().await?;
+            write_output(output_path, &sessions)?;
+        }
+        SessionsCommand::Show { session_id } => match proto.query_session(session_id).await? {
+            Some(session) => write_output(output_path, &session)?,
+            None => return Err(ErrorMessage("no such session").into()),
+        },
+        SessionsCommand::Delete { session_id } => {
+            proto.delete_session(session_id).await?;
+            info!(log, "deleted session"; "session_id" => session_id);
+        }
+        SessionsCommand::Purge => {
+            let purged = proto.purge_sessions().await?;
+            info!(log, "purged sessions"; "count" => purged.len());
+            write_output(output_path, &purged)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Request the status of the runner, write it, and check that the runner is
+/// healthy.
+#[tokio::main]
+async fn runner_status(
+    log: Logger,
+    config: &Config,
+    options: &StatusOptions,
+    output_path: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect(&config.host).await?;
+    info!(log, "Connected"; "peer" => &config.host);
+
+    let mut proto = RecorderProto::new(
+        log.clone(),
+        stream,
+        FfmpegRecorder::new(log.clone(), &config.recording),
+    );
+
+    let status = proto.status().await?;
+    write_output(output_path, &status)?;
+
+    let min_free_disk = options.min_free_disk.saturating_mul(1024 * 1024);
+
+    match status.free_disk {
+        Some(free_disk) if free_disk < min_free_disk => {
+            error!(
+                log,
+                "runner is low on disk space";
+                "free_disk" => free_disk,
+                "min_free_disk" => min_free_disk,
+            );
+            return Err(ErrorMessage("runner is unhealthy").into());
+        }
+        Some(..) => {}
+        None => warn!(log, "runner did not report its free disk space"),
+    }
+
+    info!(
+        log,
+        "runner is healthy";
+        "version" => %status.version,
+        "uptime_secs" => status.uptime_secs,
+    );
+
+    Ok(())
+}
+
+/// Load previously saved replicates from `results_path` if provided, or
+/// otherwise record the build with the given task ID.
+///
+/// The replicates are re-aggregated with the current configuration.
+fn record_or_load(
+    log: Logger,
+    config: &Config,
+    options: &CompareOptions,
+    task_id: Option<&str>,
+    results_path: Option<&Path>,
+) -> Result<ReplicateSet, Box<dyn Error>> {
+    let replicates = if let Some(results_path) = results_path {
+        info!(log, "loading saved results"; "path" => results_path.display());
+
+        let f = BufReader::new(File::open(results_path)?);
+        let replicate_set: ReplicateSet = serde_json::from_reader(f)?;
+        replicate_set.replicates
+    } else {
+        let task_id = task_id.expect("either a task ID or saved results are required");
+
+        record(log, config, &RecordOptions::with_task_id(task_id, options))?
+    };
+
+    Ok(ReplicateSet::new(
+        replicates,
+        &config.perfherder.visual_complete_thresholds,
+    ))
+}
+
 #[tokio::main]
 async fn record(
     log: Logger,
-    config: Config,
+    config: &Config,
     options: &RecordOptions,
-) -> Result<VisualMetrics, Box<dyn Error>> {
+) -> Result<Vec<VisualMetrics>, Box<dyn Error>> {
     let tempdir = TempDir::new().expect("could not create temp directory");
 
+    if options.runs == 0 {
+        return Err(ErrorMessage("at least one run is required").into());
+    }
+
     if let Some(ref profile_path) = &options.profile_path {
         let meta = tokio::fs::metadata(profile_path).await?;
 
@@ -148,7 +599,17 @@ async fn record(
         }
     }
 
-    let session_id = {
+    if let Some(ref build_path) = &options.build_path {
+        let meta = tokio::fs::metadata(build_path).await?;
+
+        if !meta.is_file() {
+            return Err(ErrorMessage("build is not a file").into());
+        }
+    }
+
+    let session_id = if options.queue {
+        queue_job(log.clone(), config, options).await?
+    } else {
         let stream = TcpStream::connect(&config.host).await?;
         info!(log, "Connected"; "peer" => &config.host);
 
@@ -160,36 +621,142 @@ async fn record(
             FfmpegRecorder::new(log.clone(), &config.recording),
         );
 
-        proto
+        let mut result = proto
             .new_session(
-                &options.task_id,
+                &options.build(),
+                options.runs,
                 options.profile_path.as_deref(),
                 &options.prefs,
             )
-            .await?
+            .await;
+
+        // An interrupted profile transfer leaves the session on the runner so
+        // that we can pick up where we left off.
+        let mut attempts = 1;
+        loop {
+            match result {
+                Err(RecorderProtoError::ProfileTransferInterrupted { session_id, source })
+                    if attempts < PROFILE_TRANSFER_ATTEMPTS =>
+                {
+                    attempts += 1;
+
+                    warn!(
+                        log,
+                        "Profile transfer was interrupted. Resuming...";
+                        "session_id" => &session_id,
+                        "error" => %source,
+                    );
+
+                    let stream = TcpStream::connect(&config.host).await?;
+                    let mut proto = RecorderProto::new(
+                        log.clone(),
+                        stream,
+                        FfmpegRecorder::new(log.clone(), &config.recording),
+                    );
+
+                    result = proto
+                        .resume_profile(&session_id, options.profile_path.as_deref().unwrap())
+                        .await
+                        .map(|()| session_id);
+                }
+                result => break result?,
+            }
+        }
+    };
+
+    let idle = if options.skip_idle {
+        Idle::Skip
+    } else {
+        Idle::Wait
     };
 
-    info!(log, "Disconnected from runner. Waiting to reconnect...");
+    let recording_dir = if options.keep_video {
+        current_dir()?
+    } else {
+        tempdir.path().into()
+    };
 
-    let recording_path = {
-        let reconnect = || {
-            info!(log, "Attempting re-connection to runner...");
-            TcpStream::connect(&config.host)
+    let mut metrics = Vec::with_capacity(options.runs as usize);
+
+    for run in 1..=options.runs {
+        info!(log, "Disconnected from runner. Waiting to reconnect..."; "run" => run);
+
+        let (recording_path, idle_readings) = {
+            let reconnect = || {
+                info!(log, "Attempting re-connection to runner...");
+                TcpStream::connect(&config.host)
+            };
+
+            // This will attempt to reconnect for 0:30 + 1:00 + 2:00 + 4:00 = 7:30.
+            let stream = delayed_exponential_retry(reconnect, Duration::from_secs(30), 4)
+                .await
+                .map_err(|e| {
+                    error!(
+                        log,
+                        "Could not connect to runner";
+                        "last_error" => %e.source().unwrap()
+                    );
+                    e
+                })?;
+
+            info!(log, "Re-connected"; "peer" => &config.host);
+
+            let mut proto = RecorderProto::new(
+                log.clone(),
+                stream,
+                FfmpegRecorder::new(log.clone(), &config.recording),
+            );
+
+            // Each run needs its own directory so that recordings do not
+            // overwrite one another.
+            let run_dir = if options.runs > 1 {
+                let run_dir = recording_dir.join(format!("run-{}", run));
+                tokio::fs::create_dir_all(&run_dir).await?;
+                run_dir
+            } else {
+                recording_dir.clone()
+            };
+
+            proto.resume_session(&session_id, idle, &run_dir).await?
         };
 
-        // This will attempt to reconnect for 0:30 + 1:00 + 2:00 + 4:00 = 7:30.
-        let stream = delayed_exponential_retry(reconnect, Duration::from_secs(30), 4)
-            .await
-            .map_err(|e| {
-                error!(
-                    log,
-                    "Could not connect to runner";
-                    "last_error" => %e.source().unwrap()
-                );
-                e
-            })?;
+        info!(log, "disconnected from FxRunner");
+
+        if options.keep_video {
+            info!(log, "video written to disk"; "path" => recording_path.display());
+        }
 
-        info!(log, "Re-connected"; "peer" => &config.host);
+        let mut run_metrics = analyze_video(
+            log.clone(),
+            &AnalyzeOptions {
+                video_path: recording_path,
+            },
+        )?;
+        run_metrics.idle_readings = idle_readings;
+        metrics.push(run_metrics);
+    }
+
+    Ok(metrics)
+}
+
+/// Submit a job to the runner and wait for the runner to start it.
+///
+/// Returns the ID of the session that the job is running in.
+async fn queue_job(
+    log: Logger,
+    config: &Config,
+    options: &RecordOptions,
+) -> Result<String, Box<dyn Error>> {
+    let build = match options.build() {
+        Build::Source(source) => source,
+        Build::Sideload(..) => {
+            return Err(ErrorMessage("sideloaded builds cannot be queued").into());
+        }
+    };
+
+    let job_id = {
+        let stream = TcpStream::connect(&config.host).await?;
+        info!(log, "Connected"; "peer" => &config.host);
 
         let mut proto = RecorderProto::new(
             log.clone(),
@@ -197,58 +764,103 @@ async fn record(
             FfmpegRecorder::new(log.clone(), &config.recording),
         );
 
-        let idle = if options.skip_idle {
-            Idle::Skip
-        } else {
-            Idle::Wait
-        };
-
-        let recording_dir = if options.keep_video {
-            current_dir()?
-        } else {
-            tempdir.path().into()
-        };
-
         proto
-            .resume_session(&session_id, idle, &recording_dir)
+            .submit_job(
+                &build,
+                options.runs,
+                options.profile_path.as_deref(),
+                &options.prefs,
+            )
             .await?
     };
 
-    info!(log, "disconnected from FxRunner");
+    let deadline = Instant::now() + Duration::from_secs(options.queue_timeout_mins * 60);
+
+    loop {
+        if Instant::now() >= deadline {
+            error!(
+                log,
+                "Timed out waiting for job to start";
+                "job_id" => &job_id,
+                "timeout_mins" => options.queue_timeout_mins,
+            );
+            break Err(ErrorMessage("timed out waiting for job to start").into());
+        }
 
-    if options.keep_video {
-        info!(log, "video written to disk"; "path" => recording_path.display());
-    }
+        delay_for(JOB_POLL_INTERVAL).await;
 
-    analyze_video(
-        log,
-        config,
-        &AnalyzeOptions {
-            video_path: recording_path,
-        },
-    )
+        // The runner may be restarting, so failing to reach it is expected.
+        let summary = match TcpStream::connect(&config.host).await {
+            Ok(stream) => {
+                let mut proto = RecorderProto::new(
+                    log.clone(),
+                    stream,
+                    FfmpegRecorder::new(log.clone(), &config.recording),
+                );
+
+                match proto.query_session(&job_id).await {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        warn!(log, "Could not query job"; "job_id" => &job_id, "error" => %e);
+                        continue;
+                    }
+                }
+            }
+            Err(e) => {
+                warn!(log, "Could not connect to runner"; "error" => %e);
+                continue;
+            }
+        };
+
+        let summary = match summary {
+            Some(summary) => summary,
+            None => return Err(ErrorMessage("job no longer exists on the runner").into()),
+        };
+
+        match summary.state {
+            SessionState::RestartPending | SessionState::Running => {
+                info!(log, "Job started"; "job_id" => &job_id, "state" => ?summary.state);
+                break Ok(job_id);
+            }
+            // The job may have started and finished while the runner was
+            // restarting and could not be reached, in which case there is
+            // nothing left to record.
+            SessionState::Finished => {
+                error!(log, "Job completed before it could be recorded"; "job_id" => &job_id);
+                break Err(ErrorMessage("job completed before it could be recorded").into());
+            }
+            SessionState::Failed => {
+                error!(
+                    log,
+                    "Runner could not start job";
+                    "job_id" => &job_id,
+                    "error" => summary.error.as_deref().unwrap_or("unknown error"),
+                );
+                break Err(ErrorMessage("job failed").into());
+            }
+            SessionState::Queued => {
+                info!(log, "Waiting for job to start"; "job_id" => &job_id);
+            }
+            // The runner starts a job without handling other requests, so the
+            // job was interrupted if it is seen in any other state.
+            state @ SessionState::Created
+            | state @ SessionState::BuildExtracted
+            | state @ SessionState::ProfilePending
+            | state @ SessionState::ProfileReady
+            | state @ SessionState::PrefsWritten => {
+                error!(log, "Job was interrupted while starting"; "job_id" => &job_id, "state" => ?state);
+                break Err(ErrorMessage("job was interrupted while starting").into());
+            }
+        }
+    }
 }
 
-fn analyze_video(
-    log: Logger,
-    config: Config,
-    options: &AnalyzeOptions,
-) -> Result<VisualMetrics, Box<dyn Error>> {
+fn analyze_video(log: Logger, options: &AnalyzeOptions) -> Result<VisualMetrics, Box<dyn Error>> {
     info!(log, "analyzing video"; "video" => &options.video_path.display());
 
     let working_dir = TempDir::new()?;
 
     let cropped_video_path = crop_video(log.clone(), &options.video_path, working_dir.path())?;
 
-    // run visual metrics
-    let metrics = compute_visual_metrics(
-        log.clone(),
-        &config.visual_metrics_path,
-        &cropped_video_path,
-        working_dir.path(),
-    )?;
-
-    info!(log, "computed visual metrics"; "metrics" => ?metrics);
-
-    Ok(metrics)
+    compute_visual_metrics(log, &cropped_video_path, working_dir.path()).map_err(Into::into)
 }
@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Aggregation of visual metrics across replicate recordings.
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::VisualMetrics;
+use crate::stats::{filter_outliers, Summary};
+
+/// The visual metrics of a set of replicate recordings and their aggregates.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplicateSet {
+    /// The metrics of each replicate.
+    pub replicates: Vec<VisualMetrics>,
+
+    /// The aggregated metrics.
+    #[serde(default)]
+    pub metrics: Vec<AggregatedMetric>,
+}
+
+impl ReplicateSet {
+    /// Aggregate the given replicates.
+    pub fn new(replicates: Vec<VisualMetrics>, visual_complete_thresholds: &[u8]) -> Self {
+        let metrics = aggregate(&replicates, visual_complete_thresholds);
+
+        ReplicateSet {
+            replicates,
+            metrics,
+        }
+    }
+}
+
+/// A single metric aggregated across replicates.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AggregatedMetric {
+    /// The name of the metric.
+    pub name: String,
+
+    /// The unit of the metric.
+    pub unit: String,
+
+    /// The value of the metric in each replicate.
+    pub replicates: Vec<u32>,
+
+    /// The summary of all replicates.
+    pub summary: Summary,
+
+    /// The summary of the replicates with outliers removed.
+    pub filtered: Summary,
+}
+
+impl AggregatedMetric {
+    /// The representative value of the metric.
+    ///
+    /// This is the median of the replicates with outliers removed.
+    pub fn value(&self) -> f64 {
+        self.filtered.median
+    }
+}
+
+/// Return the named metrics of a single recording, along with their units.
+///
+/// Visual completeness metrics are included for each of the given thresholds
+/// that was reached.
+pub fn metric_values(
+    metrics: &VisualMetrics,
+    visual_complete_thresholds: &[u8],
+) -> Vec<(String, &'static str, u32)> {
+    let mut values = vec![
+        ("SpeedIndex".into(), "ms * %", metrics.speed_index),
+        (
+            "PerceptualSpeedIndex".into(),
+            "ms * %",
+            metrics.perceptual_speed_index,
+        ),
+        (
+            "ContentfulSpeedIndex".into(),
+            "ms * %",
+            metrics.contentful_speed_index,
+        ),
+        (
+            "FirstVisualChange".into(),
+            "ms",
+            metrics.first_visual_change,
+        ),
+        ("LastVisualChange".into(), "ms", metrics.last_visual_change),
+    ];
+
+    for &threshold in visual_complete_thresholds {
+        if let Some(value) = metrics.visual_complete(threshold) {
+            values.push((format!("VisualComplete{}", threshold), "ms", value));
+        }
+    }
+
+    values
+}
+
+/// Aggregate each metric across replicates.
+///
+/// Metrics are returned in the order that [`metric_values`](fn.metric_values.html)
+/// produces them. A metric missing from some replicates is aggregated over the
+/// replicates that have it.
+pub fn aggregate(
+    replicates: &[VisualMetrics],
+    visual_complete_thresholds: &[u8],
+) -> Vec<AggregatedMetric> {
+    let mut metrics: Vec<(String, &'static str, Vec<u32>)> = vec![];
+
+    for replicate in replicates {
+        for (name, unit, value) in metric_values(replicate, visual_complete_thresholds) {
+            match metrics.iter_mut().find(|(n, _, _)| *n == name) {
+                Some((_, _, values)) => values.push(value),
+                None => metrics.push((name, unit, vec![value])),
+            }
+        }
+    }
+
+    metrics
+        .into_iter()
+        .map(|(name, unit, replicates)| {
+            let values: Vec<f64> = replicates.iter().map(|&v| v as f64).collect();
+
+            // There is always at least one value, and the filtered values
+            // always include the median.
+            let summary = Summary::new(&values).unwrap();
+            let filtered = Summary::new(&filter_outliers(&values)).unwrap();
+
+            AggregatedMetric {
+                name,
+                unit: unit.into(),
+                replicates,
+                summary,
+                filtered,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        assert!(aggregate(&[], &[85]).is_empty());
+
+        let replicates = vec![
+            VisualMetrics::for_test(1000, 300),
+            VisualMetrics::for_test(1100, 300),
+            VisualMetrics::for_test(1050, 400),
+            VisualMetrics::for_test(990, 300),
+            VisualMetrics::for_test(5000, 300),
+        ];
+
+        let aggregated = aggregate(&replicates, &[85]);
+        let names: Vec<&str> = aggregated.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "SpeedIndex",
+                "PerceptualSpeedIndex",
+                "ContentfulSpeedIndex",
+                "FirstVisualChange",
+                "LastVisualChange",
+                "VisualComplete85",
+            ]
+        );
+
+        let si = &aggregated[0];
+        assert_eq!(si.unit, "ms * %");
+        assert_eq!(si.replicates, vec![1000, 1100, 1050, 990, 5000]);
+        assert_eq!(si.summary.count, 5);
+        assert_eq!(si.summary.max, 5000.0);
+        assert_eq!(si.filtered.count, 4);
+        assert_eq!(si.filtered.max, 1100.0);
+        assert_eq!(si.value(), 1025.0);
+
+        let vc85 = &aggregated[5];
+        assert_eq!(vc85.replicates, vec![300, 300, 400, 300, 300]);
+        assert_eq!(vc85.value(), 300.0);
+    }
+
+    #[test]
+    fn test_replicate_set_serde() {
+        let set = ReplicateSet::new(vec![VisualMetrics::for_test(1000, 300)], &[85]);
+        let json = serde_json::to_string(&set).unwrap();
+
+        let loaded: ReplicateSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.replicates.len(), 1);
+        assert_eq!(loaded.metrics.len(), set.metrics.len());
+
+        let loaded: ReplicateSet = serde_json::from_str(r#"{"replicates": []}"#).unwrap();
+        assert!(loaded.metrics.is_empty());
+    }
+}
@@ -2,21 +2,25 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{create_dir_all, read_dir, File};
 use std::io::{self, BufReader};
+use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::str::FromStr;
 
-use image::{GenericImageView, ImageError, Rgb};
+use image::{DynamicImage, GenericImageView, ImageError, Rgb, RgbImage};
 use itertools::Itertools;
+use libfxrecord::net::IdleReadings;
 use libfxrecord::ORANGE;
-use serde::{Deserialize, Serialize};
-use slog::{error, info, warn};
+use serde::{Deserialize, Serialize, Serializer};
+use slog::{info, warn};
 use thiserror::Error;
 
 use crate::ffmpeg::{run_ffmpeg, FfmpegError};
+use crate::visualmetrics::{compute_frame_metrics, Frame};
 
 #[derive(Debug, Error)]
 #[error("Could not crop video: {}", .0)]
@@ -103,8 +107,8 @@ pub fn extract_frames(
     Ok(frames_dir)
 }
 
-/// Information about a frame being processed in
-/// [`find_first_orange_frame`][function.find_first_orange_frame.html].
+/// Information about a frame extracted by
+/// [`extract_frames`][function.extract_frames.html].
 #[derive(Debug)]
 struct FrameInfo {
     /// The path to the frame.
@@ -124,7 +128,7 @@ fn squared_distance(a: &Rgb<u8>, b: &Rgb<u8>) -> i64 {
 }
 
 #[derive(Debug, Error)]
-pub enum OrangeError {
+pub enum FrameError {
     #[error("could not read frame directory: {}", .0)]
     ReadDir(#[source] io::Error),
 
@@ -133,27 +137,14 @@ pub enum OrangeError {
 
     #[error("could not load image `{}': {}'", .1.display(), .0)]
     Load(#[source] ImageError, PathBuf),
-
-    #[error("no orange frame detected")]
-    MissingOrange,
 }
 
-/// Return the frame number of the first orange frame of the video.
-fn find_first_orange_frame(log: slog::Logger, frames_dir: &Path) -> Result<u32, OrangeError> {
-    // The x and y dimensions of the region to sample.
-    const SAMPLE_SIZE: u32 = 50;
-
-    // The maximum squared Euclidean distance we will accept between a colour and ORANGE.
-    //
-    // Non-orange frames are in the range of 10 000.
-    const THRESHOLD: i64 = 500;
-
-    // This is the orange that Splash generates and that visuametrics.py expects.
-    let orange = image::Rgb(ORANGE);
-
+/// Return information about the frames in the frame directory, sorted by frame
+/// number.
+fn read_frames(log: slog::Logger, frames_dir: &Path) -> Result<Vec<FrameInfo>, FrameError> {
     let mut frames = vec![];
-    for entry in read_dir(frames_dir).map_err(OrangeError::ReadDir)? {
-        let entry = entry.map_err(OrangeError::ReadDir)?;
+    for entry in read_dir(frames_dir).map_err(FrameError::ReadDir)? {
+        let entry = entry.map_err(FrameError::ReadDir)?;
         let path = entry.path();
         let path_str = String::from(path.file_name().unwrap().to_str().unwrap());
 
@@ -186,25 +177,38 @@ fn find_first_orange_frame(log: slog::Logger, frames_dir: &Path) -> Result<u32,
 
     frames.sort_by(|a, b| a.frame_num.cmp(&b.frame_num));
 
-    for info in &frames {
-        let f = BufReader::new(
-            File::open(&info.path)
-                .map_err(|source| OrangeError::Open(source, info.path.clone()))?,
-        );
-        let image = image::load(f, image::ImageFormat::Png)
-            .map_err(|source| OrangeError::Load(source, info.path.clone()))?
-            .into_rgb();
+    Ok(frames)
+}
 
-        let x = (image.width() - SAMPLE_SIZE) / 2;
-        let y = (image.height() - SAMPLE_SIZE) / 2;
+/// Load a frame as an RGB image.
+fn load_frame(info: &FrameInfo) -> Result<RgbImage, FrameError> {
+    let f = BufReader::new(
+        File::open(&info.path).map_err(|source| FrameError::Open(source, info.path.clone()))?,
+    );
 
-        let avg = average_image(&image.view(x, y, SAMPLE_SIZE, SAMPLE_SIZE));
-        if squared_distance(&avg, &orange) < THRESHOLD {
-            return Ok(info.frame_num);
-        }
-    }
+    image::load(f, image::ImageFormat::Png)
+        .map(DynamicImage::into_rgb)
+        .map_err(|source| FrameError::Load(source, info.path.clone()))
+}
+
+/// Return whether or not the frame is orange.
+fn is_orange(image: &RgbImage) -> bool {
+    // The x and y dimensions of the region to sample.
+    const SAMPLE_SIZE: u32 = 50;
+
+    // The maximum squared Euclidean distance we will accept between a colour and ORANGE.
+    //
+    // Non-orange frames are in the range of 10 000.
+    const THRESHOLD: i64 = 500;
+
+    // This is the orange that Splash generates.
+    let orange = image::Rgb(ORANGE);
 
-    Err(OrangeError::MissingOrange)
+    let x = (image.width() - SAMPLE_SIZE) / 2;
+    let y = (image.height() - SAMPLE_SIZE) / 2;
+
+    let avg = average_image(&image.view(x, y, SAMPLE_SIZE, SAMPLE_SIZE));
+    squared_distance(&avg, &orange) < THRESHOLD
 }
 
 /// Compute the average colour of an image.
@@ -242,95 +246,127 @@ pub struct VisualMetrics {
     #[serde(rename = "SpeedIndex")]
     pub speed_index: u32,
 
+    #[serde(rename = "PerceptualSpeedIndex")]
+    pub perceptual_speed_index: u32,
+
+    #[serde(rename = "ContentfulSpeedIndex")]
+    pub contentful_speed_index: u32,
+
     #[serde(rename = "VisualProgress")]
-    pub visual_progress: String,
+    pub visual_progress: VisualProgress,
+
+    /// The idle readings the runner achieved before the recording, if it
+    /// waited to become idle.
+    #[serde(
+        rename = "IdleReadings",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idle_readings: Option<IdleReadings>,
 }
 
 #[derive(Debug, Error)]
 pub enum VisualMetricsError {
-    #[error("Error executing visualmetrics.py: {}", .0)]
-    Exec(#[source] std::io::Error),
-
-    #[error("Could not wait for visualmetrics.py to exit: {}", .0)]
-    Wait(#[source] std::io::Error),
-
-    #[error("visualmetrics.py exited with non-zero status code: {}", .0)]
-    ExitCode(i32),
-
     #[error(transparent)]
-    Orange(#[from] OrangeError),
+    Frame(#[from] FrameError),
 
-    #[error("Could not parse output of visualmetrics.py as JSON: {}", .0)]
-    Parse(#[from] serde_json::Error),
+    #[error("no orange frame detected")]
+    MissingOrange,
 
-    #[error("Could not parse visual progress: {}", .0)]
-    VisualProgress(#[from] VisualProgressError),
+    #[error("no frames after the orange frames")]
+    NoFrames,
 
     #[error("Could not extract frames from video: {}", .0)]
     ExtractFrames(#[from] ExtractFramesError),
 }
 
-/// Compute visual metrics with visualmetrics.py
+/// Compute visual metrics from the frames of the video.
 pub fn compute_visual_metrics(
     log: slog::Logger,
-    vismet_path: &Path,
     video: &Path,
     target_directory: &Path,
 ) -> Result<VisualMetrics, VisualMetricsError> {
     // The time base is the reciprocal of the frame rate (units of `s`);
     const TIME_BASE: f64 = 1.0 / 60.0;
 
-    info!(log, "running visual metrics...");
-
-    let output = Command::new("python")
-        .args(&[
-            vismet_path.as_os_str(),
-            OsStr::new("-vvv"),
-            OsStr::new("--logformat"),
-            OsStr::new("%(levelname)s  %(message)s"),
-            OsStr::new("--video"),
-            video.as_os_str(),
-            OsStr::new("--orange"),
-            OsStr::new("--json"),
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(VisualMetricsError::Exec)?
-        .wait_with_output()
-        .map_err(VisualMetricsError::Wait)?;
-
-    if !output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-
-        error!(
-            log,
-            "visualmetrics.py encountered an error";
-            "status" => output.status.code().unwrap(),
-            "stdout" => %stdout,
-            "stderr" => %stderr,
-        );
+    let frames_dir = extract_frames(log.clone(), video, target_directory)?;
+
+    info!(log, "computing visual metrics...");
+
+    let mut orange_timestamp = None;
+    let mut frames = vec![];
+    // The number of frames at the end of `frames` that are orange.
+    let mut trailing_orange = 0;
+
+    for info in read_frames(log.clone(), &frames_dir)? {
+        let image = load_frame(&info)?;
+        let timestamp = ((info.frame_num as f64) * TIME_BASE * 1000.0) as u32;
+        let orange = is_orange(&image);
 
-        return Err(VisualMetricsError::ExitCode(output.status.code().unwrap()));
+        if orange_timestamp.is_none() {
+            if orange {
+                orange_timestamp = Some(timestamp);
+            }
+            continue;
+        }
+
+        if frames.is_empty() && orange {
+            // Skip the remaining orange frames at the start.
+            continue;
+        }
+
+        if orange {
+            trailing_orange += 1;
+        } else {
+            trailing_orange = 0;
+        }
+
+        frames.push(Frame::new(timestamp, &image));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    info!(
-        log,
-        "ran visualmetrics.py";
-        "log" => %String::from_utf8_lossy(&output.stderr),
-        "output" => %stdout,
-    );
+    let orange_timestamp = orange_timestamp.ok_or(VisualMetricsError::MissingOrange)?;
+    frames.truncate(frames.len() - trailing_orange);
 
-    let metrics: VisualMetrics = serde_json::from_str(&stdout)?;
-    let frames_dir = extract_frames(log.clone(), video, target_directory)?;
-    let orange_frame_num = find_first_orange_frame(log.clone(), &frames_dir)?;
+    let video_recording_start = frames
+        .first()
+        .ok_or(VisualMetricsError::NoFrames)?
+        .timestamp;
+    let frame_metrics = compute_frame_metrics(&frames).ok_or(VisualMetricsError::NoFrames)?;
+
+    let metrics = VisualMetrics {
+        video_recording_start,
+        first_visual_change: frame_metrics.first_visual_change,
+        last_visual_change: frame_metrics.last_visual_change,
+        speed_index: frame_metrics.speed_index,
+        perceptual_speed_index: frame_metrics.perceptual_speed_index,
+        contentful_speed_index: frame_metrics.contentful_speed_index,
+        visual_progress: VisualProgress(frame_metrics.visual_progress),
+        idle_readings: None,
+    };
+
+    info!(log, "computed visual metrics"; "metrics" => ?metrics);
 
     // We paint an orange frame *after* we have start firefox, so we want to
     // find the timestamp directly before this frame was painted.
-    let start_timestamp = ((orange_frame_num as f64) * TIME_BASE * 1000.0) as u32;
-    metrics.normalize(start_timestamp).map_err(Into::into)
+    Ok(metrics.normalize(orange_timestamp))
+}
+
+/// The visual progress of a recording, as a series of `(time in ms, percent
+/// complete)` pairs ordered by time.
+///
+/// This serializes as an array of pairs. It can be deserialized from either an
+/// array of pairs or the raw string format produced by visualmetrics.py, i.e.,
+/// `"0=0, 1234=45, 2345=100"`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "VisualProgressRepr")]
+pub struct VisualProgress(pub Vec<(u32, u8)>);
+
+/// The serialized representations of [`VisualProgress`](struct.VisualProgress.html).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VisualProgressRepr {
+    Raw(String),
+    Array(Vec<(u32, u8)>),
 }
 
 #[derive(Clone, Debug, Error)]
@@ -339,15 +375,89 @@ pub enum VisualProgressError {
     MissingEquals,
 
     #[error("Could not parse timestamp in VisualProgress: {}", .0)]
-    ParseKey(#[from] std::num::ParseIntError),
+    ParseKey(#[source] ParseIntError),
+
+    #[error("Could not parse progress in VisualProgress: {}", .0)]
+    ParseValue(#[source] ParseIntError),
+}
+
+impl VisualProgress {
+    /// Return the time (in ms) at which the progress first reached at least
+    /// `percent`.
+    pub fn time_to(&self, percent: u8) -> Option<u32> {
+        self.0
+            .iter()
+            .find(|(_, progress)| *progress >= percent)
+            .map(|(time, _)| *time)
+    }
+}
+
+impl FromStr for VisualProgress {
+    type Err = VisualProgressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Ok(VisualProgress::default());
+        }
+
+        s.split(',')
+            .map(|kvp| {
+                let idx = kvp.find('=').ok_or(VisualProgressError::MissingEquals)?;
+                let (key, value) = kvp.split_at(idx);
+
+                let time = key.trim().parse().map_err(VisualProgressError::ParseKey)?;
+                let progress = value[1..]
+                    .trim()
+                    .parse()
+                    .map_err(VisualProgressError::ParseValue)?;
+
+                Ok((time, progress))
+            })
+            .collect::<Result<_, _>>()
+            .map(VisualProgress)
+    }
+}
+
+impl fmt::Display for VisualProgress {
+    /// Format the visual progress in the raw format produced by
+    /// visualmetrics.py.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw = self
+            .0
+            .iter()
+            .map(|(time, progress)| format!("{}={}", time, progress))
+            .join(", ");
+
+        f.write_str(&raw)
+    }
+}
+
+impl TryFrom<VisualProgressRepr> for VisualProgress {
+    type Error = VisualProgressError;
+
+    fn try_from(repr: VisualProgressRepr) -> Result<Self, Self::Error> {
+        match repr {
+            VisualProgressRepr::Raw(s) => s.parse(),
+            VisualProgressRepr::Array(v) => Ok(VisualProgress(v)),
+        }
+    }
+}
+
+impl Serialize for VisualProgress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.0)
+    }
 }
 
 impl VisualMetrics {
+    /// Return the time (in ms) at which the recording was at least `percent`
+    /// visually complete.
+    pub fn visual_complete(&self, percent: u8) -> Option<u32> {
+        self.visual_progress.time_to(percent)
+    }
+
     /// Normalize the metrics so that the video start is at zero.
-    pub fn normalize(
-        &self,
-        orange_frame_timestamp: u32,
-    ) -> Result<VisualMetrics, VisualProgressError> {
+    pub fn normalize(&self, orange_frame_timestamp: u32) -> VisualMetrics {
         assert!(
             self.video_recording_start != 0,
             "VisualMetrics should have orange frames"
@@ -364,7 +474,7 @@ impl VisualMetrics {
         //   |   \ orange_frame_timestamp
         //   \ video start (0)
         //
-        // visualmetrics.py will ignore all the orange frames at the start.
+        // The visual metrics computation ignores all the orange frames at the start.
         // However, we want to include them in the computations since we only
         // paint them once we have started Firefox.
         let orange_duration = self.video_recording_start - orange_frame_timestamp;
@@ -373,7 +483,7 @@ impl VisualMetrics {
         // `orange_frame_timestamp`.
         let video_recording_start = orange_frame_timestamp;
 
-        //  visualmetrics.py detects the first white frame as as the
+        //  The visual metrics computation detects the first white frame as as the
         //  `video_recording_start`, but that is actually the first frame we have
         //  painted (before that it was orange), so really that is the
         //  `first_visual_change`.
@@ -387,33 +497,146 @@ impl VisualMetrics {
         // * video_recording_start;
         let speed_index = self.speed_index + 100 * orange_duration;
 
+        // The perceptual and contentful speed indices are integrals of the
+        // same form, so they are offset in the same way.
+        let perceptual_speed_index = self.perceptual_speed_index + 100 * orange_duration;
+        let contentful_speed_index = self.contentful_speed_index + 100 * orange_duration;
+
         // Each entry of the visual progress (except 0) needs to be adjusted to
         // include the orange duration.
-        let visual_progress = self
-            .visual_progress
-            .split(", ")
-            .enumerate()
-            .map(|(i, kvp)| -> Result<Cow<str>, VisualProgressError> {
-                if i == 0 {
-                    Ok(Cow::Borrowed(kvp))
-                } else {
-                    let idx = kvp.find('=').ok_or(VisualProgressError::MissingEquals)?;
-
-                    let (key, value) = kvp.split_at(idx);
-                    let key: u32 = key.parse()?;
-                    // value will include the =.
-                    Ok(Cow::Owned(format!("{}{}", key + orange_duration, value)))
-                }
-            })
-            .intersperse(Ok(Cow::Borrowed(", ")))
-            .collect::<Result<String, _>>()?;
+        let visual_progress = VisualProgress(
+            self.visual_progress
+                .0
+                .iter()
+                .enumerate()
+                .map(|(i, &(time, progress))| {
+                    if i == 0 {
+                        (time, progress)
+                    } else {
+                        (time + orange_duration, progress)
+                    }
+                })
+                .collect(),
+        );
 
-        Ok(VisualMetrics {
+        VisualMetrics {
             video_recording_start,
             first_visual_change,
             last_visual_change,
             speed_index,
+            perceptual_speed_index,
+            contentful_speed_index,
             visual_progress,
-        })
+            idle_readings: self.idle_readings.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl VisualMetrics {
+    /// Create metrics for tests with the given speed index and time to 85%
+    /// visual completeness.
+    pub(crate) fn for_test(speed_index: u32, visual_complete_85: u32) -> Self {
+        VisualMetrics {
+            video_recording_start: 0,
+            first_visual_change: 100,
+            last_visual_change: 500,
+            speed_index,
+            perceptual_speed_index: 246,
+            contentful_speed_index: 100,
+            visual_progress: VisualProgress(vec![
+                (0, 0),
+                (100, 50),
+                (visual_complete_85, 90),
+                (500, 100),
+            ]),
+            idle_readings: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_visual_progress_parse() {
+        assert_eq!(
+            "0=0, 1234=45, 2345=100".parse::<VisualProgress>().unwrap(),
+            VisualProgress(vec![(0, 0), (1234, 45), (2345, 100)])
+        );
+        assert_eq!(
+            "".parse::<VisualProgress>().unwrap(),
+            VisualProgress::default()
+        );
+
+        assert!(matches!(
+            "0=0, 1234".parse::<VisualProgress>(),
+            Err(VisualProgressError::MissingEquals)
+        ));
+        assert!(matches!(
+            "x=0".parse::<VisualProgress>(),
+            Err(VisualProgressError::ParseKey(..))
+        ));
+        assert!(matches!(
+            "0=101%".parse::<VisualProgress>(),
+            Err(VisualProgressError::ParseValue(..))
+        ));
+    }
+
+    #[test]
+    fn test_visual_progress_serde() {
+        let progress = VisualProgress(vec![(0, 0), (1234, 45), (2345, 100)]);
+
+        assert_eq!(progress.to_string(), "0=0, 1234=45, 2345=100");
+        assert_eq!(
+            serde_json::to_string(&progress).unwrap(),
+            "[[0,0],[1234,45],[2345,100]]"
+        );
+
+        assert_eq!(
+            serde_json::from_str::<VisualProgress>("[[0,0],[1234,45],[2345,100]]").unwrap(),
+            progress
+        );
+        assert_eq!(
+            serde_json::from_str::<VisualProgress>(r#""0=0, 1234=45, 2345=100""#).unwrap(),
+            progress
+        );
+        assert!(serde_json::from_str::<VisualProgress>(r#""0=0, 1234""#).is_err());
+    }
+
+    #[test]
+    fn test_visual_progress_time_to() {
+        let progress = VisualProgress(vec![(0, 0), (100, 50), (200, 90), (500, 100)]);
+
+        assert_eq!(progress.time_to(0), Some(0));
+        assert_eq!(progress.time_to(50), Some(100));
+        assert_eq!(progress.time_to(85), Some(200));
+        assert_eq!(progress.time_to(95), Some(500));
+        assert_eq!(progress.time_to(100), Some(500));
+        assert_eq!(VisualProgress::default().time_to(85), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let metrics = VisualMetrics {
+            video_recording_start: 1000,
+            ..VisualMetrics::for_test(225, 300)
+        };
+        let normalized = metrics.normalize(750);
+
+        assert_eq!(normalized.video_recording_start, 750);
+        assert_eq!(normalized.first_visual_change, 1000);
+        assert_eq!(normalized.last_visual_change, 750);
+        assert_eq!(normalized.speed_index, 225 + 100 * 250);
+        assert_eq!(normalized.perceptual_speed_index, 246 + 100 * 250);
+        assert_eq!(normalized.contentful_speed_index, 100 + 100 * 250);
+        assert_eq!(normalized.visual_complete(85), Some(550));
+        assert_eq!(normalized.visual_complete(95), Some(750));
+        assert_eq!(normalized.visual_complete(99), Some(750));
+        assert_eq!(
+            normalized.visual_progress,
+            VisualProgress(vec![(0, 0), (350, 50), (550, 90), (750, 100)])
+        );
     }
 }
@@ -0,0 +1,136 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Comparison of the visual metrics of two builds.
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::ReplicateSet;
+use crate::stats::{
+    mann_whitney_u, mean_difference_interval, welch_t_test, MannWhitney, Summary, TTest,
+};
+
+/// The comparison of a single metric between a base and a new build.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MetricComparison {
+    /// The name of the metric.
+    pub name: String,
+
+    /// The unit of the metric.
+    pub unit: String,
+
+    /// The summary of the base replicates.
+    pub base: Summary,
+
+    /// The summary of the new replicates.
+    pub new: Summary,
+
+    /// The difference between the means (`new - base`).
+    pub delta: f64,
+
+    /// The difference between the means as a percentage of the base mean.
+    ///
+    /// This is `None` if the base mean is zero.
+    pub percent_change: Option<f64>,
+
+    /// The result of Welch's t-test.
+    ///
+    /// This is `None` if either build has fewer than two replicates.
+    pub t_test: Option<TTest>,
+
+    /// The result of the Mann-Whitney U test.
+    pub mann_whitney: Option<MannWhitney>,
+
+    /// The confidence interval of the difference between the means.
+    ///
+    /// This is `None` if either build has fewer than two replicates.
+    pub confidence_interval: Option<(f64, f64)>,
+}
+
+/// Compare each metric present in both replicate sets.
+///
+/// Confidence intervals are computed at the given confidence level (e.g.,
+/// `0.95`).
+pub fn compare(base: &ReplicateSet, new: &ReplicateSet, confidence: f64) -> Vec<MetricComparison> {
+    base.metrics
+        .iter()
+        .filter_map(|base_metric| {
+            let new_metric = new.metrics.iter().find(|m| m.name == base_metric.name)?;
+
+            let base_values: Vec<f64> = base_metric.replicates.iter().map(|&v| v as f64).collect();
+            let new_values: Vec<f64> = new_metric.replicates.iter().map(|&v| v as f64).collect();
+
+            let delta = new_metric.summary.mean - base_metric.summary.mean;
+            let percent_change = if base_metric.summary.mean == 0.0 {
+                None
+            } else {
+                Some(delta / base_metric.summary.mean * 100.0)
+            };
+
+            Some(MetricComparison {
+                name: base_metric.name.clone(),
+                unit: base_metric.unit.clone(),
+                base: base_metric.summary.clone(),
+                new: new_metric.summary.clone(),
+                delta,
+                percent_change,
+                t_test: welch_t_test(&base_values, &new_values),
+                mann_whitney: mann_whitney_u(&base_values, &new_values),
+                confidence_interval: mean_difference_interval(
+                    &base_values,
+                    &new_values,
+                    confidence,
+                ),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::VisualMetrics;
+
+    fn replicate_set(speed_indices: &[u32], thresholds: &[u8]) -> ReplicateSet {
+        ReplicateSet::new(
+            speed_indices
+                .iter()
+                .map(|&si| VisualMetrics::for_test(si, 300))
+                .collect(),
+            thresholds,
+        )
+    }
+
+    #[test]
+    fn test_compare() {
+        let base = replicate_set(&[1000, 1010, 990, 1005, 995], &[85, 95]);
+        let new = replicate_set(&[1100, 1110, 1090, 1105, 1095], &[85]);
+
+        let comparisons = compare(&base, &new, 0.95);
+
+        // Metrics missing from either set are not compared.
+        assert!(comparisons.iter().all(|c| c.name != "VisualComplete95"));
+        assert!(comparisons.iter().any(|c| c.name == "VisualComplete85"));
+
+        let si = comparisons.iter().find(|c| c.name == "SpeedIndex").unwrap();
+        assert_eq!(si.delta, 100.0);
+        assert_eq!(si.percent_change, Some(10.0));
+        assert!(si.t_test.as_ref().unwrap().p_value < 0.001);
+        assert!(si.mann_whitney.as_ref().unwrap().p_value < 0.05);
+
+        let (low, high) = si.confidence_interval.unwrap();
+        assert!(low < 100.0 && 100.0 < high);
+        assert!(low > 0.0);
+
+        // Identical metrics have no change.
+        let lvc = comparisons
+            .iter()
+            .find(|c| c.name == "LastVisualChange")
+            .unwrap();
+        assert_eq!(lvc.delta, 0.0);
+        assert_eq!(lvc.percent_change, Some(0.0));
+        assert_eq!(lvc.t_test.as_ref().unwrap().p_value, 1.0);
+        assert_eq!(lvc.confidence_interval, Some((0.0, 0.0)));
+    }
+}
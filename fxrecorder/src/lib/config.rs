@@ -2,21 +2,38 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::path::PathBuf;
-
 use serde::Deserialize;
 
+use crate::perfherder::DEFAULT_VISUAL_COMPLETE_THRESHOLDS;
+
 /// The configuration for FxRecorder.
 #[derive(Debug, Deserialize)]
 pub struct Config {
     /// The address of the `fxrunner` to connect to.
     pub host: String,
 
-    /// The path to the `visualmetrics.py` script.
-    pub visual_metrics_path: PathBuf,
-
     /// The recording configuraton.
     pub recording: RecordingConfig,
+
+    /// The Perfherder reporting configuration.
+    #[serde(default)]
+    pub perfherder: PerfherderConfig,
+}
+
+/// Perfherder-specific configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PerfherderConfig {
+    /// The visual completeness thresholds (in percent) to report as
+    /// `VisualCompleteNN` subtests.
+    pub visual_complete_thresholds: Vec<u8>,
+}
+
+impl Default for PerfherderConfig {
+    fn default() -> Self {
+        PerfherderConfig {
+            visual_complete_thresholds: DEFAULT_VISUAL_COMPLETE_THRESHOLDS.to_vec(),
+        }
+    }
 }
 
 /// Recording-specific configuration.
@@ -2,10 +2,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod aggregate;
 pub mod analysis;
+pub mod compare;
 pub mod config;
 pub mod ffmpeg;
 pub mod perfherder;
 pub mod proto;
 pub mod recorder;
 pub mod retry;
+pub mod stats;
+pub mod visualmetrics;
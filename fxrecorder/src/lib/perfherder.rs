@@ -2,11 +2,31 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::analysis::VisualMetrics;
+use crate::aggregate::AggregatedMetric;
 use serde_json::{json, Value};
 
+/// The visual completeness thresholds (in percent) that are reported by default.
+pub const DEFAULT_VISUAL_COMPLETE_THRESHOLDS: [u8; 3] = [85, 95, 99];
+
 /// Generate a JSON blob containing the performance metrics for Perfherder.
-pub fn generate_perfherder_metrics(metrics: &VisualMetrics) -> Value {
+///
+/// Each aggregated metric is reported as a subtest whose value is the
+/// outlier-filtered median and which carries the value of every replicate.
+pub fn generate_perfherder_metrics(metrics: &[AggregatedMetric]) -> Value {
+    let subtests: Vec<Value> = metrics
+        .iter()
+        .map(|metric| {
+            json!({
+              "name": metric.name,
+              "value": metric.value(),
+              "replicates": metric.replicates,
+              "unit": metric.unit,
+              "lowerIsBetter": true,
+              "shouldAlert": true,
+            })
+        })
+        .collect();
+
     json!({
       "application": {
         "name": "firefox",
@@ -17,30 +37,51 @@ pub fn generate_perfherder_metrics(metrics: &VisualMetrics) -> Value {
       "suites": [
         {
           "name": "firstrun",
-          "subtests": [
-            {
-              "name": "SpeedIndex",
-              "value": metrics.speed_index,
-              "unit": "ms * %",
-              "lowerIsBetter": true,
-              "shouldAlert": true,
-            },
-            {
-              "name": "FirstVisualChange",
-              "value": metrics.first_visual_change,
-              "unit": "ms",
-              "lowerIsBetter": true,
-              "shouldAlert": true,
-            },
-            {
-              "name": "LastVisualChange",
-              "value": metrics.last_visual_change,
-              "unit": "ms",
-              "lowerIsBetter": true,
-              "shouldAlert": true,
-            }
-          ]
+          "subtests": subtests,
         }
       ],
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aggregate::aggregate;
+    use crate::analysis::VisualMetrics;
+
+    fn subtest<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+        value["suites"][0]["subtests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|subtest| subtest["name"] == name)
+    }
+
+    #[test]
+    fn test_visual_complete_subtests() {
+        let replicates = vec![VisualMetrics::for_test(225, 300)];
+
+        let value = generate_perfherder_metrics(&aggregate(&replicates, &[]));
+        assert!(subtest(&value, "VisualComplete85").is_none());
+
+        let value = generate_perfherder_metrics(&aggregate(&replicates, &[50, 85, 101]));
+        assert!(subtest(&value, "VisualComplete50").is_some());
+        assert!(subtest(&value, "VisualComplete101").is_none());
+        assert_eq!(subtest(&value, "VisualComplete85").unwrap()["value"], 300.0);
+    }
+
+    #[test]
+    fn test_replicates() {
+        let replicates = vec![
+            VisualMetrics::for_test(1000, 300),
+            VisualMetrics::for_test(1100, 300),
+            VisualMetrics::for_test(1050, 300),
+        ];
+        let value = generate_perfherder_metrics(&aggregate(&replicates, &[]));
+
+        let si = subtest(&value, "SpeedIndex").unwrap();
+        assert_eq!(si["value"], 1050.0);
+        assert_eq!(si["replicates"], json!([1000, 1100, 1050]));
+        assert_eq!(si["unit"], "ms * %");
+    }
+}
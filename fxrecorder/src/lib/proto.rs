@@ -4,19 +4,59 @@
 
 use std::error::Error;
 use std::fmt::Debug;
-use std::io;
+use std::io::{self, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use libfxrecord::error::ErrorMessage;
 use libfxrecord::net::*;
 use libfxrecord::prefs::PrefValue;
+use sha2::{Digest, Sha256};
 use slog::{error, info, warn, Logger};
 use thiserror::Error;
 use tokio::fs::File;
 use tokio::net::TcpStream;
+use tokio::prelude::*;
+use tokio::time::timeout;
 
 use crate::recorder::Recorder;
 
+/// How long a build download can go without reporting progress before it is
+/// considered stalled.
+const DOWNLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many consecutive stall timeouts a build download can have before it
+/// is abandoned.
+const DOWNLOAD_STALL_LIMIT: u32 = 5;
+
+/// The extensions of profile archives that are streamed to the runner.
+///
+/// The runner extracts these as they are received, instead of writing them to
+/// disk first.
+const STREAMED_PROFILE_EXTENSIONS: &[&str] = &[".tar", ".tar.bz2", ".tar.gz", ".tgz", ".tar.xz"];
+
+/// The size of the chunks a profile is sent in.
+const PROFILE_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The build that a new session will use.
+#[derive(Clone, Debug)]
+pub enum Build {
+    /// A build that the runner will retrieve itself.
+    ///
+    /// This must not be a `BuildSource::Sideload`, which only describes the
+    /// archive to the runner. Use [`Sideload`](#variant.Sideload) instead.
+    Source(BuildSource),
+
+    /// A build archive on the recorder that will be sent to the runner.
+    Sideload(PathBuf),
+}
+
+impl From<BuildSource> for Build {
+    fn from(source: BuildSource) -> Self {
+        Build::Source(source)
+    }
+}
+
 /// The recorder side of the protocol.
 pub struct RecorderProto<R> {
     inner: Option<Proto<RunnerMessage, RecorderMessage, RunnerMessageKind, RecorderMessageKind>>,
@@ -40,21 +80,35 @@ where
     /// Send a request for a new session to the runner.
     pub async fn new_session(
         &mut self,
-        task_id: &str,
+        build: &Build,
+        runs: u32,
         profile_path: Option<&Path>,
         prefs: &[(String, PrefValue)],
     ) -> Result<String, RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
         info!(self.log, "Requesting new session");
 
-        let profile_size = match profile_path {
+        let profile = match profile_path {
             None => None,
-            Some(profile_path) => Some(tokio::fs::metadata(profile_path).await?.len()),
+            Some(profile_path) => Some(profile_info(profile_path).await?),
+        };
+
+        let source = match build {
+            Build::Source(BuildSource::Sideload { .. }) => {
+                panic!("sideloaded builds must be requested with Build::Sideload")
+            }
+            Build::Source(source) => source.clone(),
+            Build::Sideload(build_path) => BuildSource::Sideload {
+                size: tokio::fs::metadata(build_path).await?.len(),
+            },
         };
 
         self.send::<Session>(
             NewSessionRequest {
-                build_task_id: task_id.into(),
-                profile_size,
+                build: source.clone(),
+                profile: profile.clone(),
+                runs,
                 prefs: Vec::from(prefs),
             }
             .into(),
@@ -69,16 +123,86 @@ where
             }
         };
 
+        if source.is_taskcluster() {
+            let task_id = match self.recv::<ResolveBuild>().await?.result {
+                Ok(task_id) => task_id,
+                Err(e) => {
+                    error!(self.log, "Runner could not resolve build"; "build" => %source, "error" => %e);
+                    return Err(e.into());
+                }
+            };
+
+            info!(self.log, "Runner resolved build"; "build" => %source, "task_id" => &task_id);
+        }
+
+        let mut downloading = false;
+        let mut stalls = 0;
         loop {
-            let DownloadBuild { result } = self.recv().await?;
+            let DownloadBuild { result } = if downloading {
+                // The runner reports progress periodically while downloading,
+                // so a long silence means the download has stalled.
+                match timeout(DOWNLOAD_STALL_TIMEOUT, self.recv()).await {
+                    Ok(msg) => {
+                        stalls = 0;
+                        msg?
+                    }
+                    Err(..) => {
+                        stalls += 1;
+                        let secs = DOWNLOAD_STALL_TIMEOUT.as_secs() * stalls as u64;
+
+                        if stalls >= DOWNLOAD_STALL_LIMIT {
+                            error!(
+                                self.log,
+                                "Build download stalled";
+                                "secs_without_progress" => secs,
+                            );
+                            return Err(RecorderProtoError::DownloadStalled { secs });
+                        }
+
+                        warn!(
+                            self.log,
+                            "Build download appears to have stalled";
+                            "secs_without_progress" => secs,
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                self.recv().await?
+            };
 
             match result {
                 Ok(DownloadStatus::Downloading) => {
-                    info!(self.log, "Downloading build ...");
+                    if let Build::Sideload(ref build_path) = build {
+                        info!(self.log, "Sending build ..."; "path" => build_path.display());
+                        self.send_file(build_path).await?;
+                    } else {
+                        info!(self.log, "Downloading build ...");
+                        downloading = true;
+                    }
+                }
+
+                Ok(DownloadStatus::Progress(progress)) => {
+                    info!(
+                        self.log,
+                        "Downloading build ...";
+                        "received" => progress.received,
+                        "total" => progress.total,
+                        "bytes_per_sec" => progress.bytes_per_sec,
+                    );
                 }
 
                 Ok(DownloadStatus::Downloaded) => {
                     info!(self.log, "Build download complete; extracting build ...");
+                    downloading = false;
+                }
+
+                Ok(DownloadStatus::Cached) => {
+                    info!(self.log, "Runner has build cached; extracting build ...");
+                }
+
+                Ok(DownloadStatus::Local) => {
+                    info!(self.log, "Runner found build; extracting build ...");
                 }
 
                 Ok(DownloadStatus::Extracted) => {
@@ -87,7 +211,7 @@ where
                 }
 
                 Err(e) => {
-                    error!(self.log, "Build download failed"; "task_id" => task_id, "error" => %e);
+                    error!(self.log, "Build download failed"; "build" => %source, "error" => %e);
                     return Err(e.into());
                 }
             }
@@ -99,7 +223,7 @@ where
         }
 
         if let Some(profile_path) = profile_path {
-            self.send_profile(profile_path, profile_size.unwrap())
+            self.send_profile(&session_id, profile_path, &profile.unwrap())
                 .await?
         } else {
             info!(self.log, "No profile to send");
@@ -109,6 +233,249 @@ where
             }
         }
 
+        self.finish_new_session().await?;
+
+        Ok(session_id)
+    }
+
+    /// Submit a job to the runner's queue.
+    ///
+    /// The runner starts the job by itself once it is not busy with another
+    /// session and restarts. The returned job ID is the ID of the session
+    /// that the job will run in, which can be resumed once it has restarted.
+    pub async fn submit_job(
+        &mut self,
+        build: &BuildSource,
+        runs: u32,
+        profile_path: Option<&Path>,
+        prefs: &[(String, PrefValue)],
+    ) -> Result<String, RecorderProtoError<R::Error>> {
+        if let BuildSource::Sideload { .. } = build {
+            panic!("sideloaded builds cannot be submitted as jobs");
+        }
+
+        let profile = match profile_path {
+            None => None,
+            Some(profile_path) => {
+                // The runner keeps the profile archive until the job starts,
+                // so it cannot be streamed.
+                if is_streamed_profile(profile_path) {
+                    return Err(RecorderProtoError::StreamedJobProfile);
+                }
+
+                Some(profile_info(profile_path).await?)
+            }
+        };
+
+        self.handshake().await?;
+
+        info!(self.log, "Submitting job");
+
+        self.send(Session::SubmitJob(NewSessionRequest {
+            build: build.clone(),
+            profile: profile.clone(),
+            runs,
+            prefs: Vec::from(prefs),
+        }))
+        .await?;
+
+        let job_id = match self.recv::<SubmitJobResponse>().await?.result {
+            Ok(job_id) => job_id,
+            Err(e) => {
+                error!(self.log, "Runner could not queue job"; "error" => %e);
+                return Err(e.into());
+            }
+        };
+
+        if let (Some(profile_path), Some(profile)) = (profile_path, profile) {
+            match self.recv::<RecvProfile>().await?.result? {
+                DownloadStatus::Downloading => {
+                    info!(self.log, "Sending profile"; "profile_size" => profile.size);
+                }
+                unexpected => {
+                    return Err(RecorderProtoError::RecvProfileMismatch {
+                        received: unexpected,
+                        expected: DownloadStatus::Downloading,
+                    });
+                }
+            }
+
+            // The runner discards the job if the transfer fails, so it cannot
+            // be resumed.
+            self.send_profile_chunks(&job_id, profile_path, &profile, 0)
+                .await
+                .map_err(|e| match e {
+                    RecorderProtoError::ProfileTransferInterrupted { source, .. } => {
+                        RecorderProtoError::Proto(source)
+                    }
+                    e => e,
+                })?;
+
+            match self.recv::<RecvProfile>().await?.result? {
+                DownloadStatus::Downloaded => info!(self.log, "Profile sent"),
+                unexpected => {
+                    return Err(RecorderProtoError::RecvProfileMismatch {
+                        received: unexpected,
+                        expected: DownloadStatus::Downloaded,
+                    });
+                }
+            }
+        }
+
+        info!(self.log, "Job queued"; "job_id" => &job_id);
+
+        Ok(job_id)
+    }
+
+    /// Query the runner for the state of an existing session.
+    ///
+    /// Returns `None` if the session no longer exists (e.g., because it
+    /// expired).
+    pub async fn query_session(
+        &mut self,
+        session_id: &str,
+    ) -> Result<Option<SessionSummary>, RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Querying session"; "session_id" => session_id);
+        self.send::<Session>(
+            QuerySessionRequest {
+                session_id: session_id.into(),
+            }
+            .into(),
+        )
+        .await?;
+
+        match self.recv::<QuerySessionResponse>().await?.result {
+            Ok(summary) => Ok(summary),
+            Err(e) => {
+                error!(self.log, "Runner could not query session"; "error" => %e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// List the sessions on the runner, oldest first.
+    pub async fn list_sessions(
+        &mut self,
+    ) -> Result<Vec<SessionSummary>, RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Listing sessions");
+        self.send::<Session>(ListSessionsRequest.into()).await?;
+
+        match self.recv::<ListSessionsResponse>().await?.result {
+            Ok(sessions) => Ok(sessions),
+            Err(e) => {
+                error!(self.log, "Runner could not list sessions"; "error" => %e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Delete a session on the runner.
+    pub async fn delete_session(
+        &mut self,
+        session_id: &str,
+    ) -> Result<(), RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Deleting session"; "session_id" => session_id);
+        self.send::<Session>(
+            DeleteSessionRequest {
+                session_id: session_id.into(),
+            }
+            .into(),
+        )
+        .await?;
+
+        if let Err(e) = self.recv::<DeleteSessionResponse>().await?.result {
+            error!(self.log, "Runner could not delete session"; "error" => %e);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Delete all sessions on the runner.
+    ///
+    /// Returns the IDs of the deleted sessions.
+    pub async fn purge_sessions(&mut self) -> Result<Vec<String>, RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Purging sessions");
+        self.send::<Session>(PurgeSessionsRequest.into()).await?;
+
+        match self.recv::<PurgeSessionsResponse>().await?.result {
+            Ok(purged) => Ok(purged),
+            Err(e) => {
+                error!(self.log, "Runner could not purge sessions"; "error" => %e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Request the status of the runner.
+    pub async fn status(&mut self) -> Result<RunnerStatus, RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Requesting runner status");
+        self.send::<Session>(StatusRequest.into()).await?;
+
+        match self.recv::<StatusResponse>().await?.result {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                error!(self.log, "Runner could not report its status"; "error" => %e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Resume sending the profile for a new session after the transfer was
+    /// interrupted.
+    ///
+    /// The profile must be the same as the one originally sent.
+    pub async fn resume_profile(
+        &mut self,
+        session_id: &str,
+        profile_path: &Path,
+    ) -> Result<(), RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
+        info!(self.log, "Resuming profile transfer"; "session_id" => session_id);
+        let profile = profile_info(profile_path).await?;
+
+        self.send::<Session>(
+            ResumeProfileRequest {
+                session_id: session_id.into(),
+            }
+            .into(),
+        )
+        .await?;
+
+        let offset = match self.recv::<ResumeProfileResponse>().await?.result {
+            Ok(offset) => offset,
+            Err(e) => {
+                error!(self.log, "Runner could not resume profile transfer"; "error" => %e);
+                return Err(e.into());
+            }
+        };
+
+        info!(
+            self.log,
+            "Sending rest of profile";
+            "offset" => offset,
+            "profile_size" => profile.size,
+        );
+        self.send_profile_chunks(session_id, profile_path, &profile, offset)
+            .await?;
+        self.recv_profile_status().await?;
+
+        self.finish_new_session().await
+    }
+
+    /// Wait for the runner to finish setting up a new session.
+    async fn finish_new_session(&mut self) -> Result<(), RecorderProtoError<R::Error>> {
         if let WritePrefs { result: Err(e) } = self.recv().await? {
             error!(self.log, "Runner could not write prefs"; "error" => %e);
             return Err(e.into());
@@ -121,16 +488,24 @@ where
 
         info!(self.log, "Runner is restarting...");
 
-        Ok(session_id)
+        Ok(())
     }
 
     /// Send a request to resume a session to the runner.
+    ///
+    /// If the session has runs remaining after this one, the runner will
+    /// restart and the session must be resumed again.
+    ///
+    /// Returns the path to the recording and, if the runner waited to become
+    /// idle, the idle readings it achieved.
     pub async fn resume_session(
         &mut self,
         session_id: &str,
         idle: Idle,
         directory: &Path,
-    ) -> Result<PathBuf, RecorderProtoError<R::Error>> {
+    ) -> Result<(PathBuf, Option<IdleReadings>), RecorderProtoError<R::Error>> {
+        self.handshake().await?;
+
         info!(self.log, "Resuming session");
         self.send::<Session>(
             ResumeSessionRequest {
@@ -141,26 +516,54 @@ where
         )
         .await?;
 
-        if let ResumeResponse { result: Err(e) } = self.recv().await? {
-            error!(
-                self.log,
-                "Could not resume session with runner";
-                "id" => session_id,
-                "error" => %e,
-            );
-            return Err(e.into());
-        }
+        let remaining_runs = match self.recv::<ResumeResponse>().await?.result {
+            Ok(remaining_runs) => remaining_runs,
+            Err(e) => {
+                error!(
+                    self.log,
+                    "Could not resume session with runner";
+                    "id" => session_id,
+                    "error" => %e,
+                );
+                return Err(e.into());
+            }
+        };
 
-        if idle == Idle::Wait {
+        info!(self.log, "Resumed session"; "remaining_runs" => remaining_runs);
+
+        let idle_readings = if idle == Idle::Wait {
             info!(self.log, "Waiting for runner to become idle...");
 
-            if let WaitForIdle { result: Err(e) } = self.recv().await? {
-                error!(self.log, "Runner could not become idle"; "error" => %e);
-                return Err(e.into());
+            let readings = match self.recv::<WaitForIdle>().await?.result {
+                Ok(readings) => readings,
+                Err(e) => {
+                    error!(self.log, "Runner could not become idle"; "error" => %e);
+                    return Err(e.into());
+                }
+            };
+
+            info!(
+                self.log,
+                "Runner became idle";
+                "cpu_idle" => readings.cpu_idle,
+                "samples" => readings.samples,
+                "elapsed_ms" => readings.elapsed_ms,
+            );
+
+            for disk in &readings.disks {
+                info!(
+                    self.log,
+                    "Runner disk activity";
+                    "device" => &disk.device,
+                    "reads" => disk.reads,
+                    "writes" => disk.writes,
+                );
             }
 
-            info!(self.log, "Runner became idle");
-        }
+            Some(readings)
+        } else {
+            None
+        };
 
         info!(self.log, "Beginning recording...");
         let handle = self
@@ -211,23 +614,78 @@ where
             warn!(self.log, "runner did not clean up successfully"; "error" => ?e);
         }
 
+        if remaining_runs > 1 {
+            if let Restarting { result: Err(e) } = self.recv().await? {
+                error!(self.log, "Runner could not restart"; "error" => %e);
+                return Err(e.into());
+            }
+
+            info!(self.log, "Runner is restarting for the next run...");
+        }
+
         info!(self.log, "recording complete");
 
-        Ok(recording_path)
+        Ok((recording_path, idle_readings))
+    }
+
+    /// Exchange version information with the runner.
+    ///
+    /// This must be the first exchange on every connection.
+    async fn handshake(&mut self) -> Result<(), RecorderProtoError<R::Error>> {
+        let local = VersionInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        self.send(Handshake {
+            version: local.clone(),
+        })
+        .await?;
+
+        let HandshakeResponse {
+            version: remote,
+            result,
+        } = self.recv().await?;
+
+        if !local.is_compatible(&remote) {
+            error!(
+                self.log,
+                "Runner speaks an incompatible protocol version";
+                "local" => %local,
+                "remote" => %remote,
+            );
+            return Err(ProtoError::VersionMismatch {
+                local: Box::new(local),
+                remote: Box::new(remote),
+            }
+            .into());
+        }
+
+        if let Err(e) = result {
+            error!(self.log, "Runner rejected handshake"; "error" => %e);
+            return Err(e.into());
+        }
+
+        info!(self.log, "Handshake complete"; "runner" => %remote);
+
+        Ok(())
     }
 
     /// Send the profile at the given path to the runner.
     async fn send_profile(
         &mut self,
+        session_id: &str,
         profile_path: &Path,
-        profile_size: u64,
+        profile: &ProfileInfo,
     ) -> Result<(), RecorderProtoError<R::Error>> {
         let RecvProfile { result } = self.recv().await?;
 
         match result? {
-            DownloadStatus::Downloading => {
-                info!(self.log, "Sending profile"; "profile_size" => profile_size);
-            }
+            DownloadStatus::Downloading => match profile.transfer {
+                ProfileTransfer::Archive => {
+                    info!(self.log, "Sending profile"; "profile_size" => profile.size);
+                }
+                ProfileTransfer::Stream => {
+                    info!(self.log, "Streaming profile"; "profile_size" => profile.size);
+                }
+            },
 
             unexpected => {
                 return Err(RecorderProtoError::RecvProfileMismatch {
@@ -237,12 +695,64 @@ where
             }
         }
 
-        let mut stream = self.inner.take().unwrap().into_inner();
-        let result = Self::send_profile_impl(&mut stream, profile_path).await;
-        self.inner = Some(Proto::new(stream));
+        self.send_profile_chunks(session_id, profile_path, profile, 0)
+            .await?;
+        self.recv_profile_status().await
+    }
+
+    /// Send the profile at the given path to the runner in chunks, starting at
+    /// the given offset.
+    ///
+    /// If sending a chunk of an archive transfer fails, the transfer can be
+    /// resumed with [`resume_profile`](#method.resume_profile).
+    async fn send_profile_chunks(
+        &mut self,
+        session_id: &str,
+        profile_path: &Path,
+        profile: &ProfileInfo,
+        mut offset: u64,
+    ) -> Result<(), RecorderProtoError<R::Error>> {
+        let mut f = File::open(profile_path).await?;
+        f.seek(SeekFrom::Start(offset)).await?;
+
+        let mut buf = vec![0u8; PROFILE_CHUNK_SIZE];
+        while offset < profile.size {
+            let len = buf.len().min((profile.size - offset) as usize);
+            let n = f.read(&mut buf[..len]).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "profile was truncated while it was being sent",
+                )
+                .into());
+            }
+
+            let chunk = ProfileChunk {
+                offset,
+                data: buf[..n].to_vec(),
+            };
+
+            if let Err(e) = self.send(chunk).await {
+                error!(self.log, "Could not send profile"; "offset" => offset, "error" => %e);
+
+                if profile.transfer == ProfileTransfer::Archive {
+                    return Err(RecorderProtoError::ProfileTransferInterrupted {
+                        session_id: session_id.into(),
+                        source: e,
+                    });
+                }
 
-        result?;
+                return Err(e.into());
+            }
+
+            offset += n as u64;
+        }
 
+        Ok(())
+    }
+
+    /// Wait for the runner to verify and extract the profile.
+    async fn recv_profile_status(&mut self) -> Result<(), RecorderProtoError<R::Error>> {
         let mut state = DownloadStatus::Downloading;
         loop {
             let next_state = self.recv::<RecvProfile>().await?.result?;
@@ -260,8 +770,12 @@ where
             state = next_state;
 
             match state {
-                // This would be caught above because this is never an expected state.
-                DownloadStatus::Downloading => unreachable!(),
+                // This would be caught above because these are never expected
+                // states.
+                DownloadStatus::Downloading
+                | DownloadStatus::Progress(..)
+                | DownloadStatus::Cached
+                | DownloadStatus::Local => unreachable!(),
 
                 DownloadStatus::Downloaded => {
                     info!(self.log, "Profile sent; extracting...");
@@ -279,18 +793,27 @@ where
         Ok(())
     }
 
-    /// Write the raw bytes from the profile to the runner.
-    async fn send_profile_impl(
+    /// Write the raw bytes of the file at the given path to the runner.
+    async fn send_file(&mut self, path: &Path) -> Result<(), RecorderProtoError<R::Error>> {
+        let mut stream = self.inner.take().unwrap().into_inner();
+        let result = Self::send_file_impl(&mut stream, path).await;
+        self.inner = Some(Proto::new(stream));
+
+        result
+    }
+
+    async fn send_file_impl(
         stream: &mut TcpStream,
-        profile_path: &Path,
+        path: &Path,
     ) -> Result<(), RecorderProtoError<R::Error>> {
-        let mut f = File::open(profile_path).await?;
+        let mut f = File::open(path).await?;
 
         tokio::io::copy(&mut f, stream)
             .await
             .map_err(Into::into)
             .map(drop)
     }
+
     /// Send the given message to the recorder.
     ///
     /// If the underlying proto is None, this will panic.
@@ -312,6 +835,50 @@ where
     }
 }
 
+/// Describe the profile archive at the given path to the runner.
+async fn profile_info(path: &Path) -> Result<ProfileInfo, io::Error> {
+    let mut f = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; PROFILE_CHUNK_SIZE];
+    let mut size = 0;
+
+    loop {
+        let n = f.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+
+    let transfer = if is_streamed_profile(path) {
+        ProfileTransfer::Stream
+    } else {
+        ProfileTransfer::Archive
+    };
+
+    Ok(ProfileInfo {
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+        transfer,
+    })
+}
+
+/// Whether or not the profile archive at the given path is streamed to the
+/// runner, based on its extension.
+fn is_streamed_profile(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => {
+            let name = name.to_ascii_lowercase();
+            STREAMED_PROFILE_EXTENSIONS
+                .iter()
+                .any(|ext| name.ends_with(ext))
+        }
+        None => false,
+    }
+}
+
 /// An error in the RecordingProto.
 ///
 /// For a `RecordingProto<R: Recorder>`, `RecordingError` is `<R as Recorder>::Error`.
@@ -323,6 +890,16 @@ where
     #[error(transparent)]
     Proto(#[from] ProtoError<RunnerMessageKind>),
 
+    #[error(
+        "The profile transfer for session `{}' was interrupted: {}",
+        session_id,
+        source
+    )]
+    ProfileTransferInterrupted {
+        session_id: String,
+        source: ProtoError<RunnerMessageKind>,
+    },
+
     #[error(
         "Expected a download status of `{}', but received `{}' instead",
         expected,
@@ -333,6 +910,12 @@ where
         received: DownloadStatus,
     },
 
+    #[error("The build download made no progress for {} seconds", secs)]
+    DownloadStalled { secs: u64 },
+
+    #[error("Streamed profiles cannot be used by queued jobs")]
+    StreamedJobProfile,
+
     #[error(transparent)]
     Recording(RecordingError),
 }
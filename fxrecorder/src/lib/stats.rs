@@ -0,0 +1,499 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Descriptive statistics over replicate measurements.
+
+use serde::{Deserialize, Serialize};
+
+/// Summary statistics of a set of values.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Summary {
+    /// The number of values.
+    pub count: usize,
+
+    /// The arithmetic mean.
+    pub mean: f64,
+
+    /// The median.
+    pub median: f64,
+
+    /// The geometric mean.
+    ///
+    /// This is zero if any value is zero.
+    pub geometric_mean: f64,
+
+    /// The sample standard deviation.
+    ///
+    /// This is zero if there are fewer than two values.
+    pub stddev: f64,
+
+    /// The minimum value.
+    pub min: f64,
+
+    /// The maximum value.
+    pub max: f64,
+}
+
+impl Summary {
+    /// Summarize the given values.
+    ///
+    /// Returns `None` if there are no values.
+    pub fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let count = values.len();
+        let sorted = sorted(values);
+
+        let mean = mean(values);
+        let geometric_mean = if values.iter().any(|&v| v <= 0.0) {
+            0.0
+        } else {
+            (values.iter().map(|v| v.ln()).sum::<f64>() / count as f64).exp()
+        };
+
+        Some(Summary {
+            count,
+            mean,
+            median: quantile(&sorted, 0.5),
+            geometric_mean,
+            stddev: variance(values).sqrt(),
+            min: sorted[0],
+            max: sorted[count - 1],
+        })
+    }
+}
+
+/// Compute the arithmetic mean of the values.
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Compute the sample variance of the values.
+///
+/// This is zero if there are fewer than two values.
+pub fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+
+    let mean = mean(values);
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+/// Return a sorted copy of the values.
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).expect("values must not be NaN"));
+    sorted
+}
+
+/// Compute the `q`th quantile of sorted values with linear interpolation.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q * (sorted.len() - 1) as f64;
+    let lower = pos.floor() as usize;
+    let upper = pos.ceil() as usize;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+/// Remove outliers from the values.
+///
+/// Values outside of Tukey's fences (more than 1.5 times the interquartile
+/// range below the first quartile or above the third quartile) are considered
+/// outliers. Fewer than four values are never filtered.
+pub fn filter_outliers(values: &[f64]) -> Vec<f64> {
+    if values.len() < 4 {
+        return values.to_vec();
+    }
+
+    let sorted = sorted(values);
+    let q1 = quantile(&sorted, 0.25);
+    let q3 = quantile(&sorted, 0.75);
+    let iqr = q3 - q1;
+
+    let low = q1 - 1.5 * iqr;
+    let high = q3 + 1.5 * iqr;
+
+    values
+        .iter()
+        .copied()
+        .filter(|&v| v >= low && v <= high)
+        .collect()
+}
+
+/// The result of Welch's unequal variances t-test.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TTest {
+    /// The t statistic.
+    pub t: f64,
+
+    /// The degrees of freedom.
+    pub df: f64,
+
+    /// The two-sided p-value.
+    pub p_value: f64,
+}
+
+/// Perform Welch's t-test on two samples.
+///
+/// Returns `None` if either sample has fewer than two values.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<TTest> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let diff = mean(b) - mean(a);
+    let (se, df) = welch_standard_error(a, b);
+
+    if se == 0.0 {
+        // Both samples are constant.
+        return Some(TTest {
+            t: 0.0,
+            df,
+            p_value: if diff == 0.0 { 1.0 } else { 0.0 },
+        });
+    }
+
+    let t = diff / se;
+
+    Some(TTest {
+        t,
+        df,
+        p_value: student_t_p_value(t, df),
+    })
+}
+
+/// Compute the confidence interval of the difference between the means of two
+/// samples (i.e., `mean(b) - mean(a)`) at the given confidence level.
+///
+/// Returns `None` if either sample has fewer than two values.
+pub fn mean_difference_interval(a: &[f64], b: &[f64], confidence: f64) -> Option<(f64, f64)> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let diff = mean(b) - mean(a);
+    let (se, df) = welch_standard_error(a, b);
+
+    if se == 0.0 {
+        return Some((diff, diff));
+    }
+
+    let margin = student_t_critical_value(1.0 - confidence, df) * se;
+    Some((diff - margin, diff + margin))
+}
+
+/// Compute the standard error of the difference of the means and the
+/// Welch-Satterthwaite degrees of freedom.
+fn welch_standard_error(a: &[f64], b: &[f64]) -> (f64, f64) {
+    let na = a.len() as f64;
+    let nb = b.len() as f64;
+    let va = variance(a) / na;
+    let vb = variance(b) / nb;
+
+    let se2 = va + vb;
+    let df = if se2 == 0.0 {
+        na + nb - 2.0
+    } else {
+        se2 * se2 / (va * va / (na - 1.0) + vb * vb / (nb - 1.0))
+    };
+
+    (se2.sqrt(), df)
+}
+
+/// The result of the Mann-Whitney U test.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MannWhitney {
+    /// The U statistic of the first sample.
+    pub u: f64,
+
+    /// The two-sided p-value.
+    ///
+    /// This uses the normal approximation with tie and continuity
+    /// corrections.
+    pub p_value: f64,
+}
+
+/// Perform the Mann-Whitney U test on two samples.
+///
+/// Returns `None` if either sample is empty.
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+
+    let na = a.len() as f64;
+    let nb = b.len() as f64;
+    let n = na + nb;
+
+    let mut combined: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&v| (v, true))
+        .chain(b.iter().map(|&v| (v, false)))
+        .collect();
+    combined.sort_by(|x, y| x.0.partial_cmp(&y.0).expect("values must not be NaN"));
+
+    // Assign ranks, averaging the ranks of ties.
+    let mut rank_sum_a = 0f64;
+    let mut tie_correction = 0f64;
+    let mut i = 0;
+    while i < combined.len() {
+        let mut j = i;
+        while j < combined.len() && combined[j].0 == combined[i].0 {
+            j += 1;
+        }
+
+        let ties = (j - i) as f64;
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum_a += rank * combined[i..j].iter().filter(|(_, is_a)| *is_a).count() as f64;
+        tie_correction += ties * ties * ties - ties;
+
+        i = j;
+    }
+
+    let u = rank_sum_a - na * (na + 1.0) / 2.0;
+    let mu = na * nb / 2.0;
+    let sigma = (na * nb / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)))).sqrt();
+
+    let p_value = if sigma == 0.0 {
+        1.0
+    } else {
+        let z = ((u - mu).abs() - 0.5).max(0.0) / sigma;
+        erfc(z / std::f64::consts::SQRT_2).min(1.0)
+    };
+
+    Some(MannWhitney { u, p_value })
+}
+
+/// Compute the two-sided p-value of the t statistic with `df` degrees of freedom.
+fn student_t_p_value(t: f64, df: f64) -> f64 {
+    regularized_incomplete_beta(df / (df + t * t), df / 2.0, 0.5)
+}
+
+/// Compute the t value whose two-sided p-value is `alpha`.
+fn student_t_critical_value(alpha: f64, df: f64) -> f64 {
+    let mut low = 0f64;
+    let mut high = 1e6f64;
+
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if student_t_p_value(mid, df) > alpha {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+/// The complementary error function.
+///
+/// This has a fractional error of less than 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+/// The natural logarithm of the gamma function, via the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return pi.ln() - (pi * x).sin().ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let a = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEFFICIENTS[0], |a, (i, c)| a + c / (x + i as f64));
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`.
+fn regularized_incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    } else if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+/// Evaluate the continued fraction for the incomplete beta function with
+/// Lentz's method.
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const MAX_ITERATIONS: u32 = 300;
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let mut c = 1f64;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+
+        let numerator = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / clamp(1.0 + numerator * d);
+        c = clamp(1.0 + numerator / c);
+        h *= d * c;
+
+        let numerator = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / clamp(1.0 + numerator * d);
+        c = clamp(1.0 + numerator / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(Summary::new(&[]), None);
+
+        let summary = Summary::new(&[4.0]).unwrap();
+        assert_eq!(summary.count, 1);
+        assert_close(summary.mean, 4.0);
+        assert_close(summary.median, 4.0);
+        assert_close(summary.geometric_mean, 4.0);
+        assert_close(summary.stddev, 0.0);
+
+        let summary = Summary::new(&[8.0, 1.0, 4.0, 2.0]).unwrap();
+        assert_eq!(summary.count, 4);
+        assert_close(summary.mean, 3.75);
+        assert_close(summary.median, 3.0);
+        assert_close(summary.geometric_mean, 64f64.powf(0.25));
+        assert_close(summary.stddev, (28.75f64 / 3.0).sqrt());
+        assert_close(summary.min, 1.0);
+        assert_close(summary.max, 8.0);
+
+        let summary = Summary::new(&[0.0, 1.0, 2.0]).unwrap();
+        assert_close(summary.geometric_mean, 0.0);
+    }
+
+    #[test]
+    fn test_filter_outliers() {
+        assert_eq!(filter_outliers(&[1.0, 100.0, 2.0]), vec![1.0, 100.0, 2.0]);
+
+        assert_eq!(
+            filter_outliers(&[10.0, 11.0, 12.0, 11.0, 50.0, 10.0]),
+            vec![10.0, 11.0, 12.0, 11.0, 10.0]
+        );
+
+        assert_eq!(
+            filter_outliers(&[10.0, 11.0, 12.0, 13.0]),
+            vec![10.0, 11.0, 12.0, 13.0]
+        );
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        assert_eq!(welch_t_test(&[1.0], &[1.0, 2.0]), None);
+
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+
+        let result = welch_t_test(&a, &b).unwrap();
+        assert_close(result.t, 5.0);
+        assert_close(result.df, 8.0);
+        assert!((result.p_value - 0.001_052_825).abs() < 1e-6);
+
+        let result = welch_t_test(&a, &a).unwrap();
+        assert_close(result.p_value, 1.0);
+
+        let result = welch_t_test(&[1.0, 1.0], &[2.0, 2.0]).unwrap();
+        assert_close(result.p_value, 0.0);
+    }
+
+    #[test]
+    fn test_mean_difference_interval() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+
+        let (low, high) = mean_difference_interval(&a, &b, 0.95).unwrap();
+        assert!((low - 2.694_003).abs() < 1e-5, "low = {}", low);
+        assert!((high - 7.305_997).abs() < 1e-5, "high = {}", high);
+
+        assert_eq!(
+            mean_difference_interval(&[1.0, 1.0], &[2.0, 2.0], 0.95),
+            Some((1.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn test_mann_whitney_u() {
+        assert_eq!(mann_whitney_u(&[], &[1.0]), None);
+
+        let result =
+            mann_whitney_u(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]).unwrap();
+        assert_close(result.u, 0.0);
+        assert!((result.p_value - 0.012_185).abs() < 1e-5);
+
+        // Ties are ranked equally.
+        let result = mann_whitney_u(&[1.0, 2.0, 2.0], &[2.0, 3.0, 4.0]).unwrap();
+        assert_close(result.u, 1.0);
+
+        let result = mann_whitney_u(&[1.0, 1.0], &[1.0, 1.0]).unwrap();
+        assert_close(result.p_value, 1.0);
+    }
+}
@@ -0,0 +1,538 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A native implementation of the visual metrics computed by
+//! [browsertime's visualmetrics.py][visualmetrics].
+//!
+//! [visualmetrics]: https://github.com/sitespeedio/browsertime/blob/main/browsertime/visualmetrics.py
+
+use image::imageops::{grayscale, resize, FilterType};
+use image::{GenericImageView, GrayImage, Rgb, RgbImage};
+
+/// The number of buckets in each channel of a histogram.
+const BUCKETS: usize = 256;
+
+/// Pixels with every channel at or above this value are considered white and
+/// are excluded from histograms.
+///
+/// This allows for a tiny bit of slop for compression artifacts.
+const WHITE_THRESHOLD: u8 = 250;
+
+/// The distance between buckets that are considered to match when computing
+/// progress, to allow for slight colour variations.
+const SLOP: usize = 5;
+
+/// The maximum width of the thumbnails used to compute SSIM.
+///
+/// Frames are downscaled so that we do not have to keep every full-size frame
+/// in memory until the last frame is known.
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// The size of the (square) windows over which SSIM is computed.
+const SSIM_WINDOW: u32 = 8;
+
+/// The minimum Sobel gradient magnitude for a pixel to be considered an edge.
+const EDGE_THRESHOLD: i32 = 128;
+
+/// A histogram of the colour channels of a frame.
+///
+/// White pixels are not counted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Histogram {
+    r: Vec<u64>,
+    g: Vec<u64>,
+    b: Vec<u64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            r: vec![0; BUCKETS],
+            g: vec![0; BUCKETS],
+            b: vec![0; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    /// Compute the histogram of an image.
+    pub fn from_image<I>(image: &I) -> Self
+    where
+        I: GenericImageView<Pixel = Rgb<u8>>,
+    {
+        let mut histogram = Histogram::default();
+
+        for (_, _, pixel) in image.pixels() {
+            if pixel[0] < WHITE_THRESHOLD
+                || pixel[1] < WHITE_THRESHOLD
+                || pixel[2] < WHITE_THRESHOLD
+            {
+                histogram.r[pixel[0] as usize] += 1;
+                histogram.g[pixel[1] as usize] += 1;
+                histogram.b[pixel[2] as usize] += 1;
+            }
+        }
+
+        histogram
+    }
+
+    fn channels(&self) -> [&[u64]; 3] {
+        [&self.r, &self.g, &self.b]
+    }
+}
+
+/// A frame of video to be analyzed.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The timestamp of the frame (in ms).
+    pub timestamp: u32,
+
+    /// The histogram of the frame.
+    pub histogram: Histogram,
+
+    /// The number of edge pixels in the frame.
+    pub contentfulness: u64,
+
+    /// A downscaled greyscale copy of the frame.
+    pub thumbnail: GrayImage,
+}
+
+impl Frame {
+    /// Compute the data required for analysis from an image.
+    pub fn new(timestamp: u32, image: &RgbImage) -> Self {
+        let luma = grayscale(image);
+
+        let thumbnail = if luma.width() > THUMBNAIL_WIDTH {
+            let height =
+                (luma.height() as u64 * THUMBNAIL_WIDTH as u64 / luma.width() as u64).max(1) as u32;
+            resize(&luma, THUMBNAIL_WIDTH, height, FilterType::Triangle)
+        } else {
+            luma.clone()
+        };
+
+        Frame {
+            timestamp,
+            histogram: Histogram::from_image(image),
+            contentfulness: contentfulness(&luma),
+            thumbnail,
+        }
+    }
+}
+
+/// Metrics computed from a sequence of frames.
+///
+/// All times are relative to the timestamp of the first frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrameMetrics {
+    /// The time of the first frame that differs from the first frame (in ms).
+    pub first_visual_change: u32,
+
+    /// The time of the last frame (in ms).
+    pub last_visual_change: u32,
+
+    /// The speed index.
+    pub speed_index: u32,
+
+    /// The perceptual speed index, computed from the SSIM of each frame
+    /// against the last frame.
+    pub perceptual_speed_index: u32,
+
+    /// The contentful speed index, computed from the number of edge pixels in
+    /// each frame.
+    pub contentful_speed_index: u32,
+
+    /// The visual progress of each frame as `(time in ms, percent complete)`
+    /// pairs.
+    pub visual_progress: Vec<(u32, u8)>,
+}
+
+/// Compute visual metrics from a sequence of de-duplicated frames, ordered by
+/// their timestamps.
+///
+/// Returns `None` if there are no frames.
+pub fn compute_frame_metrics(frames: &[Frame]) -> Option<FrameMetrics> {
+    let first = frames.first()?;
+    let last = frames.last()?;
+
+    let visual_progress: Vec<(u32, u8)> = frames
+        .iter()
+        .map(|frame| {
+            (
+                frame.timestamp - first.timestamp,
+                frame_progress(&frame.histogram, &first.histogram, &last.histogram),
+            )
+        })
+        .collect();
+
+    let (first_visual_change, last_visual_change) = if frames.len() > 1 {
+        (
+            visual_progress[1].0,
+            visual_progress[visual_progress.len() - 1].0,
+        )
+    } else {
+        (0, 0)
+    };
+
+    // Like visualmetrics.py, the first frame is always considered to be
+    // perceptually incomplete.
+    let perceptual_progress: Vec<(u32, f64)> = visual_progress
+        .iter()
+        .zip(frames)
+        .enumerate()
+        .map(|(i, (&(time, _), frame))| {
+            if i == 0 {
+                (time, 0.0)
+            } else {
+                (time, ssim(&frame.thumbnail, &last.thumbnail))
+            }
+        })
+        .collect();
+
+    let max_content = frames
+        .iter()
+        .map(|frame| frame.contentfulness)
+        .max()
+        .unwrap_or(0);
+    let contentful_progress: Vec<(u32, f64)> = visual_progress
+        .iter()
+        .zip(frames)
+        .map(|(&(time, _), frame)| {
+            if max_content == 0 {
+                (time, 0.0)
+            } else {
+                (time, frame.contentfulness as f64 / max_content as f64)
+            }
+        })
+        .collect();
+
+    Some(FrameMetrics {
+        first_visual_change,
+        last_visual_change,
+        speed_index: speed_index(&visual_progress),
+        perceptual_speed_index: area_above(&perceptual_progress) as u32,
+        contentful_speed_index: area_above(&contentful_progress) as u32,
+        visual_progress,
+    })
+}
+
+/// Compute how close a frame is to the final frame, as a percentage.
+///
+/// Progress is the fraction of the histogram difference between the first and
+/// last frames that has been accounted for by the given frame.
+pub fn frame_progress(histogram: &Histogram, start: &Histogram, last: &Histogram) -> u8 {
+    let mut total = 0u64;
+    let mut matched = 0u64;
+
+    for ((current, start), last) in histogram
+        .channels()
+        .iter()
+        .zip(start.channels().iter())
+        .zip(last.channels().iter())
+    {
+        let mut available: Vec<u64> = current
+            .iter()
+            .zip(start.iter())
+            .map(|(c, s)| c.max(s) - c.min(s))
+            .collect();
+
+        for i in 0..BUCKETS {
+            let mut target = last[i].max(start[i]) - last[i].min(start[i]);
+
+            if target == 0 {
+                continue;
+            }
+
+            total += target;
+
+            let low = i.saturating_sub(SLOP);
+            let high = (i + SLOP).min(BUCKETS);
+
+            for slot in &mut available[low..high] {
+                let this_match = target.min(*slot);
+                *slot -= this_match;
+                matched += this_match;
+                target -= this_match;
+            }
+        }
+    }
+
+    if total == 0 {
+        100
+    } else {
+        (matched as f64 / total as f64 * 100.0).floor() as u8
+    }
+}
+
+/// Compute the speed index from visual progress.
+///
+/// The speed index is the area above the visual progress curve, i.e., the sum
+/// of the time spent at each level of incompleteness.
+pub fn speed_index(visual_progress: &[(u32, u8)]) -> u32 {
+    let progress: Vec<(u32, f64)> = visual_progress
+        .iter()
+        .map(|&(time, progress)| (time, progress as f64 / 100.0))
+        .collect();
+
+    area_above(&progress) as u32
+}
+
+/// Compute the area above a step-wise progress curve, where progress is in the
+/// range `[0, 1]`.
+fn area_above(progress: &[(u32, f64)]) -> f64 {
+    let mut area = 0f64;
+    let mut last = match progress.first() {
+        Some(first) => *first,
+        None => return 0.0,
+    };
+
+    for &(time, progress) in progress {
+        let elapsed = (time - last.0) as f64;
+        area += elapsed * (1.0 - last.1);
+        last = (time, progress);
+    }
+
+    area
+}
+
+/// Compute the mean structural similarity (SSIM) of two greyscale images.
+///
+/// SSIM is computed over non-overlapping square windows and averaged. The
+/// result is in the range `[-1, 1]`, where 1 indicates identical images.
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let width = a.width().min(b.width());
+    let height = a.height().min(b.height());
+
+    let mut total = 0f64;
+    let mut windows = 0u64;
+
+    for y in (0..height).step_by(SSIM_WINDOW as usize) {
+        for x in (0..width).step_by(SSIM_WINDOW as usize) {
+            let w = SSIM_WINDOW.min(width - x);
+            let h = SSIM_WINDOW.min(height - y);
+            let n = (w * h) as f64;
+
+            let a = a.view(x, y, w, h);
+            let b = b.view(x, y, w, h);
+
+            let mean_a = a.pixels().map(|(_, _, p)| p[0] as f64).sum::<f64>() / n;
+            let mean_b = b.pixels().map(|(_, _, p)| p[0] as f64).sum::<f64>() / n;
+
+            let mut var_a = 0f64;
+            let mut var_b = 0f64;
+            let mut covar = 0f64;
+            for ((_, _, pa), (_, _, pb)) in a.pixels().zip(b.pixels()) {
+                let da = pa[0] as f64 - mean_a;
+                let db = pb[0] as f64 - mean_b;
+                var_a += da * da;
+                var_b += db * db;
+                covar += da * db;
+            }
+            var_a /= n;
+            var_b /= n;
+            covar /= n;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// Compute the contentfulness of a greyscale image, i.e., the number of pixels
+/// that lie on an edge.
+///
+/// Edges are detected with the Sobel operator.
+pub fn contentfulness(image: &GrayImage) -> u64 {
+    let (width, height) = image.dimensions();
+    if width < 3 || height < 3 {
+        return 0;
+    }
+
+    let px = |x: u32, y: u32| image.get_pixel(x, y)[0] as i32;
+
+    let mut edges = 0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = px(x + 1, y - 1) + 2 * px(x + 1, y) + px(x + 1, y + 1)
+                - px(x - 1, y - 1)
+                - 2 * px(x - 1, y)
+                - px(x - 1, y + 1);
+            let gy = px(x - 1, y + 1) + 2 * px(x, y + 1) + px(x + 1, y + 1)
+                - px(x - 1, y - 1)
+                - 2 * px(x, y - 1)
+                - px(x + 1, y - 1);
+
+            if gx.abs() + gy.abs() >= EDGE_THRESHOLD {
+                edges += 1;
+            }
+        }
+    }
+
+    edges
+}
+
+#[cfg(test)]
+mod test {
+    use image::{ImageBuffer, RgbImage};
+
+    use super::*;
+
+    const WIDTH: u32 = 100;
+    const HEIGHT: u32 = 100;
+
+    /// Generate a white frame where the first `rows` rows are black.
+    fn partial_frame(rows: u32) -> RgbImage {
+        ImageBuffer::from_fn(WIDTH, HEIGHT, |_, y| {
+            if y < rows {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    fn frame(timestamp: u32, rows: u32) -> Frame {
+        Frame::new(timestamp, &partial_frame(rows))
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::from_image(&partial_frame(0));
+        assert_eq!(histogram, Histogram::default());
+
+        let histogram = Histogram::from_image(&partial_frame(10));
+        assert_eq!(histogram.r[0], 10 * WIDTH as u64);
+        assert_eq!(histogram.g[0], 10 * WIDTH as u64);
+        assert_eq!(histogram.b[0], 10 * WIDTH as u64);
+        assert_eq!(histogram.r.iter().sum::<u64>(), 10 * WIDTH as u64);
+
+        // Nearly-white pixels are not counted, but a pixel with only some
+        // channels near white is.
+        let image: RgbImage = ImageBuffer::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([251, 252, 253])
+            } else {
+                Rgb([255, 255, 10])
+            }
+        });
+        let histogram = Histogram::from_image(&image);
+        assert_eq!(histogram.r[255], 1);
+        assert_eq!(histogram.b[10], 1);
+        assert_eq!(histogram.r.iter().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn test_frame_progress() {
+        let start = Histogram::from_image(&partial_frame(0));
+        let half = Histogram::from_image(&partial_frame(50));
+        let quarter = Histogram::from_image(&partial_frame(25));
+        let last = Histogram::from_image(&partial_frame(100));
+
+        assert_eq!(frame_progress(&start, &start, &last), 0);
+        assert_eq!(frame_progress(&quarter, &start, &last), 25);
+        assert_eq!(frame_progress(&half, &start, &last), 50);
+        assert_eq!(frame_progress(&last, &start, &last), 100);
+
+        // If the first and last frames are identical, every frame is complete.
+        assert_eq!(frame_progress(&half, &start, &start), 100);
+
+        // Colours within the slop are considered to match.
+        let almost: RgbImage = ImageBuffer::from_pixel(WIDTH, HEIGHT, Rgb([4, 4, 4]));
+        assert_eq!(
+            frame_progress(&Histogram::from_image(&almost), &start, &last),
+            100
+        );
+    }
+
+    #[test]
+    fn test_speed_index() {
+        assert_eq!(speed_index(&[]), 0);
+        assert_eq!(speed_index(&[(0, 0)]), 0);
+        assert_eq!(speed_index(&[(0, 0), (100, 100)]), 100);
+        assert_eq!(speed_index(&[(0, 0), (100, 50), (300, 100)]), 200);
+    }
+
+    #[test]
+    fn test_ssim() {
+        let white = grayscale(&partial_frame(0));
+        let half = grayscale(&partial_frame(50));
+        let black = grayscale(&partial_frame(100));
+
+        assert!((ssim(&white, &white) - 1.0).abs() < 1e-9);
+        assert!((ssim(&half, &half) - 1.0).abs() < 1e-9);
+        assert!(ssim(&white, &black) < 0.01);
+
+        let s = ssim(&half, &black);
+        assert!(s > 0.4 && s < 0.6, "ssim = {}", s);
+    }
+
+    #[test]
+    fn test_contentfulness() {
+        assert_eq!(contentfulness(&grayscale(&partial_frame(0))), 0);
+        assert_eq!(contentfulness(&grayscale(&partial_frame(100))), 0);
+
+        // The rows on either side of the boundary between black and white
+        // are edges.
+        assert_eq!(
+            contentfulness(&grayscale(&partial_frame(50))),
+            2 * (WIDTH as u64 - 2)
+        );
+    }
+
+    #[test]
+    fn test_frame_thumbnail() {
+        let large = Frame::new(0, &ImageBuffer::from_pixel(1280, 720, Rgb([0, 0, 0])));
+        assert_eq!(large.thumbnail.dimensions(), (THUMBNAIL_WIDTH, 180));
+
+        let small = frame(0, 0);
+        assert_eq!(small.thumbnail.dimensions(), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn test_compute_frame_metrics() {
+        assert_eq!(compute_frame_metrics(&[]), None);
+
+        assert_eq!(
+            compute_frame_metrics(&[frame(500, 0)]),
+            Some(FrameMetrics {
+                first_visual_change: 0,
+                last_visual_change: 0,
+                speed_index: 0,
+                perceptual_speed_index: 0,
+                contentful_speed_index: 0,
+                visual_progress: vec![(0, 100)],
+            })
+        );
+
+        assert_eq!(
+            compute_frame_metrics(&[
+                frame(500, 0),
+                frame(600, 50),
+                frame(700, 75),
+                frame(1000, 100),
+            ]),
+            Some(FrameMetrics {
+                first_visual_change: 100,
+                last_visual_change: 500,
+                speed_index: 100 + 50 + 75,
+                // The first frame is perceptually incomplete and the other
+                // frames are partially similar to the last frame.
+                perceptual_speed_index: 246,
+                // The last frame has no edges, so only the first frame has
+                // no content relative to the other frames.
+                contentful_speed_index: 100,
+                visual_progress: vec![(0, 0), (100, 50), (200, 75), (500, 100)],
+            })
+        );
+    }
+}
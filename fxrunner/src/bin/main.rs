@@ -3,24 +3,34 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::error::Error;
-use std::io;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::process::exit;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use libfxrecord::config::read_config;
 use libfxrecord::logging::build_file_logger;
+use libfxrunner::cache::BuildCache;
 use libfxrunner::config::Config;
-use libfxrunner::osapi::{WindowsPerfProvider, WindowsShutdownProvider};
+#[cfg(not(windows))]
+use libfxrunner::osapi::ProcfsPerfProvider;
+#[cfg(windows)]
+use libfxrunner::osapi::WindowsPerfProvider;
+use libfxrunner::osapi::WindowsShutdownProvider;
 use libfxrunner::proto::RunnerProto;
+use libfxrunner::registry::SessionRegistry;
 use libfxrunner::session::DefaultSessionManager;
 use libfxrunner::splash::WindowsSplash;
 use libfxrunner::taskcluster::FirefoxCi;
-use slog::{error, info, warn, Logger};
+use slog::{error, info, Logger};
 use structopt::StructOpt;
 use tokio::fs::create_dir_all;
 use tokio::net::TcpListener;
-use tokio::time::delay_for;
+use tokio::task::spawn_blocking;
+use tokio::time::{delay_for, timeout};
+
+/// How often to check for queued jobs that can be started while waiting for a
+/// connection.
+const NEXT_JOB_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fxrunner", about = "Start FxRunner")]
@@ -74,6 +84,7 @@ async fn main() {
 }
 
 async fn fxrunner(log: Logger, options: Options) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
     let config: Config = read_config(&options.config_path, "fxrunner")?;
 
     if let Err(e) = create_dir_all(&config.session_dir).await {
@@ -87,13 +98,29 @@ async fn fxrunner(log: Logger, options: Options) -> Result<(), Box<dyn Error>> {
         return Err(e.into());
     }
 
+    let firefox_ci = FirefoxCi::new(&config.taskcluster)?;
+    let registry = SessionRegistry::new(config.session_dir.clone(), config.session_ttl());
+
+    // Sessions may have expired while the runner was down.
+    reap_sessions(log.clone(), &registry).await;
+
     loop {
         let mut listener = TcpListener::bind(&config.host).await?;
 
         loop {
+            // Queued jobs are started whenever the runner is not busy with
+            // another session, which is checked after every request and
+            // periodically while idle.
+            if start_next_job(log.clone(), &config, &options, &firefox_ci, started).await {
+                break;
+            }
+
             info!(log, "Waiting for connection...");
 
-            let (stream, addr) = listener.accept().await?;
+            let (stream, addr) = match timeout(NEXT_JOB_INTERVAL, listener.accept()).await {
+                Ok(result) => result?,
+                Err(_) => continue,
+            };
             info!(log, "Received connection"; "peer" => addr);
 
             let result = RunnerProto::<_, _, _, _, WindowsSplash>::handle_request(
@@ -101,9 +128,17 @@ async fn fxrunner(log: Logger, options: Options) -> Result<(), Box<dyn Error>> {
                 config.display_size,
                 stream,
                 shutdown_provider(&options),
-                FirefoxCi::default(),
-                WindowsPerfProvider::default(),
-                DefaultSessionManager::new(log.clone(), &config.session_dir),
+                firefox_ci.clone(),
+                perf_provider(&config),
+                config.idle.clone(),
+                DefaultSessionManager::new(log.clone(), &config.session_dir, config.session_ttl()),
+                config
+                    .cache
+                    .as_ref()
+                    .map(|cache| BuildCache::new(cache.dir.clone(), cache.max_size())),
+                config.extract_limits.clone(),
+                config.runner_build_dir.clone(),
+                started,
             )
             .await;
 
@@ -120,15 +155,13 @@ async fn fxrunner(log: Logger, options: Options) -> Result<(), Box<dyn Error>> {
 
             info!(log, "Client disconnected");
 
-            // We aren't restarting, which means we handled a resume request. We
-            // only expect a single pending request at a time, so the request
-            // directory *should* be empty. If it isn't, then isn't empty it.
-            if let Err(e) = cleanup_session_dir(log.clone(), &config.session_dir).await {
-                error!(log, "Could not cleanup session directory"; "error" => %e);
-            }
+            // We aren't restarting, so the request may have finished or
+            // abandoned a session. Sessions that can still be resumed are kept
+            // until they expire.
+            reap_sessions(log.clone(), &registry).await;
         }
 
-        info!(log, "Client disconnected for restart");
+        info!(log, "Restarting");
         drop(listener);
 
         if options.skip_restart() {
@@ -159,23 +192,69 @@ fn shutdown_provider(_: &Options) -> WindowsShutdownProvider {
     WindowsShutdownProvider::default()
 }
 
-async fn cleanup_session_dir(log: slog::Logger, path: &Path) -> Result<(), io::Error> {
-    info!(log, "Cleaning session directory...");
-
-    let mut entries = tokio::fs::read_dir(path).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if let Err(e) = tokio::fs::remove_dir_all(&path).await {
-            error!(
-                log,
-                "Could not remove session directory";
-                "path" => path.display(),
-                "error" => %e,
-            );
-        } else {
-            warn!(log, "Deleted session"; "path" => path.display());
+#[cfg(windows)]
+fn perf_provider(_: &Config) -> WindowsPerfProvider {
+    WindowsPerfProvider::default()
+}
+
+#[cfg(not(windows))]
+fn perf_provider(config: &Config) -> ProcfsPerfProvider {
+    ProcfsPerfProvider::new(config.disk_devices.clone())
+}
+
+/// Start the next queued job, if any.
+///
+/// Returns whether or not the runner is restarting.
+async fn start_next_job(
+    log: Logger,
+    config: &Config,
+    options: &Options,
+    firefox_ci: &FirefoxCi,
+    started: Instant,
+) -> bool {
+    let result = RunnerProto::<_, _, _, _, WindowsSplash>::start_next_job(
+        log.clone(),
+        config.display_size,
+        shutdown_provider(options),
+        firefox_ci.clone(),
+        perf_provider(config),
+        config.idle.clone(),
+        DefaultSessionManager::new(log.clone(), &config.session_dir, config.session_ttl()),
+        config
+            .cache
+            .as_ref()
+            .map(|cache| BuildCache::new(cache.dir.clone(), cache.max_size())),
+        config.extract_limits.clone(),
+        config.runner_build_dir.clone(),
+        started,
+    )
+    .await;
+
+    match result {
+        Ok(restart) => restart,
+        Err(e) => {
+            error!(log, "Could not start queued job"; "error" => %e);
+            false
         }
     }
+}
+
+async fn reap_sessions(log: Logger, registry: &SessionRegistry) {
+    info!(log, "Reaping expired sessions...");
 
-    Ok(())
+    let result = spawn_blocking({
+        let registry = registry.clone();
+        move || registry.reap()
+    })
+    .await
+    .expect("registry task was cancelled or panicked");
+
+    match result {
+        Ok(reaped) => {
+            for session_id in reaped {
+                info!(log, "Reaped session"; "session_id" => session_id);
+            }
+        }
+        Err(e) => error!(log, "Could not reap sessions"; "error" => %e),
+    }
 }
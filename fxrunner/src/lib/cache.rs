@@ -0,0 +1,307 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::fs::write_atomic;
+
+/// The name of the file that tracks the entries in the cache.
+const INDEX_NAME: &str = "index.json";
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("IO error: {}", .0)]
+    Io(#[from] io::Error),
+
+    #[error("could not read or write cache index: {}", .0)]
+    Index(#[from] serde_json::Error),
+}
+
+/// A cache of downloaded build artifacts.
+///
+/// Artifacts are stored under a key derived from their task ID and artifact
+/// name. When the cache grows beyond its size limit, the least recently used
+/// artifacts are evicted.
+#[derive(Clone, Debug)]
+pub struct BuildCache {
+    /// The directory containing the cached artifacts.
+    dir: PathBuf,
+
+    /// The maximum size of all cached artifacts, in bytes.
+    max_size: u64,
+}
+
+/// The index of the artifacts in the cache.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheIndex {
+    /// A counter that is incremented every time an entry is used.
+    clock: u64,
+
+    /// The cached artifacts, by key.
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// An artifact in the cache.
+#[derive(Debug, Deserialize, Serialize)]
+struct CacheEntry {
+    task_id: String,
+    artifact_name: String,
+    size: u64,
+
+    /// The value of the index clock when this entry was last used.
+    last_used: u64,
+}
+
+impl CacheIndex {
+    fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = self.clock;
+        }
+    }
+
+    /// Remove the least recently used entry from the index, returning its key.
+    fn pop_lru(&mut self) -> Option<String> {
+        let key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())?;
+
+        self.entries.remove(&key);
+        Some(key)
+    }
+}
+
+impl BuildCache {
+    /// Create a cache in the given directory that holds at most `max_size`
+    /// bytes of artifacts.
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        BuildCache { dir, max_size }
+    }
+
+    /// The key for the given artifact of the given task.
+    pub fn key(task_id: &str, artifact_name: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(task_id.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(artifact_name.as_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Place the cached artifact at `dest`, if it is cached.
+    ///
+    /// The artifact is hard-linked if possible and copied otherwise.
+    ///
+    /// Returns whether or not the artifact was cached.
+    pub fn retrieve(
+        &self,
+        task_id: &str,
+        artifact_name: &str,
+        dest: &Path,
+    ) -> Result<bool, CacheError> {
+        let key = Self::key(task_id, artifact_name);
+        let mut index = self.read_index()?;
+
+        if !index.entries.contains_key(&key) {
+            return Ok(false);
+        }
+
+        let path = self.dir.join(&key);
+        if !path.is_file() {
+            // The artifact was removed from underneath us.
+            index.entries.remove(&key);
+            self.write_index(&index)?;
+            return Ok(false);
+        }
+
+        if fs::hard_link(&path, dest).is_err() {
+            fs::copy(&path, dest)?;
+        }
+
+        index.touch(&key);
+        self.write_index(&index)?;
+
+        Ok(true)
+    }
+
+    /// Add the artifact at `path` to the cache.
+    ///
+    /// Least recently used artifacts are evicted to make room. Artifacts larger
+    /// than the cache are not cached.
+    pub fn insert(
+        &self,
+        task_id: &str,
+        artifact_name: &str,
+        path: &Path,
+    ) -> Result<(), CacheError> {
+        let size = fs::metadata(path)?.len();
+        if size > self.max_size {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)?;
+
+        let key = Self::key(task_id, artifact_name);
+        let mut index = self.read_index()?;
+        index.entries.remove(&key);
+
+        while index.total_size() + size > self.max_size {
+            match index.pop_lru() {
+                Some(evicted) => remove_if_exists(&self.dir.join(evicted))?,
+                None => break,
+            }
+        }
+
+        let cached_path = self.dir.join(&key);
+        remove_if_exists(&cached_path)?;
+        if fs::hard_link(path, &cached_path).is_err() {
+            fs::copy(path, &cached_path)?;
+        }
+
+        index.entries.insert(
+            key.clone(),
+            CacheEntry {
+                task_id: task_id.into(),
+                artifact_name: artifact_name.into(),
+                size,
+                last_used: 0,
+            },
+        );
+        index.touch(&key);
+
+        self.write_index(&index)
+    }
+
+    fn read_index(&self) -> Result<CacheIndex, CacheError> {
+        match File::open(self.dir.join(INDEX_NAME)) {
+            Ok(f) => Ok(serde_json::from_reader(BufReader::new(f))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(CacheIndex::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_index(&self, index: &CacheIndex) -> Result<(), CacheError> {
+        write_atomic(&self.dir.join(INDEX_NAME), &serde_json::to_vec(index)?)?;
+
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn artifact(dir: &Path, name: &str, size: usize) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, vec![0u8; size]).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(BuildCache::key("foo", "bar"), BuildCache::key("foo", "bar"));
+        assert_ne!(BuildCache::key("foo", "bar"), BuildCache::key("foo", "baz"));
+        assert_ne!(BuildCache::key("foo", "bar"), BuildCache::key("bar", "bar"));
+        assert_eq!(BuildCache::key("foo", "bar").len(), 64);
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache_dir = TempDir::new().unwrap();
+        let work_dir = TempDir::new().unwrap();
+        let cache = BuildCache::new(cache_dir.path().join("cache"), 100);
+
+        let dest = work_dir.path().join("dest.zip");
+        assert!(!cache.retrieve("foo", "target.zip", &dest).unwrap());
+        assert!(!dest.exists());
+
+        cache
+            .insert(
+                "foo",
+                "target.zip",
+                &artifact(work_dir.path(), "foo.zip", 40),
+            )
+            .unwrap();
+
+        assert!(cache.retrieve("foo", "target.zip", &dest).unwrap());
+        assert_eq!(fs::metadata(&dest).unwrap().len(), 40);
+        assert!(!cache
+            .retrieve("foo", "other.zip", &work_dir.path().join("other.zip"))
+            .unwrap());
+
+        // Artifacts larger than the cache are not cached.
+        cache
+            .insert(
+                "huge",
+                "target.zip",
+                &artifact(work_dir.path(), "huge.zip", 101),
+            )
+            .unwrap();
+        assert!(!cache
+            .retrieve("huge", "target.zip", &work_dir.path().join("huge-dest.zip"))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let cache_dir = TempDir::new().unwrap();
+        let work_dir = TempDir::new().unwrap();
+        let cache = BuildCache::new(cache_dir.path().to_path_buf(), 100);
+
+        for task_id in &["a", "b"] {
+            cache
+                .insert(
+                    task_id,
+                    "target.zip",
+                    &artifact(work_dir.path(), task_id, 40),
+                )
+                .unwrap();
+        }
+
+        // Using `a` makes `b` the least recently used.
+        assert!(cache
+            .retrieve("a", "target.zip", &work_dir.path().join("a-dest"))
+            .unwrap());
+
+        cache
+            .insert("c", "target.zip", &artifact(work_dir.path(), "c", 40))
+            .unwrap();
+
+        assert!(!cache_dir
+            .path()
+            .join(BuildCache::key("b", "target.zip"))
+            .exists());
+
+        for (task_id, cached) in &[("a", true), ("b", false), ("c", true)] {
+            let dest = work_dir.path().join(format!("{}-check", task_id));
+            assert_eq!(
+                cache.retrieve(task_id, "target.zip", &dest).unwrap(),
+                *cached
+            );
+        }
+    }
+}
@@ -2,10 +2,17 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use serde::Deserialize;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::taskcluster::{BUILD_ARTIFACT_NAME, DEFAULT_ROOT_URL};
 
 /// The configuration for FxRunner.
 #[derive(Debug, Deserialize)]
@@ -14,10 +21,281 @@ pub struct Config {
     pub host: SocketAddr,
 
     /// The directory to store session state in.
+    ///
+    /// Directories in here that do not belong to a session are removed.
     pub session_dir: PathBuf,
 
+    /// How long a session may go unused before it expires (in hours).
+    #[serde(default = "default_session_ttl_hours")]
+    pub session_ttl_hours: u64,
+
     /// The size of the display.
     pub display_size: Size,
+
+    /// The policy for deciding when the runner is idle.
+    #[serde(default, deserialize_with = "deserialize_idle_policy")]
+    pub idle: IdlePolicy,
+
+    /// The block devices to monitor while waiting to become idle (e.g.,
+    /// `sda`).
+    ///
+    /// If empty, every disk is monitored. This only applies to runners that
+    /// read `/proc`; Windows runners always monitor `C:`.
+    #[serde(default)]
+    pub disk_devices: Vec<String>,
+
+    /// The cache for downloaded builds.
+    ///
+    /// If not provided, builds are not cached.
+    pub cache: Option<CacheConfig>,
+
+    /// The Taskcluster deployment to download builds from.
+    #[serde(default)]
+    pub taskcluster: TaskclusterConfig,
+
+    /// The limits on extracted builds and profiles.
+    #[serde(default)]
+    pub extract_limits: ExtractLimits,
+
+    /// The directory, such as a shared directory, that recorders may use
+    /// builds from.
+    ///
+    /// If not provided, recorders cannot use builds on the runner.
+    pub runner_build_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// How long a session may go unused before it expires.
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_ttl_hours.saturating_mul(60 * 60))
+    }
+}
+
+fn default_session_ttl_hours() -> u64 {
+    24
+}
+
+/// Deserialize an `IdlePolicy`, rejecting policies that can never be met.
+fn deserialize_idle_policy<'de, D>(deserializer: D) -> Result<IdlePolicy, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let policy = IdlePolicy::deserialize(deserializer)?;
+    policy.validate().map_err(D::Error::custom)?;
+    Ok(policy)
+}
+
+/// Limits on the contents of extracted archives.
+///
+/// These protect the runner from hostile or corrupt archives, such as zip
+/// bombs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ExtractLimits {
+    /// The maximum total size of the extracted files (in megabytes).
+    pub max_size_mb: u64,
+
+    /// The maximum number of entries in an archive.
+    pub max_entries: u64,
+
+    /// The maximum ratio of the total size of the extracted files to the size
+    /// of the archive.
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_size_mb: 8 * 1024,
+            max_entries: 100_000,
+            max_compression_ratio: 1_000,
+        }
+    }
+}
+
+impl ExtractLimits {
+    /// The maximum total size of the extracted files (in bytes).
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// The configuration for the Taskcluster deployment builds are downloaded from.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TaskclusterConfig {
+    /// The root URL of the Taskcluster deployment.
+    pub root_url: String,
+
+    /// The name of the build artifact to download.
+    pub artifact_name: String,
+
+    /// The credentials to use for downloading private artifacts.
+    ///
+    /// If not provided, requests are made anonymously.
+    pub credentials: Option<Credentials>,
+}
+
+impl Default for TaskclusterConfig {
+    fn default() -> Self {
+        TaskclusterConfig {
+            root_url: DEFAULT_ROOT_URL.into(),
+            artifact_name: BUILD_ARTIFACT_NAME.into(),
+            credentials: None,
+        }
+    }
+}
+
+/// Taskcluster client credentials.
+#[derive(Clone, Deserialize)]
+pub struct Credentials {
+    /// The client ID.
+    pub client_id: String,
+
+    /// The access token of the client.
+    pub access_token: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Do not leak the access token into logs.
+        f.debug_struct("Credentials")
+            .field("client_id", &self.client_id)
+            .field("access_token", &"<redacted>")
+            .finish()
+    }
+}
+
+/// The configuration for the build cache.
+#[derive(Debug, Deserialize)]
+pub struct CacheConfig {
+    /// The directory to store cached builds in.
+    pub dir: PathBuf,
+
+    /// The maximum size of all cached builds (in megabytes).
+    pub max_size_mb: u64,
+}
+
+impl CacheConfig {
+    /// The maximum size of all cached builds (in bytes).
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// The policy for deciding when the CPU and disk are idle.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct IdlePolicy {
+    /// The minimum fraction of time the CPU must be idle during a sample
+    /// interval.
+    pub cpu_idle_threshold: f64,
+
+    /// The time between samples (in milliseconds).
+    pub sample_interval_ms: u64,
+
+    /// The number of consecutive idle samples required.
+    pub consecutive_samples: u32,
+
+    /// The maximum time to wait to become idle (in milliseconds).
+    pub timeout_ms: u64,
+
+    /// The disk activity tolerated during a sample interval for devices
+    /// without their own tolerance.
+    pub disk_tolerance: DiskTolerance,
+
+    /// The disk activity tolerated during a sample interval for specific
+    /// devices, by device name.
+    pub device_disk_tolerances: HashMap<String, DiskTolerance>,
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        IdlePolicy {
+            cpu_idle_threshold: 0.95,
+            sample_interval_ms: 500,
+            consecutive_samples: 1,
+            timeout_ms: 15_000,
+            disk_tolerance: DiskTolerance::default(),
+            device_disk_tolerances: HashMap::new(),
+        }
+    }
+}
+
+impl IdlePolicy {
+    /// The time between samples.
+    pub fn sample_interval(&self) -> Duration {
+        Duration::from_millis(self.sample_interval_ms)
+    }
+
+    /// The maximum number of samples to take before timing out.
+    pub fn max_samples(&self) -> u64 {
+        if self.sample_interval_ms == 0 {
+            return 1;
+        }
+
+        (self.timeout_ms / self.sample_interval_ms).max(1)
+    }
+
+    /// The disk activity tolerated for the given device.
+    pub fn disk_tolerance(&self, device: &str) -> DiskTolerance {
+        self.device_disk_tolerances
+            .get(device)
+            .copied()
+            .unwrap_or(self.disk_tolerance)
+    }
+
+    /// Check that the CPU idle threshold is a fraction and that the required
+    /// number of consecutive idle samples can be taken before timing out.
+    pub fn validate(&self) -> Result<(), IdlePolicyError> {
+        if !(0.0..=1.0).contains(&self.cpu_idle_threshold) {
+            return Err(IdlePolicyError::CpuIdleThreshold(self.cpu_idle_threshold));
+        }
+
+        if self.consecutive_samples == 0 {
+            return Err(IdlePolicyError::NoSamples);
+        }
+
+        if u64::from(self.consecutive_samples) > self.max_samples() {
+            return Err(IdlePolicyError::TooManySamples {
+                consecutive_samples: self.consecutive_samples,
+                max_samples: self.max_samples(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// An error in an `IdlePolicy`.
+#[derive(Debug, Error, PartialEq)]
+pub enum IdlePolicyError {
+    #[error("cpu_idle_threshold ({}) must be between 0 and 1", .0)]
+    CpuIdleThreshold(f64),
+
+    #[error("consecutive_samples must be at least 1")]
+    NoSamples,
+
+    #[error(
+        "consecutive_samples ({}) is more than the {} samples that can be taken before timing out",
+        consecutive_samples,
+        max_samples
+    )]
+    TooManySamples {
+        consecutive_samples: u32,
+        max_samples: u64,
+    },
+}
+
+/// The number of disk reads and writes tolerated during a sample interval.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct DiskTolerance {
+    /// The maximum number of reads.
+    pub reads: u32,
+
+    /// The maximum number of writes.
+    pub writes: u32,
 }
 
 /// The size of a video.
@@ -28,3 +306,90 @@ pub struct Size {
     /// The size in the x dimension.
     pub x: u16,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_idle_policy_validate() {
+        assert_eq!(IdlePolicy::default().validate(), Ok(()));
+
+        let policy = IdlePolicy {
+            consecutive_samples: 30,
+            ..Default::default()
+        };
+        assert_eq!(policy.validate(), Ok(()));
+
+        let policy = IdlePolicy {
+            consecutive_samples: 31,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.validate(),
+            Err(IdlePolicyError::TooManySamples {
+                consecutive_samples: 31,
+                max_samples: 30,
+            })
+        );
+
+        let policy = IdlePolicy {
+            consecutive_samples: 0,
+            ..Default::default()
+        };
+        assert_eq!(policy.validate(), Err(IdlePolicyError::NoSamples));
+
+        for &threshold in &[0.0, 1.0] {
+            let policy = IdlePolicy {
+                cpu_idle_threshold: threshold,
+                ..Default::default()
+            };
+            assert_eq!(policy.validate(), Ok(()));
+        }
+
+        for &threshold in &[-0.1, 1.5, f64::INFINITY] {
+            let policy = IdlePolicy {
+                cpu_idle_threshold: threshold,
+                ..Default::default()
+            };
+            assert_eq!(
+                policy.validate(),
+                Err(IdlePolicyError::CpuIdleThreshold(threshold))
+            );
+        }
+
+        // NaN is never equal to itself.
+        let policy = IdlePolicy {
+            cpu_idle_threshold: f64::NAN,
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.validate(),
+            Err(IdlePolicyError::CpuIdleThreshold(threshold)) if threshold.is_nan()
+        ));
+    }
+
+    #[test]
+    fn test_config_idle_policy() {
+        let config = r#"
+            host = "0.0.0.0:8888"
+            session_dir = "sessions"
+            display_size = { x = 1366, y = 768 }
+        "#;
+        assert!(toml::from_str::<Config>(config).is_ok());
+
+        let err =
+            toml::from_str::<Config>(&format!("{}\n[idle]\nconsecutive_samples = 31\n", config))
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("consecutive_samples (31) is more than the 30 samples"));
+
+        let err =
+            toml::from_str::<Config>(&format!("{}\n[idle]\ncpu_idle_threshold = 95.0\n", config))
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("cpu_idle_threshold (95) must be between 0 and 1"));
+    }
+}
@@ -2,8 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::fs::{rename, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
 use async_trait::async_trait;
 use tokio::fs::metadata;
+use tokio::task::spawn_blocking;
 
 /// An extension trait for Path to add async versions of its metadata helpers.
 #[async_trait]
@@ -31,3 +36,54 @@ impl PathExt for std::path::Path {
         }
     }
 }
+
+/// Write the contents of a file so that it is never left partially written if
+/// the runner crashes or loses power.
+///
+/// The contents are written to a temporary file, which is synced to disk before
+/// it replaces the file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    {
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(contents)?;
+        f.sync_all()?;
+    }
+
+    rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// An async version of [`write_atomic`](fn.write_atomic.html).
+pub async fn write_atomic_async(
+    path: PathBuf,
+    contents: impl Into<Vec<u8>>,
+) -> Result<(), io::Error> {
+    let contents = contents.into();
+
+    spawn_blocking(move || write_atomic(&path, &contents))
+        .await
+        .expect("write task was cancelled or panicked")
+}
+
+/// Sync the directory containing the given path so that a rename into it is
+/// durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), io::Error> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+/// Sync the directory containing the given path so that a rename into it is
+/// durable.
+///
+/// Directories cannot be opened as files on Windows, where renames are durable
+/// once they complete.
+#[cfg(not(unix))]
+fn sync_parent_dir(_: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
@@ -2,10 +2,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod cache;
 pub mod config;
 pub mod fs;
 pub mod osapi;
 pub mod proto;
+pub mod registry;
 pub mod session;
 pub mod splash;
 pub mod taskcluster;
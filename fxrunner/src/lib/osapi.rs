@@ -7,18 +7,24 @@
 use std::error::Error;
 use std::fmt::Debug;
 use std::io;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+use libfxrecord::net::{DiskReading, IdleReadings};
 use thiserror::Error;
 use tokio::time::delay_for;
 
+use crate::config::IdlePolicy;
+
 pub mod error;
 pub mod handle;
 mod perf;
 pub mod process;
+mod procfs;
 mod shutdown;
 
 pub use perf::{CpuTimes, IoCounters};
+pub use procfs::ProcfsError;
 
 /// A trait providing the ability to restart the current machine.
 pub trait ShutdownProvider: Debug {
@@ -38,14 +44,29 @@ pub trait PerfProvider: Debug {
     /// The error type returned by [`get_cpu_idle_time()`](trait.PerfProvider.html#method.get_cpu_idle_time).
     type CpuTimeError: Error + 'static;
 
-    /// The number of attempts that [`cpu_and_disk_idle()`](fn.cpu_and_disk_idle.html) will make before timing out.
-    const ATTEMPT_COUNT: usize = 30;
-
     /// Return raw read and write IO counters.
     fn get_disk_io_counters(&self) -> Result<IoCounters, Self::DiskIoError>;
 
+    /// Return raw read and write IO counters for each monitored device, by
+    /// device name.
+    ///
+    /// By default, this reports the counters from
+    /// [`get_disk_io_counters()`](trait.PerfProvider.html#method.get_disk_io_counters)
+    /// as a single unnamed device.
+    fn get_device_io_counters(&self) -> Result<Vec<(String, IoCounters)>, Self::DiskIoError> {
+        Ok(vec![(String::new(), self.get_disk_io_counters()?)])
+    }
+
     /// Return the interval that the cpu was idle since startup (in arbitrary units).
     fn get_cpu_usage_time(&self) -> Result<CpuTimes, Self::CpuTimeError>;
+
+    /// Return the number of bytes available on the disk containing the given
+    /// path, if the provider can determine it.
+    ///
+    /// By default, this returns `None`.
+    fn get_free_disk_space(&self, _path: &Path) -> Result<Option<u64>, Self::DiskIoError> {
+        Ok(None)
+    }
 }
 
 /// A [`ShutdownProvider`](trait.ShutdownProvider.html) that uses the Windows API.
@@ -94,9 +115,69 @@ impl PerfProvider for WindowsPerfProvider {
         perf::get_disk_io_counters()
     }
 
+    fn get_device_io_counters(&self) -> Result<Vec<(String, IoCounters)>, Self::DiskIoError> {
+        Ok(vec![("C:".into(), perf::get_disk_io_counters()?)])
+    }
+
     fn get_cpu_usage_time(&self) -> Result<CpuTimes, Self::CpuTimeError> {
         perf::get_cpu_usage_time()
     }
+
+    fn get_free_disk_space(&self, path: &Path) -> Result<Option<u64>, Self::DiskIoError> {
+        perf::get_free_disk_space(path).map(Some)
+    }
+}
+
+/// A [`PerfProvider`](trait.PerfProvider.html) that reads `/proc/stat` and
+/// `/proc/diskstats`.
+#[derive(Debug)]
+pub struct ProcfsPerfProvider {
+    /// The path that procfs is mounted at.
+    root: PathBuf,
+
+    /// The names of the block devices to monitor (e.g., `sda`).
+    ///
+    /// If empty, all disks except loop and RAM devices are monitored.
+    devices: Vec<String>,
+}
+
+impl Default for ProcfsPerfProvider {
+    fn default() -> Self {
+        ProcfsPerfProvider::new(Vec::new())
+    }
+}
+
+impl ProcfsPerfProvider {
+    /// Create a provider that monitors the given block devices.
+    pub fn new(devices: Vec<String>) -> Self {
+        ProcfsPerfProvider::with_root("/proc", devices)
+    }
+
+    /// Create a provider that reads procfs from the given path instead of
+    /// `/proc`.
+    pub fn with_root(root: impl AsRef<Path>, devices: Vec<String>) -> Self {
+        ProcfsPerfProvider {
+            root: root.as_ref().into(),
+            devices,
+        }
+    }
+}
+
+impl PerfProvider for ProcfsPerfProvider {
+    type DiskIoError = ProcfsError;
+    type CpuTimeError = ProcfsError;
+
+    fn get_disk_io_counters(&self) -> Result<IoCounters, Self::DiskIoError> {
+        procfs::get_disk_io_counters(&self.root, &self.devices)
+    }
+
+    fn get_device_io_counters(&self) -> Result<Vec<(String, IoCounters)>, Self::DiskIoError> {
+        procfs::get_device_io_counters(&self.root, &self.devices)
+    }
+
+    fn get_cpu_usage_time(&self) -> Result<CpuTimes, Self::CpuTimeError> {
+        procfs::get_cpu_usage_time(&self.root)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -114,38 +195,75 @@ where
     CpuTimeError(P::CpuTimeError),
 }
 
-/// Wait for the CPU and disk to become idle.
-pub async fn cpu_and_disk_idle<P>(p: &P) -> Result<(), WaitForIdleError<P>>
+/// Wait for the CPU and disk to become idle according to the given policy.
+///
+/// Returns the readings from the final sample.
+pub async fn cpu_and_disk_idle<P>(
+    p: &P,
+    policy: &IdlePolicy,
+) -> Result<IdleReadings, WaitForIdleError<P>>
 where
     P: PerfProvider,
 {
-    const TARGET_CPU_IDLE_PERCENTAGE: f64 = 0.95;
+    let start = Instant::now();
 
     let mut counters = p
-        .get_disk_io_counters()
+        .get_device_io_counters()
         .map_err(WaitForIdleError::DiskIoError)?;
 
     let mut time = p
         .get_cpu_usage_time()
         .map_err(WaitForIdleError::CpuTimeError)?;
 
-    for _ in 0..P::ATTEMPT_COUNT {
-        delay_for(Duration::from_millis(500)).await;
+    let mut consecutive_samples = 0;
+
+    for sample in 1..=policy.max_samples() {
+        delay_for(policy.sample_interval()).await;
 
         let new_counters = p
-            .get_disk_io_counters()
+            .get_device_io_counters()
             .map_err(WaitForIdleError::DiskIoError)?;
         let new_time = p
             .get_cpu_usage_time()
             .map_err(WaitForIdleError::CpuTimeError)?;
 
-        let delta_reads = new_counters.reads - counters.reads;
-        let delta_writes = new_counters.writes - counters.writes;
-
-        let idle = (new_time.idle - time.idle) as f64 / (new_time.total - time.total) as f64;
-
-        if idle >= TARGET_CPU_IDLE_PERCENTAGE && delta_reads == 0 && delta_writes == 0 {
-            return Ok(());
+        let disks: Vec<DiskReading> = new_counters
+            .iter()
+            .map(|(device, new)| {
+                let old = counters
+                    .iter()
+                    .find(|(d, _)| d == device)
+                    .map(|(_, old)| *old)
+                    .unwrap_or(*new);
+
+                DiskReading {
+                    device: device.clone(),
+                    reads: new.reads.wrapping_sub(old.reads),
+                    writes: new.writes.wrapping_sub(old.writes),
+                }
+            })
+            .collect();
+
+        let cpu_idle = new_time.idle_since(&time);
+
+        let disk_idle = disks.iter().all(|reading| {
+            let tolerance = policy.disk_tolerance(&reading.device);
+            reading.reads <= tolerance.reads && reading.writes <= tolerance.writes
+        });
+
+        if cpu_idle >= policy.cpu_idle_threshold && disk_idle {
+            consecutive_samples += 1;
+
+            if consecutive_samples >= policy.consecutive_samples {
+                return Ok(IdleReadings {
+                    cpu_idle,
+                    disks,
+                    samples: sample as u32,
+                    elapsed_ms: start.elapsed().as_millis() as u64,
+                });
+            }
+        } else {
+            consecutive_samples = 0;
         }
 
         counters = new_counters;
@@ -5,11 +5,15 @@
 use std::convert::TryFrom;
 use std::ffi::CString;
 use std::io;
+use std::iter::once;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
 use std::ptr::null_mut;
 use std::u32;
 
 use thiserror::Error;
 use winapi::shared::minwindef::FILETIME;
+use winapi::shared::ntdef::ULARGE_INTEGER;
 use winapi::um::winioctl::DISK_PERFORMANCE;
 use winapi::um::{fileapi, ioapiset, processthreadsapi, winioctl, winnt};
 
@@ -29,6 +33,9 @@ enum DiskIoErrorKind {
 
     #[error("could not retrieve IO counters for C:\\ drive")]
     IoCounterError,
+
+    #[error("could not retrieve free disk space")]
+    FreeSpaceError,
 }
 
 #[derive(Debug, Error)]
@@ -84,6 +91,23 @@ pub(super) fn get_disk_io_counters() -> Result<IoCounters, DiskIoError> {
     })
 }
 
+/// Return the number of bytes available to the current user on the disk
+/// containing the given path.
+pub(super) fn get_free_disk_space(path: &Path) -> Result<u64, DiskIoError> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(once(0)).collect();
+    let mut free: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+
+    check_nonzero(unsafe {
+        fileapi::GetDiskFreeSpaceExW(path.as_ptr(), &mut free as *mut _, null_mut(), null_mut())
+    })
+    .map_err(|source| DiskIoError {
+        kind: DiskIoErrorKind::FreeSpaceError,
+        source,
+    })?;
+
+    Ok(unsafe { *free.QuadPart() })
+}
+
 /// Information about the idle time of a CPU in an interval.
 #[derive(Debug, Default, Clone, Copy)]
 pub struct CpuTimes {
@@ -93,6 +117,23 @@ pub struct CpuTimes {
     pub total: u64,
 }
 
+impl CpuTimes {
+    /// The fraction of time the CPU was idle between an earlier reading and
+    /// this one.
+    ///
+    /// Counters that go backwards (e.g., iowait on Linux) count as no idle
+    /// time rather than overflowing.
+    pub fn idle_since(&self, earlier: &CpuTimes) -> f64 {
+        let total = self.total.saturating_sub(earlier.total);
+        if total == 0 {
+            return 0.0;
+        }
+
+        let idle = self.idle.saturating_sub(earlier.idle).min(total);
+        idle as f64 / total as f64
+    }
+}
+
 pub(super) fn get_cpu_usage_time() -> Result<CpuTimes, io::Error> {
     let mut idle_time = FILETIME {
         dwLowDateTime: 0,
@@ -0,0 +1,283 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::osapi::perf::{CpuTimes, IoCounters};
+
+#[derive(Debug, Error)]
+pub enum ProcfsError {
+    #[error("could not read `{}': {}", .1.display(), .0)]
+    Read(#[source] io::Error, PathBuf),
+
+    #[error("could not parse `{}'", .0.display())]
+    Parse(PathBuf),
+
+    #[error("block device `{}' not found in `{}'", .0, .1.display())]
+    MissingDevice(String, PathBuf),
+}
+
+/// Read the aggregate CPU times from `/proc/stat` under the given root.
+pub(super) fn get_cpu_usage_time(root: &Path) -> Result<CpuTimes, ProcfsError> {
+    let path = root.join("stat");
+    let contents = read_to_string(&path).map_err(|e| ProcfsError::Read(e, path.clone()))?;
+
+    parse_stat(&contents).ok_or(ProcfsError::Parse(path))
+}
+
+/// Read the IO counters of the given block devices from `/proc/diskstats`
+/// under the given root, summed across devices.
+///
+/// If no devices are given, the counters of every disk except loop and RAM
+/// devices are summed. Partitions are skipped, as their IO is already counted
+/// by their disk.
+pub(super) fn get_disk_io_counters(
+    root: &Path,
+    devices: &[String],
+) -> Result<IoCounters, ProcfsError> {
+    let mut counters = IoCounters::default();
+
+    for (_, device_counters) in get_device_io_counters(root, devices)? {
+        counters.reads = counters.reads.wrapping_add(device_counters.reads);
+        counters.writes = counters.writes.wrapping_add(device_counters.writes);
+    }
+
+    Ok(counters)
+}
+
+/// Read the IO counters of each of the given block devices from
+/// `/proc/diskstats` under the given root.
+///
+/// If no devices are given, every disk except loop and RAM devices is read.
+pub(super) fn get_device_io_counters(
+    root: &Path,
+    devices: &[String],
+) -> Result<Vec<(String, IoCounters)>, ProcfsError> {
+    let path = root.join("diskstats");
+    let contents = read_to_string(&path).map_err(|e| ProcfsError::Read(e, path.clone()))?;
+
+    let stats = parse_diskstats(&contents).ok_or_else(|| ProcfsError::Parse(path.clone()))?;
+
+    // The counters are truncated to match the width of the Windows counters.
+    let counters = |(name, reads, writes): &(String, u64, u64)| {
+        (
+            name.clone(),
+            IoCounters {
+                reads: *reads as u32,
+                writes: *writes as u32,
+            },
+        )
+    };
+
+    if devices.is_empty() {
+        Ok(stats
+            .iter()
+            .filter(|(name, _, _)| !name.starts_with("loop") && !name.starts_with("ram"))
+            .filter(|(name, _, _)| !stats.iter().any(|(disk, _, _)| is_partition_of(name, disk)))
+            .map(counters)
+            .collect())
+    } else {
+        devices
+            .iter()
+            .map(|device| {
+                stats
+                    .iter()
+                    .find(|(name, _, _)| name == device)
+                    .map(counters)
+                    .ok_or_else(|| ProcfsError::MissingDevice(device.clone(), path.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Whether or not the device with the given name is a partition of the given
+/// disk.
+///
+/// Partitions are named after their disk followed by the partition number,
+/// which is separated by a `p` when the disk name ends in a digit (e.g., `sda1`
+/// and `nvme0n1p1`).
+fn is_partition_of(name: &str, disk: &str) -> bool {
+    let number = match name.strip_prefix(disk) {
+        Some(rest) if disk.ends_with(|c: char| c.is_ascii_digit()) => rest.strip_prefix('p'),
+        rest => rest,
+    };
+
+    match number {
+        Some(number) => !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Parse the aggregate `cpu` line of `/proc/stat`.
+///
+/// Idle time includes time spent waiting for IO. Guest time is already
+/// accounted for in user time and so is not included in the total.
+fn parse_stat(contents: &str) -> Option<CpuTimes> {
+    let line = contents
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some("cpu"))?;
+
+    let fields = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>()
+        .ok()?;
+
+    // user, nice, system, and idle are always present.
+    if fields.len() < 4 {
+        return None;
+    }
+
+    let iowait = fields.get(4).copied().unwrap_or(0);
+
+    Some(CpuTimes {
+        idle: fields[3] + iowait,
+        total: fields.iter().sum(),
+    })
+}
+
+/// Parse `/proc/diskstats` into `(device name, reads completed, writes
+/// completed)` triples.
+fn parse_diskstats(contents: &str) -> Option<Vec<(String, u64, u64)>> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            // major, minor, name, reads completed, reads merged, sectors read,
+            // time reading, writes completed, ...
+            if fields.len() < 8 {
+                return None;
+            }
+
+            Some((
+                fields[2].to_owned(),
+                fields[3].parse().ok()?,
+                fields[7].parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::write;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const STAT: &str = "\
+cpu  100 10 50 800 40 5 5 0 20 0
+cpu0 50 5 25 400 20 2 3 0 10 0
+intr 12345
+";
+
+    const DISKSTATS: &str = "\
+   7       0 loop0 50 0 100 10 0 0 0 0 0 10 10
+   8       0 sda 1000 10 20000 500 2000 20 40000 900 0 1000 1400
+   8       1 sda1 900 10 18000 450 1900 20 38000 850 0 900 1300
+ 259       0 nvme0n1 300 0 6000 100 400 0 8000 200 0 200 300
+";
+
+    #[test]
+    fn test_parse_stat() {
+        let times = parse_stat(STAT).unwrap();
+        assert_eq!(times.idle, 840);
+        assert_eq!(times.total, 1010);
+
+        assert!(parse_stat("cpu0 1 2 3 4\n").is_none());
+        assert!(parse_stat("cpu 1 2 x 4\n").is_none());
+        assert!(parse_stat("cpu 1 2\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_stat_iowait_decreasing() {
+        // iowait can go backwards between readings, so the idle time can too.
+        let before = parse_stat("cpu  100 10 50 800 40 5 5 0 20 0\n").unwrap();
+        let after = parse_stat("cpu  150 10 60 810 20 5 5 0 20 0\n").unwrap();
+
+        assert_eq!(before.idle, 840);
+        assert_eq!(after.idle, 830);
+        assert_eq!(after.idle_since(&before), 0.0);
+
+        let after = parse_stat("cpu  100 10 50 900 30 5 5 0 20 0\n").unwrap();
+        assert_eq!(after.idle_since(&before), 1.0);
+
+        assert_eq!(before.idle_since(&before), 0.0);
+    }
+
+    #[test]
+    fn test_is_partition_of() {
+        assert!(is_partition_of("sda1", "sda"));
+        assert!(is_partition_of("sda12", "sda"));
+        assert!(is_partition_of("nvme0n1p1", "nvme0n1"));
+        assert!(is_partition_of("mmcblk0p2", "mmcblk0"));
+
+        assert!(!is_partition_of("sda", "sda"));
+        assert!(!is_partition_of("sdab", "sda"));
+        assert!(!is_partition_of("nvme0n10", "nvme0n1"));
+        assert!(!is_partition_of("nvme0n1p", "nvme0n1"));
+        assert!(!is_partition_of("sdb1", "sda"));
+    }
+
+    #[test]
+    fn test_procfs() {
+        let root = TempDir::new().unwrap();
+        write(root.path().join("stat"), STAT).unwrap();
+        write(root.path().join("diskstats"), DISKSTATS).unwrap();
+
+        let times = get_cpu_usage_time(root.path()).unwrap();
+        assert_eq!(times.idle, 840);
+
+        let counters = get_disk_io_counters(root.path(), &["sda".into()]).unwrap();
+        assert_eq!(counters.reads, 1000);
+        assert_eq!(counters.writes, 2000);
+
+        let counters =
+            get_disk_io_counters(root.path(), &["sda".into(), "nvme0n1".into()]).unwrap();
+        assert_eq!(counters.reads, 1300);
+        assert_eq!(counters.writes, 2400);
+
+        // All disks except loop devices. Partitions are not counted twice.
+        let counters = get_disk_io_counters(root.path(), &[]).unwrap();
+        assert_eq!(counters.reads, 1300);
+        assert_eq!(counters.writes, 2400);
+
+        let devices = get_device_io_counters(root.path(), &[]).unwrap();
+        let names: Vec<&str> = devices.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["sda", "nvme0n1"]);
+        assert_eq!(devices[1].1.reads, 300);
+        assert_eq!(devices[1].1.writes, 400);
+
+        // Partitions can still be monitored explicitly.
+        let counters = get_disk_io_counters(root.path(), &["sda1".into()]).unwrap();
+        assert_eq!(counters.reads, 900);
+        assert_eq!(counters.writes, 1900);
+
+        assert!(matches!(
+            get_disk_io_counters(root.path(), &["sdb".into()]),
+            Err(ProcfsError::MissingDevice(..))
+        ));
+
+        write(root.path().join("diskstats"), "8 0 sda x\n").unwrap();
+        assert!(matches!(
+            get_disk_io_counters(root.path(), &[]),
+            Err(ProcfsError::Parse(..))
+        ));
+
+        let empty = TempDir::new().unwrap();
+        assert!(matches!(
+            get_cpu_usage_time(empty.path()),
+            Err(ProcfsError::Read(..))
+        ));
+    }
+}
@@ -4,42 +4,71 @@
 
 use std::io;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::time::Instant;
 
+use futures::executor::block_on;
+use futures::pin_mut;
 use indoc::indoc;
 use libfxrecord::error::ErrorExt;
 use libfxrecord::net::*;
-use libfxrecord::prefs::write_prefs;
+use libfxrecord::prefs::{write_prefs, PrefValue};
 use scopeguard::{guard, ScopeGuard};
-use slog::{error, info, Logger};
+use sha2::{Digest, Sha256};
+use slog::{error, info, warn, Logger};
 use thiserror::Error;
-use tokio::fs::{create_dir, rename, File, OpenOptions};
+use tokio::fs::{canonicalize, create_dir, metadata, rename, File, OpenOptions};
 use tokio::net::TcpStream;
 use tokio::prelude::*;
 use tokio::process::Command;
+use tokio::select;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver};
 use tokio::task::spawn_blocking;
 
-use crate::config::Size;
+use crate::cache::BuildCache;
+use crate::config::{ExtractLimits, IdlePolicy, Size};
 use crate::fs::PathExt;
 use crate::osapi::process::{child_processes, open_process, terminate_process};
 use crate::osapi::{cpu_and_disk_idle, PerfProvider, ShutdownProvider, WaitForIdleError};
+use crate::registry::{RegistryError, SessionRecord};
 use crate::session::{
-    cleanup_session, NewSessionError, ResumeSessionError, SessionInfo, SessionManager,
+    cleanup_session, NewSessionError, PendingProfile, ResumeSessionError, SessionInfo,
+    SessionManager,
 };
 use crate::splash::Splash;
 use crate::taskcluster::Taskcluster;
-use crate::zip::{unzip, ZipError};
+use crate::zip::{extract, extract_stream, ZipError, ZipStats};
+
+/// The name that identifies a streamed profile in errors.
+const PROFILE_STREAM_NAME: &str = "profile stream";
+
+/// The size of the buffer used to hash a partially received profile.
+const PROFILE_HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The number of chunks of a streamed profile that may be buffered before they
+/// are extracted.
+const PROFILE_STREAM_CHUNKS: usize = 16;
+
+/// The size of the buffer used to receive a sideloaded build.
+const RAW_BUFFER_SIZE: usize = 64 * 1024;
 
 /// The runner side of the protocol.
 pub struct RunnerProto<S, T, P, R, Sp> {
     inner: Option<Proto<RecorderMessage, RunnerMessage, RecorderMessageKind, RunnerMessageKind>>,
     log: Logger,
+    peer: Option<SocketAddr>,
     display_size: Size,
     shutdown_handler: S,
     tc: T,
     perf_provider: P,
+    idle_policy: IdlePolicy,
     session_manager: R,
+    build_cache: Option<BuildCache>,
+    extract_limits: ExtractLimits,
+    runner_build_dir: Option<PathBuf>,
+    started: Instant,
 
     _marker: PhantomData<Sp>,
 }
@@ -53,6 +82,13 @@ where
     Sp: Splash,
 {
     /// Handle a request from the recorder.
+    ///
+    /// `runner_build_dir` is the directory that builds on the runner may be
+    /// used from, if any. `started` is when the runner started, which is used
+    /// to report its uptime.
+    ///
+    /// Returns whether or not the runner is restarting.
+    #[allow(clippy::too_many_arguments)]
     pub async fn handle_request(
         log: Logger,
         display_size: Size,
@@ -60,37 +96,178 @@ where
         shutdown_handler: S,
         tc: T,
         perf_provider: P,
+        idle_policy: IdlePolicy,
         session_manager: R,
+        build_cache: Option<BuildCache>,
+        extract_limits: ExtractLimits,
+        runner_build_dir: Option<PathBuf>,
+        started: Instant,
     ) -> Result<bool, RunnerProtoError<S, T, P>> {
         let mut proto = Self {
+            peer: stream.peer_addr().ok(),
             inner: Some(Proto::new(stream)),
             display_size,
             log,
             shutdown_handler,
             tc,
             perf_provider,
+            idle_policy,
             session_manager,
+            build_cache,
+            extract_limits,
+            runner_build_dir,
+            started,
             _marker: PhantomData,
         };
 
+        proto.handshake().await?;
+
         match proto.recv::<Session>().await? {
             Session::NewSession(req) => {
                 proto.handle_new_session(req).await?;
                 Ok(true)
             }
 
-            Session::ResumeSession(req) => {
-                proto.handle_resume_session(req).await?;
+            Session::ResumeSession(req) => proto.handle_resume_session(req).await,
+
+            Session::ResumeProfile(req) => {
+                proto.handle_resume_profile(req).await?;
+                Ok(true)
+            }
+
+            Session::QuerySession(req) => {
+                proto.handle_query_session(req).await?;
+                Ok(false)
+            }
+
+            Session::ListSessions(..) => {
+                proto.handle_list_sessions().await?;
+                Ok(false)
+            }
+
+            Session::DeleteSession(req) => {
+                proto.handle_delete_session(req).await?;
+                Ok(false)
+            }
+
+            Session::PurgeSessions(..) => {
+                proto.handle_purge_sessions().await?;
+                Ok(false)
+            }
+
+            Session::Status(..) => {
+                proto.handle_status().await?;
+                Ok(false)
+            }
+
+            Session::SubmitJob(req) => {
+                proto.handle_submit_job(req).await?;
                 Ok(false)
             }
         }
     }
 
+    /// Start the next queued job, if any.
+    ///
+    /// No recorder is connected while the job is started. The session of the
+    /// job is set up as it would be for a new session and then the runner
+    /// restarts, after which the recorder that submitted the job resumes the
+    /// session to record it.
+    ///
+    /// Returns whether or not the runner is restarting.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_next_job(
+        log: Logger,
+        display_size: Size,
+        shutdown_handler: S,
+        tc: T,
+        perf_provider: P,
+        idle_policy: IdlePolicy,
+        session_manager: R,
+        build_cache: Option<BuildCache>,
+        extract_limits: ExtractLimits,
+        runner_build_dir: Option<PathBuf>,
+        started: Instant,
+    ) -> Result<bool, RunnerProtoError<S, T, P>> {
+        let session_id = match session_manager.next_job().await? {
+            Some(session_id) => session_id,
+            None => return Ok(false),
+        };
+
+        let mut proto = Self {
+            inner: None,
+            peer: None,
+            display_size,
+            log,
+            shutdown_handler,
+            tc,
+            perf_provider,
+            idle_policy,
+            session_manager,
+            build_cache,
+            extract_limits,
+            runner_build_dir,
+            started,
+            _marker: PhantomData,
+        };
+
+        proto.run_job(&session_id).await?;
+        Ok(true)
+    }
+
+    /// Exchange version information with the recorder.
+    ///
+    /// This must be the first exchange on every connection.
+    async fn handshake(&mut self) -> Result<(), RunnerProtoError<S, T, P>> {
+        let Handshake { version: remote } = self.recv().await?;
+        let local = VersionInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        if !local.is_compatible(&remote) {
+            error!(
+                self.log,
+                "Recorder speaks an incompatible protocol version";
+                "local" => %local,
+                "remote" => %remote,
+            );
+
+            let err = ProtoError::VersionMismatch {
+                local: Box::new(local.clone()),
+                remote: Box::new(remote),
+            };
+
+            self.send(HandshakeResponse {
+                version: local,
+                result: Err(err.into_error_message()),
+            })
+            .await?;
+
+            return Err(err.into());
+        }
+
+        info!(self.log, "Handshake complete"; "recorder" => %remote);
+        self.send(HandshakeResponse {
+            version: local,
+            result: Ok(()),
+        })
+        .await?;
+
+        Ok(())
+    }
+
     /// Handle a request for a new session from the recorder.
     async fn handle_new_session(
         &mut self,
         request: NewSessionRequest,
     ) -> Result<(), RunnerProtoError<S, T, P>> {
+        if request.runs == 0 {
+            let err = RunnerProtoError::NoRuns;
+            self.send(NewSessionResponse {
+                session_id: Err(err.into_error_message()),
+            })
+            .await?;
+            return Err(err);
+        }
+
         let session_info = match self.session_manager.new_session().await {
             Ok(session_info) => session_info,
             Err(e) => {
@@ -104,14 +281,59 @@ where
 
         let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
 
+        if let Err(e) = self
+            .set_session_state(&session_info, SessionState::Created)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(NewSessionResponse {
+                session_id: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        if let Err(e) = session_info.set_remaining_runs(request.runs).await {
+            error!(self.log, "Could not write session run count"; "error" => %e);
+            self.send(NewSessionResponse {
+                session_id: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.runs_path(), e));
+        }
+
+        let owner = self.peer.map(|addr| addr.ip().to_string());
+        let profile_sha256 = request.profile.as_ref().map(|p| p.sha256.clone());
+        self.update_session(&session_info.id, move |record| {
+            record.owner = owner;
+            record.profile_sha256 = profile_sha256;
+        })
+        .await;
+
         self.send(NewSessionResponse {
             session_id: Ok(session_info.id.clone().into_owned()),
         })
         .await?;
 
-        let firefox_bin = self
-            .download_build(&session_info, &request.build_task_id)
-            .await?;
+        let (archive_path, build) = match request.build {
+            BuildSource::TaskId(..) | BuildSource::IndexPath(..) => {
+                let task_id = self.resolve_build(&request.build).await?;
+                let archive_path = self.download_build(&session_info, &task_id).await?;
+                (archive_path, task_id)
+            }
+            BuildSource::Sideload { size } => (
+                self.recv_build(&session_info, size).await?,
+                "sideloaded build".into(),
+            ),
+            BuildSource::RunnerPath(ref path) => {
+                (self.find_runner_build(Path::new(path)).await?, path.clone())
+            }
+        };
+
+        self.update_session(&session_info.id, move |record| record.build = Some(build))
+            .await;
+
+        let firefox_bin = self.extract_build(&session_info, archive_path).await?;
         assert!(firefox_bin.is_file_async().await);
 
         if let Err(e) = self.disable_updates(&session_info).await {
@@ -125,8 +347,55 @@ where
         }
         self.send(DisableUpdates { result: Ok(()) }).await?;
 
-        let profile_path = match request.profile_size {
-            Some(profile_size) => self.recv_profile(&session_info, profile_size).await?,
+        let profile_path = match request.profile {
+            Some(ref profile) => {
+                if profile.transfer == ProfileTransfer::Archive {
+                    // Record enough of the request to finish the session if
+                    // the transfer is interrupted and later resumed.
+                    let pending = PendingProfile {
+                        profile: profile.clone(),
+                        prefs: request.prefs.clone(),
+                    };
+
+                    if let Err(e) = session_info.set_pending_profile(&pending).await {
+                        error!(self.log, "Could not write pending profile"; "error" => %e);
+                        self.send(RecvProfile {
+                            result: Err(e.into_error_message()),
+                        })
+                        .await?;
+                        return Err(RunnerProtoError::io(session_info.pending_profile_path(), e));
+                    }
+                }
+
+                info!(self.log, "Receiving profile...");
+                self.send(RecvProfile {
+                    result: Ok(DownloadStatus::Downloading),
+                })
+                .await?;
+
+                match self.recv_profile(&session_info, profile, 0).await {
+                    Ok(profile_path) => profile_path,
+                    Err(e) => {
+                        if is_disconnect(&e) && profile.transfer == ProfileTransfer::Archive {
+                            warn!(
+                                self.log,
+                                "Profile transfer interrupted; keeping session so that it can be resumed";
+                                "session_id" => %session_info.id,
+                            );
+                            ScopeGuard::into_inner(cleanup);
+
+                            if let Err(e) = self
+                                .set_session_state(&session_info, SessionState::ProfilePending)
+                                .await
+                            {
+                                error!(self.log, "Could not write session state"; "error" => %e);
+                            }
+                        }
+
+                        return Err(e);
+                    }
+                }
+            }
             None => {
                 info!(self.log, "Creating new empty profile");
 
@@ -149,165 +418,1070 @@ where
                 profile_path
             }
         };
-        assert!(profile_path.is_dir_async().await);
 
-        if !request.prefs.is_empty() {
-            let prefs_path = profile_path.join("user.js");
-            let mut f = match OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&prefs_path)
-                .await
-            {
-                Ok(f) => f,
-                Err(e) => {
-                    self.send(WritePrefs {
+        self.finish_new_session(&session_info, &profile_path, request.prefs)
+            .await?;
+
+        drop(ScopeGuard::into_inner(cleanup));
+
+        Ok(())
+    }
+
+    /// Queue a new session as a job at the request of the recorder.
+    async fn handle_submit_job(
+        &mut self,
+        request: NewSessionRequest,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received job"; "build" => %request.build, "runs" => request.runs);
+
+        let err = match request.build {
+            _ if request.runs == 0 => Some(RunnerProtoError::NoRuns),
+            BuildSource::Sideload { .. } => Some(RunnerProtoError::SideloadedJob),
+            _ => None,
+        };
+
+        if let Some(err) = err {
+            self.send(SubmitJobResponse {
+                result: Err(err.into_error_message()),
+            })
+            .await?;
+            return Err(err);
+        }
+
+        let session_info = match self.session_manager.new_session().await {
+            Ok(session_info) => session_info,
+            Err(e) => {
+                self.send(SubmitJobResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(e.into());
+            }
+        };
+
+        let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
+
+        if let Err(e) = session_info.set_job(&request).await {
+            error!(self.log, "Could not write job"; "error" => %e);
+            self.send(SubmitJobResponse {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.job_path(), e));
+        }
+
+        let owner = self.peer.map(|addr| addr.ip().to_string());
+        let profile_sha256 = request.profile.as_ref().map(|p| p.sha256.clone());
+        self.update_session(&session_info.id, move |record| {
+            record.owner = owner;
+            record.profile_sha256 = profile_sha256;
+        })
+        .await;
+
+        // Without a profile, the job is queued before it is reported to the
+        // recorder. Otherwise, it is queued once the profile is received.
+        let profile = match request.profile {
+            Some(ref profile) => {
+                self.send(SubmitJobResponse {
+                    result: Ok(session_info.id.clone().into_owned()),
+                })
+                .await?;
+
+                profile
+            }
+            None => {
+                if let Err(e) = self
+                    .set_session_state(&session_info, SessionState::Queued)
+                    .await
+                {
+                    error!(self.log, "Could not write session state"; "error" => %e);
+                    self.send(SubmitJobResponse {
                         result: Err(e.into_error_message()),
                     })
                     .await?;
-
-                    return Err(e.into());
+                    return Err(RunnerProtoError::io(session_info.state_path(), e));
                 }
-            };
 
-            if let Err(e) = write_prefs(&mut f, request.prefs.into_iter()).await {
-                self.send(WritePrefs {
+                info!(self.log, "Queued job"; "session_id" => %session_info.id);
+                self.send(SubmitJobResponse {
+                    result: Ok(session_info.id.clone().into_owned()),
+                })
+                .await?;
+
+                drop(ScopeGuard::into_inner(cleanup));
+                return Ok(());
+            }
+        };
+
+        // The profile is kept as an archive until the job is started.
+        info!(self.log, "Receiving profile...");
+        self.send(RecvProfile {
+            result: Ok(DownloadStatus::Downloading),
+        })
+        .await?;
+
+        if let Err(e) = self.recv_profile_archive(&session_info, profile, 0).await {
+            error!(self.log, "Could not receive profile"; "error" => %e);
+
+            if !is_disconnect(&e) {
+                self.send(RecvProfile {
                     result: Err(e.into_error_message()),
                 })
                 .await?;
-                return Err(e.into());
             }
+            return Err(e);
         }
 
-        self.send(WritePrefs { result: Ok(()) }).await?;
-
         if let Err(e) = self
-            .shutdown_handler
-            .initiate_restart("fxrunner: restarting for cold Firefox start")
+            .set_session_state(&session_info, SessionState::Queued)
+            .await
         {
-            error!(self.log, "Could not restart"; "error" => %e);
-            self.send(Restarting {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(RecvProfile {
                 result: Err(e.into_error_message()),
             })
             .await?;
-
-            return Err(RunnerProtoError::Shutdown(e));
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
         }
 
-        self.send(Restarting { result: Ok(()) }).await?;
+        info!(self.log, "Queued job"; "session_id" => %session_info.id);
+        self.send(RecvProfile {
+            result: Ok(DownloadStatus::Downloaded),
+        })
+        .await?;
 
         drop(ScopeGuard::into_inner(cleanup));
 
         Ok(())
     }
 
-    /// Resume a session from the recorder.
-    async fn handle_resume_session(
+    /// Start the queued job of the given session.
+    ///
+    /// If the job cannot be started, its session is marked as failed so that
+    /// the recorder that submitted it can find out why.
+    async fn run_job(&mut self, session_id: &str) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Starting job"; "session_id" => session_id);
+
+        let result = match self.session_manager.start_job(session_id).await {
+            Ok(session_info) => {
+                let result = self.prepare_job(&session_info).await;
+
+                if result.is_err() {
+                    if let Err(e) = session_info.set_state(SessionState::Failed).await {
+                        error!(self.log, "Could not write session state"; "error" => %e);
+                    }
+                }
+
+                result
+            }
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(ref e) = result {
+            error!(self.log, "Could not start job"; "session_id" => session_id, "error" => %e);
+
+            let error = e.to_string();
+            self.update_session(session_id, move |record| {
+                record.state = SessionState::Failed;
+                record.error = Some(error);
+            })
+            .await;
+        }
+
+        result
+    }
+
+    /// Set up the session of a queued job and restart.
+    async fn prepare_job(
         &mut self,
-        request: ResumeSessionRequest,
+        session_info: &SessionInfo<'_>,
     ) -> Result<(), RunnerProtoError<S, T, P>> {
-        info!(self.log, "Received resumption request");
+        let job = session_info
+            .job()
+            .await
+            .map_err(|e| RunnerProtoError::io(session_info.job_path(), e))?;
+
+        self.set_session_state(session_info, SessionState::Created)
+            .await
+            .map_err(|e| RunnerProtoError::io(session_info.state_path(), e))?;
+        session_info
+            .set_remaining_runs(job.runs)
+            .await
+            .map_err(|e| RunnerProtoError::io(session_info.runs_path(), e))?;
+
+        let (archive_path, build) = match job.build {
+            BuildSource::TaskId(..) | BuildSource::IndexPath(..) => {
+                let task_id = self.resolve_build(&job.build).await?;
+                let archive_path = self.download_build(session_info, &task_id).await?;
+                (archive_path, task_id)
+            }
+            BuildSource::RunnerPath(ref path) => {
+                (self.find_runner_build(Path::new(path)).await?, path.clone())
+            }
+            BuildSource::Sideload { .. } => return Err(RunnerProtoError::SideloadedJob),
+        };
+
+        self.update_session(&session_info.id, move |record| record.build = Some(build))
+            .await;
+
+        let firefox_bin = self.extract_build(session_info, archive_path).await?;
+        assert!(firefox_bin.is_file_async().await);
+
+        self.disable_updates(session_info).await?;
+
+        let profile_path = if job.profile.is_some() {
+            info!(self.log, "Extracting profile...");
+            let unzip_result = self.extract_profile_archive(session_info).await;
+            self.install_profile(session_info, unzip_result).await?
+        } else {
+            info!(self.log, "Creating new empty profile");
+            self.session_manager
+                .ensure_valid_profile_dir(session_info)
+                .await
+                .map_err(RunnerProtoError::EnsureProfile)?
+        };
+
+        self.finish_new_session(session_info, &profile_path, job.prefs)
+            .await
+    }
+
+    /// Resume the interrupted profile transfer of a new session.
+    async fn handle_resume_profile(
+        &mut self,
+        request: ResumeProfileRequest,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received profile resumption request");
+
+        let session_info = match self
+            .session_manager
+            .resume_profile(&request.session_id)
+            .await
+        {
+            Ok(session_info) => session_info,
+            Err(e) => {
+                self.send(ResumeProfileResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(e.into());
+            }
+        };
+
+        let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
+
+        let pending = match session_info.pending_profile().await {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!(self.log, "Could not read pending profile"; "error" => %e);
+                self.send(ResumeProfileResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(RunnerProtoError::io(session_info.pending_profile_path(), e));
+            }
+        };
+
+        let offset = match metadata(session_info.profile_archive_path()).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                error!(self.log, "Could not read partial profile"; "error" => %e);
+                self.send(ResumeProfileResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(RunnerProtoError::io(session_info.profile_archive_path(), e));
+            }
+        };
+
+        info!(self.log, "Resuming profile transfer"; "offset" => offset, "size" => pending.profile.size);
+        self.send(ResumeProfileResponse { result: Ok(offset) })
+            .await?;
+
+        let profile_path = match self
+            .recv_profile(&session_info, &pending.profile, offset)
+            .await
+        {
+            Ok(profile_path) => profile_path,
+            Err(e) => {
+                if is_disconnect(&e) {
+                    warn!(
+                        self.log,
+                        "Profile transfer interrupted; keeping session so that it can be resumed";
+                        "session_id" => %session_info.id,
+                    );
+                    ScopeGuard::into_inner(cleanup);
+                    self.update_session(&session_info.id, |_| ()).await;
+                }
+
+                return Err(e);
+            }
+        };
+
+        self.finish_new_session(&session_info, &profile_path, pending.prefs)
+            .await?;
+
+        drop(ScopeGuard::into_inner(cleanup));
+
+        Ok(())
+    }
+
+    /// Write prefs to the profile and restart, completing a new session.
+    async fn finish_new_session(
+        &mut self,
+        session_info: &SessionInfo<'_>,
+        profile_path: &Path,
+        prefs: Vec<(String, PrefValue)>,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        assert!(profile_path.is_dir_async().await);
+
+        if let Err(e) = self
+            .set_session_state(session_info, SessionState::ProfileReady)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(WritePrefs {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        if !prefs.is_empty() {
+            let prefs_path = profile_path.join("user.js");
+            let mut f = match OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&prefs_path)
+                .await
+            {
+                Ok(f) => f,
+                Err(e) => {
+                    self.send(WritePrefs {
+                        result: Err(e.into_error_message()),
+                    })
+                    .await?;
+
+                    return Err(RunnerProtoError::io(prefs_path, e));
+                }
+            };
+
+            if let Err(e) = write_prefs(&mut f, prefs.into_iter()).await {
+                self.send(WritePrefs {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(RunnerProtoError::io(prefs_path, e));
+            }
+        }
+
+        if let Err(e) = self
+            .set_session_state(session_info, SessionState::PrefsWritten)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(WritePrefs {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        self.send(WritePrefs { result: Ok(()) }).await?;
+
+        // The state must be written before restarting, as the runner may be
+        // shut down at any point afterwards.
+        if let Err(e) = self
+            .set_session_state(session_info, SessionState::RestartPending)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(Restarting {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        if let Err(e) = self
+            .shutdown_handler
+            .initiate_restart("fxrunner: restarting for cold Firefox start")
+        {
+            error!(self.log, "Could not restart"; "error" => %e);
+            self.send(Restarting {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+
+            return Err(RunnerProtoError::Shutdown(e));
+        }
+
+        self.send(Restarting { result: Ok(()) }).await?;
+
+        Ok(())
+    }
+
+    /// Resume a session from the recorder.
+    ///
+    /// Returns whether or not the runner is restarting for another run in the
+    /// same session.
+    async fn handle_resume_session(
+        &mut self,
+        request: ResumeSessionRequest,
+    ) -> Result<bool, RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received resumption request");
+
+        let session_info = match self
+            .session_manager
+            .resume_session(&request.session_id)
+            .await
+        {
+            Ok(session_info) => session_info,
+            Err(e) => {
+                self.send(ResumeResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(e.into());
+            }
+        };
+
+        let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
+
+        let remaining_runs = match session_info.remaining_runs().await {
+            Ok(remaining_runs) => remaining_runs,
+            Err(e) => {
+                error!(self.log, "Could not read session run count"; "error" => %e);
+                self.send(ResumeResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+                return Err(RunnerProtoError::io(session_info.runs_path(), e));
+            }
+        };
+
+        if let Err(e) = self
+            .set_session_state(&session_info, SessionState::Running)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(ResumeResponse {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        info!(self.log, "Resumed session"; "remaining_runs" => remaining_runs);
+        self.send(ResumeResponse {
+            result: Ok(remaining_runs),
+        })
+        .await?;
+
+        if request.idle == Idle::Wait {
+            info!(self.log, "Waiting to become idle");
+
+            let readings = match cpu_and_disk_idle(&self.perf_provider, &self.idle_policy).await {
+                Ok(readings) => readings,
+                Err(e) => {
+                    error!(self.log, "CPU and disk did not become idle"; "error" => %e);
+                    self.send(WaitForIdle {
+                        result: Err(e.into_error_message()),
+                    })
+                    .await?;
+
+                    return Err(RunnerProtoError::WaitForIdle(e));
+                }
+            };
+            info!(
+                self.log,
+                "Became idle";
+                "cpu_idle" => readings.cpu_idle,
+                "samples" => readings.samples,
+                "elapsed_ms" => readings.elapsed_ms,
+            );
+
+            if let Err(e) = self
+                .session_manager
+                .record_idle(&session_info.id, readings.clone())
+                .await
+            {
+                warn!(self.log, "Could not record idle readings"; "error" => %e);
+            }
+
+            self.send(WaitForIdle {
+                result: Ok(readings),
+            })
+            .await?;
+        }
+
+        self.recv::<StartFirefox>().await?;
+
+        let mut splash = Sp::new(self.display_size.x as u32, self.display_size.y as u32)
+            .await
+            .map_err(RunnerProtoError::Splash)?;
+        let run_firefox_result = self
+            .run_firefox(&session_info.firefox_path(), &session_info.profile_path())
+            .await;
+
+        if let Err(e) = splash.destroy() {
+            error!(self.log, "Could not destroy splash"; "error" => %e);
+
+            self.send(SessionFinished {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+        }
+
+        if let Err(e) = run_firefox_result {
+            return Err(e);
+        }
+
+        self.send(SessionFinished { result: Ok(()) }).await?;
+
+        if remaining_runs <= 1 {
+            // The session is cleaned up, but the state is recorded in case
+            // that fails.
+            if let Err(e) = self
+                .set_session_state(&session_info, SessionState::Finished)
+                .await
+            {
+                error!(self.log, "Could not write session state"; "error" => %e);
+            }
+
+            return Ok(false);
+        }
+
+        if let Err(e) = session_info.set_remaining_runs(remaining_runs - 1).await {
+            error!(self.log, "Could not write session run count"; "error" => %e);
+            self.send(Restarting {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+
+            return Err(RunnerProtoError::io(session_info.runs_path(), e));
+        }
+
+        if let Err(e) = self
+            .set_session_state(&session_info, SessionState::RestartPending)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(Restarting {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
+        if let Err(e) = self
+            .shutdown_handler
+            .initiate_restart("fxrunner: restarting for next cold Firefox start")
+        {
+            error!(self.log, "Could not restart"; "error" => %e);
+            self.send(Restarting {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+
+            return Err(RunnerProtoError::Shutdown(e));
+        }
+
+        self.send(Restarting { result: Ok(()) }).await?;
+
+        drop(ScopeGuard::into_inner(cleanup));
+
+        Ok(true)
+    }
+
+    /// Report the state of an existing session to the recorder.
+    async fn handle_query_session(
+        &mut self,
+        request: QuerySessionRequest,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received session query"; "session_id" => &request.session_id);
+
+        match self
+            .session_manager
+            .query_session(&request.session_id)
+            .await
+        {
+            Ok(summary) => {
+                self.send(QuerySessionResponse {
+                    result: Ok(summary),
+                })
+                .await?;
+
+                Ok(())
+            }
+            Err(e) => {
+                error!(self.log, "Could not read session registry"; "error" => %e);
+                self.send(QuerySessionResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Report all sessions to the recorder.
+    async fn handle_list_sessions(&mut self) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received request to list sessions");
+
+        match self.session_manager.list_sessions().await {
+            Ok(sessions) => {
+                self.send(ListSessionsResponse {
+                    result: Ok(sessions),
+                })
+                .await?;
+
+                Ok(())
+            }
+            Err(e) => {
+                error!(self.log, "Could not read session registry"; "error" => %e);
+                self.send(ListSessionsResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Delete a session at the request of the recorder.
+    async fn handle_delete_session(
+        &mut self,
+        request: DeleteSessionRequest,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received request to delete session"; "session_id" => &request.session_id);
+
+        let err = match self
+            .session_manager
+            .delete_session(&request.session_id)
+            .await
+        {
+            Ok(true) => {
+                warn!(self.log, "Deleted session"; "session_id" => &request.session_id);
+                self.send(DeleteSessionResponse { result: Ok(()) }).await?;
+
+                return Ok(());
+            }
+            Ok(false) => RunnerProtoError::NoSuchSession(request.session_id),
+            Err(e) => {
+                error!(self.log, "Could not delete session"; "error" => %e);
+                e.into()
+            }
+        };
+
+        self.send(DeleteSessionResponse {
+            result: Err(err.into_error_message()),
+        })
+        .await?;
+
+        Err(err)
+    }
+
+    /// Delete all sessions at the request of the recorder.
+    async fn handle_purge_sessions(&mut self) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received request to purge sessions");
+
+        match self.session_manager.purge_sessions().await {
+            Ok(purged) => {
+                for session_id in &purged {
+                    warn!(self.log, "Deleted session"; "session_id" => session_id);
+                }
+
+                self.send(PurgeSessionsResponse { result: Ok(purged) })
+                    .await?;
+
+                Ok(())
+            }
+            Err(e) => {
+                error!(self.log, "Could not purge sessions"; "error" => %e);
+                self.send(PurgeSessionsResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Report the status of the runner to the recorder.
+    async fn handle_status(&mut self) -> Result<(), RunnerProtoError<S, T, P>> {
+        info!(self.log, "Received status request");
+
+        match self.status().await {
+            Ok(status) => {
+                self.send(StatusResponse { result: Ok(status) }).await?;
+
+                Ok(())
+            }
+            Err(e) => {
+                error!(self.log, "Could not determine runner status"; "error" => %e);
+                self.send(StatusResponse {
+                    result: Err(e.into_error_message()),
+                })
+                .await?;
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Determine the status of the runner.
+    async fn status(&self) -> Result<RunnerStatus, RunnerProtoError<S, T, P>> {
+        // The rest of the status is still useful without the free disk space.
+        let free_disk = self
+            .perf_provider
+            .get_free_disk_space(self.session_manager.registry().dir())
+            .unwrap_or_else(|e| {
+                error!(self.log, "Could not determine free disk space"; "error" => %e);
+                None
+            });
+
+        Ok(RunnerStatus {
+            version: VersionInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            uptime_secs: self.started.elapsed().as_secs(),
+            current_session: self.session_manager.current_session().await?,
+            free_disk,
+            last_idle: self.session_manager.last_idle().await?,
+        })
+    }
+
+    /// Persist the state of the session and record it in the registry.
+    async fn set_session_state(
+        &self,
+        session_info: &SessionInfo<'_>,
+        state: SessionState,
+    ) -> Result<(), io::Error> {
+        session_info.set_state(state).await?;
+        self.update_session(&session_info.id, move |record| record.state = state)
+            .await;
+
+        Ok(())
+    }
+
+    /// Update the registry's record of the given session, marking it as used.
+    ///
+    /// Errors from the registry are logged and otherwise ignored.
+    async fn update_session<F>(&self, session_id: &str, f: F)
+    where
+        F: FnOnce(&mut SessionRecord) + Send + 'static,
+    {
+        let result = spawn_blocking({
+            let registry = self.session_manager.registry().clone();
+            let session_id = session_id.to_owned();
+            move || registry.update(&session_id, f)
+        })
+        .await
+        .expect("registry task was cancelled or panicked");
+
+        match result {
+            Ok(true) => {}
+            Ok(false) => warn!(self.log, "Session is not registered"; "session_id" => session_id),
+            Err(e) => warn!(self.log, "Could not update session registry"; "error" => %e),
+        }
+    }
+
+    /// Resolve the requested build to the ID of a build task.
+    async fn resolve_build(
+        &mut self,
+        build: &BuildSource,
+    ) -> Result<String, RunnerProtoError<S, T, P>> {
+        let task_id = match build {
+            BuildSource::TaskId(task_id) => task_id.clone(),
+            BuildSource::IndexPath(index_path) => {
+                info!(self.log, "Resolving build from Taskcluster index"; "index_path" => index_path);
+
+                match self.tc.find_indexed_task(index_path).await {
+                    Ok(task_id) => task_id,
+                    Err(e) => {
+                        error!(self.log, "Could not resolve build"; "error" => %e);
+                        self.send(ResolveBuild {
+                            result: Err(e.into_error_message()),
+                        })
+                        .await?;
+                        return Err(RunnerProtoError::Taskcluster(e));
+                    }
+                }
+            }
+            BuildSource::Sideload { .. } | BuildSource::RunnerPath(..) => {
+                unreachable!("only Taskcluster builds are resolved")
+            }
+        };
+
+        info!(self.log, "Resolved build"; "task_id" => &task_id);
+        self.send(ResolveBuild {
+            result: Ok(task_id.clone()),
+        })
+        .await?;
+
+        Ok(task_id)
+    }
+
+    /// Place the build artifact for the given task at `dest` if it is cached.
+    ///
+    /// Returns whether or not the build was cached. Errors from the cache are
+    /// logged and treated as a cache miss.
+    async fn retrieve_cached_build(&self, task_id: &str, dest: &Path) -> bool {
+        let cache = match self.build_cache {
+            Some(ref cache) => cache.clone(),
+            None => return false,
+        };
+
+        let result = spawn_blocking({
+            let task_id = task_id.to_owned();
+            let artifact_name = self.tc.build_artifact_name().to_owned();
+            let dest = dest.to_owned();
+            move || cache.retrieve(&task_id, &artifact_name, &dest)
+        })
+        .await
+        .expect("cache task was cancelled or panicked");
+
+        result.unwrap_or_else(|e| {
+            warn!(self.log, "Could not retrieve build from cache"; "error" => %e);
+            false
+        })
+    }
+
+    /// Add the downloaded build artifact for the given task to the cache.
+    ///
+    /// Errors from the cache are logged and otherwise ignored.
+    async fn cache_build(&self, task_id: &str, path: &Path) {
+        let cache = match self.build_cache {
+            Some(ref cache) => cache.clone(),
+            None => return,
+        };
+
+        let result = spawn_blocking({
+            let task_id = task_id.to_owned();
+            let artifact_name = self.tc.build_artifact_name().to_owned();
+            let path = path.to_owned();
+            move || cache.insert(&task_id, &artifact_name, &path)
+        })
+        .await
+        .expect("cache task was cancelled or panicked");
+
+        if let Err(e) = result {
+            warn!(self.log, "Could not add build to cache"; "error" => %e);
+        }
+    }
+
+    /// Download a build from taskcluster.
+    ///
+    /// Returns the path to the downloaded build artifact.
+    async fn download_build<'a>(
+        &mut self,
+        session_info: &'a SessionInfo<'a>,
+        task_id: &str,
+    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
+        let cached_path = session_info.path.join("firefox.zip");
+
+        let download_path = if self.retrieve_cached_build(task_id, &cached_path).await {
+            info!(self.log, "Using cached build"; "task_id" => &task_id);
+            self.send(DownloadBuild {
+                result: Ok(DownloadStatus::Cached),
+            })
+            .await?;
+
+            cached_path
+        } else {
+            info!(self.log, "Download build from Taskcluster"; "task_id" => &task_id);
+            self.send(DownloadBuild {
+                result: Ok(DownloadStatus::Downloading),
+            })
+            .await?;
 
-        let session_info = match self
-            .session_manager
-            .resume_session(&request.session_id)
-            .await
-        {
-            Ok(session_info) => session_info,
-            Err(e) => {
-                self.send(ResumeResponse {
-                    result: Err(e.into_error_message()),
-                })
-                .await?;
-                return Err(e.into());
-            }
-        };
+            let (progress_tx, mut progress_rx) = unbounded_channel();
+
+            let download_result = {
+                let download =
+                    self.tc
+                        .download_build_artifact(task_id, &session_info.path, progress_tx);
+                pin_mut!(download);
+
+                // Forward progress to the recorder, if any, until the download
+                // finishes.
+                let mut proto = self.inner.as_mut();
+                loop {
+                    select! {
+                        result = &mut download => break result,
+                        Some(progress) = progress_rx.recv() => {
+                            if let Some(proto) = proto.as_mut() {
+                                proto
+                                    .send(DownloadBuild {
+                                        result: Ok(DownloadStatus::Progress(progress)),
+                                    })
+                                    .await?;
+                            }
+                        }
+                    }
+                }
+            };
 
-        let _cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
+            let download_path = match download_result {
+                Ok(download_path) => download_path,
+                Err(e) => {
+                    error!(self.log, "Could not download build"; "error" => %e);
+                    self.send(DownloadBuild {
+                        result: Err(e.into_error_message()),
+                    })
+                    .await?;
+                    return Err(RunnerProtoError::Taskcluster(e));
+                }
+            };
 
-        self.send(ResumeResponse { result: Ok(()) }).await?;
+            self.send(DownloadBuild {
+                result: Ok(DownloadStatus::Downloaded),
+            })
+            .await?;
 
-        if request.idle == Idle::Wait {
-            info!(self.log, "Waiting to become idle");
+            self.cache_build(task_id, &download_path).await;
 
-            if let Err(e) = cpu_and_disk_idle(&self.perf_provider).await {
-                error!(self.log, "CPU and disk did not become idle"; "error" => %e);
-                self.send(WaitForIdle {
-                    result: Err(e.into_error_message()),
-                })
-                .await?;
+            download_path
+        };
 
-                return Err(RunnerProtoError::WaitForIdle(e));
-            }
-            info!(self.log, "Became idle");
+        Ok(download_path)
+    }
+
+    /// Receive a build archive from the recorder.
+    async fn recv_build(
+        &mut self,
+        session_info: &SessionInfo<'_>,
+        build_size: u64,
+    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
+        info!(self.log, "Receiving build..."; "build_size" => build_size);
+
+        // The recorder sends exactly `build_size` bytes and only once we are
+        // ready for them, so oversized builds can be rejected up front.
+        let limit = self.extract_limits.max_size();
+        if build_size > limit {
+            let err = RunnerProtoError::BuildTooLarge {
+                size: build_size,
+                limit,
+            };
 
-            self.send(WaitForIdle { result: Ok(()) }).await?;
+            error!(self.log, "Could not receive build"; "error" => %err);
+            self.send(DownloadBuild {
+                result: Err(err.into_error_message()),
+            })
+            .await?;
+            return Err(err);
         }
 
-        self.recv::<StartFirefox>().await?;
+        self.send(DownloadBuild {
+            result: Ok(DownloadStatus::Downloading),
+        })
+        .await?;
 
-        let mut splash = Sp::new(self.display_size.x as u32, self.display_size.y as u32).await?;
-        let run_firefox_result = self
-            .run_firefox(&session_info.firefox_path(), &session_info.profile_path())
-            .await;
+        let archive_path = session_info.path.join("firefox.zip");
 
-        if let Err(e) = splash.destroy() {
-            error!(self.log, "Could not destroy splash"; "error" => %e);
+        let mut stream = self.inner.take().unwrap().into_inner();
+        let result = Self::recv_raw(&mut stream, &archive_path, build_size).await;
+        self.inner = Some(Proto::new(stream));
 
-            self.send(SessionFinished {
+        if let Err(e) = result {
+            error!(self.log, "Could not receive build"; "error" => %e);
+            self.send(DownloadBuild {
                 result: Err(e.into_error_message()),
             })
             .await?;
-        }
-
-        if let Err(e) = run_firefox_result {
             return Err(e);
         }
 
-        self.send(SessionFinished { result: Ok(()) }).await?;
-        Ok(())
-    }
-
-    /// Download a build from taskcluster.
-    async fn download_build<'a>(
-        &mut self,
-        session_info: &'a SessionInfo<'a>,
-        task_id: &str,
-    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
-        info!(self.log, "Download build from Taskcluster"; "task_id" => &task_id);
+        info!(self.log, "Build received");
         self.send(DownloadBuild {
-            result: Ok(DownloadStatus::Downloading),
+            result: Ok(DownloadStatus::Downloaded),
         })
         .await?;
 
-        let download_path = match self
-            .tc
-            .download_build_artifact(task_id, &session_info.path)
-            .await
-        {
-            Ok(download_path) => download_path,
-            Err(e) => {
-                error!(self.log, "Could not download build"; "error" => %e);
+        Ok(archive_path)
+    }
+
+    /// Find a build archive at the given path on the runner.
+    async fn find_runner_build(
+        &mut self,
+        path: &Path,
+    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
+        let result = self.check_runner_build(path).await;
+        let build_path = match result {
+            Ok(build_path) => build_path,
+            Err(err) => {
+                error!(self.log, "Could not find build"; "error" => %err);
                 self.send(DownloadBuild {
-                    result: Err(e.into_error_message()),
+                    result: Err(err.into_error_message()),
                 })
                 .await?;
-                return Err(RunnerProtoError::Taskcluster(e));
+
+                return Err(err);
             }
         };
 
+        info!(self.log, "Using build on runner"; "path" => build_path.display());
         self.send(DownloadBuild {
-            result: Ok(DownloadStatus::Downloaded),
+            result: Ok(DownloadStatus::Local),
         })
         .await?;
-        info!(self.log, "Extracting downloaded artifact...");
+
+        Ok(build_path)
+    }
+
+    /// Resolve the path of a build on the runner, ensuring that it is a file
+    /// inside the runner's build directory.
+    async fn check_runner_build(&self, path: &Path) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
+        let build_dir = match self.runner_build_dir {
+            Some(ref build_dir) => build_dir,
+            None => return Err(RunnerProtoError::RunnerBuildsDisabled),
+        };
+
+        let build_dir = canonicalize(build_dir)
+            .await
+            .map_err(|e| RunnerProtoError::io(build_dir, e))?;
+
+        // Resolving the path also resolves any `..` components or links that
+        // would otherwise escape the build directory.
+        let build_path = match canonicalize(path).await {
+            Ok(build_path) => build_path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(RunnerProtoError::MissingBuild(path.into()))
+            }
+            Err(e) => return Err(RunnerProtoError::io(path, e)),
+        };
+
+        if !build_path.starts_with(&build_dir) {
+            return Err(RunnerProtoError::RunnerBuildOutsideDir(path.into()));
+        }
+
+        if !build_path.is_file_async().await {
+            return Err(RunnerProtoError::MissingBuild(path.into()));
+        }
+
+        Ok(build_path)
+    }
+
+    /// Extract the build archive at the given path into the session directory.
+    ///
+    /// Returns the path to the Firefox binary.
+    async fn extract_build(
+        &mut self,
+        session_info: &SessionInfo<'_>,
+        archive_path: PathBuf,
+    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
+        info!(self.log, "Extracting build...");
 
         let unzip_result = spawn_blocking({
             let download_dir = PathBuf::from(&session_info.path);
-            move || unzip(&download_path, &download_dir)
+            let limits = self.extract_limits.clone();
+            move || extract(&archive_path, &download_dir, &limits)
         })
         .await
-        .expect("unzip task was cancelled or panicked");
+        .expect("extract task was cancelled or panicked");
 
         if let Err(e) = unzip_result {
             self.send(DownloadBuild {
@@ -317,7 +1491,7 @@ where
             return Err(e.into());
         }
 
-        let firefox_path = session_info.path.join("firefox").join("firefox.exe");
+        let firefox_path = session_info.firefox_path();
         if !firefox_path.is_file_async().await {
             let err = RunnerProtoError::MissingFirefox;
 
@@ -329,6 +1503,18 @@ where
             return Err(err);
         }
 
+        if let Err(e) = self
+            .set_session_state(session_info, SessionState::BuildExtracted)
+            .await
+        {
+            error!(self.log, "Could not write session state"; "error" => %e);
+            self.send(DownloadBuild {
+                result: Err(e.into_error_message()),
+            })
+            .await?;
+            return Err(RunnerProtoError::io(session_info.state_path(), e));
+        }
+
         info!(self.log, "Extracted build");
         self.send(DownloadBuild {
             result: Ok(DownloadStatus::Extracted),
@@ -369,54 +1555,104 @@ where
         Ok(())
     }
 
-    /// Receive a profile from the recorder.
+    /// Receive a profile from the recorder, starting at the given offset in
+    /// the archive.
+    ///
+    /// If the transfer is interrupted, no error is sent to the recorder.
     async fn recv_profile(
         &mut self,
         session_info: &SessionInfo<'_>,
-        profile_size: u64,
+        profile: &ProfileInfo,
+        offset: u64,
     ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
-        info!(self.log, "Receiving profile...");
-        self.send(RecvProfile {
-            result: Ok(DownloadStatus::Downloading),
-        })
-        .await?;
+        // It is possible that the profile contains a top-level directory, in
+        // which case we don't want to directly extract to
+        // `request_info.path.join("profile")`. Instead, we unzip it to a
+        // temporary directory and then move the top level directory (which may
+        // be the path we extracted it to) to the target profile directory.
+        let unzip_path = session_info.unzipped_profile_path();
 
-        let mut stream = self.inner.take().unwrap().into_inner();
-        let result = Self::recv_profile_raw(&mut stream, &session_info.path, profile_size).await;
-        self.inner = Some(Proto::new(stream));
+        let result = match profile.transfer {
+            ProfileTransfer::Archive => {
+                match self
+                    .recv_profile_archive(session_info, profile, offset)
+                    .await
+                {
+                    Ok(()) => session_info
+                        .clear_pending_profile()
+                        .await
+                        .map(|()| None)
+                        .map_err(|e| RunnerProtoError::io(session_info.pending_profile_path(), e)),
+                    Err(e) => Err(e),
+                }
+            }
+
+            ProfileTransfer::Stream => {
+                assert_eq!(offset, 0);
+                self.recv_profile_stream(&unzip_path, profile)
+                    .await
+                    .map(Some)
+            }
+        };
 
-        let zip_path = match result {
-            Ok(zip_path) => zip_path,
+        let streamed_result = match result {
+            Ok(streamed_result) => streamed_result,
             Err(e) => {
-                self.send(DownloadBuild {
-                    result: Err(e.into_error_message()),
-                })
-                .await?;
+                error!(self.log, "Could not receive profile"; "error" => %e);
+
+                if !is_disconnect(&e) {
+                    self.send(RecvProfile {
+                        result: Err(e.into_error_message()),
+                    })
+                    .await?;
+                }
                 return Err(e);
             }
         };
 
-        info!(self.log, "Profile received; extracting...");
+        if streamed_result.is_some() {
+            info!(self.log, "Profile received");
+        } else {
+            info!(self.log, "Profile received; extracting...");
+        }
         self.send(RecvProfile {
             result: Ok(DownloadStatus::Downloaded),
         })
         .await?;
 
-        // It is possible that the profile contains a top-level directory, in
-        // which case we don't want to directly extract to
-        // `request_info.path.join("profile")`. Instead, we unzip it to a
-        // temporary directory and then move the top level directory (which may
-        // be the path we extracted it to) to the target profile directory.
-        let unzip_path = session_info.path.join("unzipped_profile");
+        let unzip_result = match streamed_result {
+            // The profile was extracted as it was received.
+            Some(unzip_result) => unzip_result,
 
-        let unzip_result = spawn_blocking({
-            let zip_path = zip_path.clone();
-            let unzip_path = unzip_path.clone();
-            move || unzip(&zip_path, &unzip_path)
+            None => self.extract_profile_archive(session_info).await,
+        };
+
+        self.install_profile(session_info, unzip_result).await
+    }
+
+    /// Extract the profile archive in the session directory.
+    async fn extract_profile_archive(
+        &self,
+        session_info: &SessionInfo<'_>,
+    ) -> Result<ZipStats, ZipError> {
+        spawn_blocking({
+            let zip_path = session_info.profile_archive_path();
+            let unzip_path = session_info.unzipped_profile_path();
+            let limits = self.extract_limits.clone();
+            move || extract(&zip_path, &unzip_path, &limits)
         })
         .await
-        .expect("unzip profile task was cancelled or panicked");
+        .expect("extract profile task was cancelled or panicked")
+    }
 
+    /// Move the extracted profile into place in the session directory.
+    ///
+    /// Returns the path to the profile.
+    async fn install_profile(
+        &mut self,
+        session_info: &SessionInfo<'_>,
+        unzip_result: Result<ZipStats, ZipError>,
+    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
         let stats = match unzip_result {
             Ok(stats) => stats,
             Err(e) => {
@@ -442,7 +1678,11 @@ where
             return Err(e);
         }
 
-        let unzipped_profile_dir = stats.top_level_dir.unwrap_or(unzip_path);
+        // The top-level directory is relative to the extracted archive.
+        let unzipped_profile_dir = match stats.top_level_dir {
+            Some(top_level_dir) => session_info.unzipped_profile_path().join(top_level_dir),
+            None => session_info.unzipped_profile_path(),
+        };
         let profile_dir = session_info.path.join("profile");
         if let Err(e) = rename(unzipped_profile_dir, &profile_dir).await {
             error!(self.log, "Could not rename profile directory after extraction"; "error" => %e);
@@ -452,7 +1692,7 @@ where
             })
             .await?;
 
-            return Err(e.into());
+            return Err(RunnerProtoError::io(profile_dir, e));
         }
 
         info!(self.log, "Profile extracted");
@@ -465,18 +1705,177 @@ where
         Ok(profile_dir)
     }
 
-    /// Receive the raw bytes of a profile from the recorder.
-    async fn recv_profile_raw(
+    /// Receive `size` raw bytes from the recorder into the file at `path`.
+    async fn recv_raw(
         stream: &mut TcpStream,
-        download_dir: &Path,
-        profile_size: u64,
-    ) -> Result<PathBuf, RunnerProtoError<S, T, P>> {
-        let zip_path = download_dir.join("profile.zip");
-        let mut f = File::create(&zip_path).await?;
+        path: &Path,
+        size: u64,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        let io_error = |e| RunnerProtoError::io(path, e);
+        let mut f = File::create(path).await.map_err(io_error)?;
+
+        // Errors reading from the stream are the recorder's, whereas errors
+        // writing the file are our own.
+        let mut buf = vec![0u8; RAW_BUFFER_SIZE];
+        let mut received = 0;
+        while received < size {
+            let len = buf.len().min((size - received) as usize);
+            let n = stream
+                .read(&mut buf[..len])
+                .await
+                .map_err(|e| RunnerProtoError::Proto(ProtoError::Io(e)))?;
+            if n == 0 {
+                return Err(RunnerProtoError::Proto(ProtoError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "recorder disconnected before the transfer completed",
+                ))));
+            }
+
+            f.write_all(&buf[..n]).await.map_err(io_error)?;
+            received += n as u64;
+        }
+        f.flush().await.map_err(io_error)?;
+
+        Ok(())
+    }
+
+    /// Receive the profile archive into the session directory, starting at
+    /// the given offset, and verify it.
+    ///
+    /// The part of the archive before the offset must already have been
+    /// received.
+    async fn recv_profile_archive(
+        &mut self,
+        session_info: &SessionInfo<'_>,
+        profile: &ProfileInfo,
+        offset: u64,
+    ) -> Result<(), RunnerProtoError<S, T, P>> {
+        let zip_path = session_info.profile_archive_path();
+        let io_error = |e| RunnerProtoError::io(&zip_path, e);
+        let mut hasher = Sha256::new();
+
+        let mut f = if offset == 0 {
+            File::create(&zip_path).await.map_err(io_error)?
+        } else {
+            // The digest covers the whole archive, including the part that was
+            // received before the transfer was interrupted.
+            let mut f = File::open(&zip_path).await.map_err(io_error)?.take(offset);
+            let mut buf = vec![0u8; PROFILE_HASH_BUFFER_SIZE];
+            loop {
+                let n = f.read(&mut buf).await.map_err(io_error)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+
+            OpenOptions::new()
+                .append(true)
+                .open(&zip_path)
+                .await
+                .map_err(io_error)?
+        };
+
+        let mut received = offset;
+        while received < profile.size {
+            let data = self.recv_profile_chunk(received, profile.size).await?;
+
+            hasher.update(&data);
+            f.write_all(&data).await.map_err(io_error)?;
+            received += data.len() as u64;
+        }
+        f.flush().await.map_err(io_error)?;
+
+        verify_profile_digest(profile, hasher)?;
+
+        Ok(())
+    }
+
+    /// Receive the profile as a tar archive, extracting it into `target` as it
+    /// is received.
+    ///
+    /// Errors receiving or verifying the archive take precedence over errors
+    /// extracting it, as the latter are meaningless if the archive was
+    /// corrupted in transit.
+    async fn recv_profile_stream(
+        &mut self,
+        target: &Path,
+        profile: &ProfileInfo,
+    ) -> Result<Result<ZipStats, ZipError>, RunnerProtoError<S, T, P>> {
+        let (mut tx, rx) = channel(PROFILE_STREAM_CHUNKS);
+
+        let extract_task = spawn_blocking({
+            let target = PathBuf::from(target);
+            let limits = self.extract_limits.clone();
+            let size = profile.size;
+            move || {
+                extract_stream(
+                    Path::new(PROFILE_STREAM_NAME),
+                    ChunkReader::new(rx),
+                    size,
+                    &target,
+                    &limits,
+                )
+            }
+        });
 
-        tokio::io::copy(&mut stream.take(profile_size), &mut f).await?;
+        let mut hasher = Sha256::new();
+        let mut extracting = true;
 
-        Ok(zip_path)
+        let recv_result = async {
+            let mut received = 0;
+            while received < profile.size {
+                let data = self.recv_profile_chunk(received, profile.size).await?;
+
+                received += data.len() as u64;
+                hasher.update(&data);
+
+                // If extraction has already failed, the rest of the archive
+                // still has to be received so that it can be verified.
+                if extracting && tx.send(data).await.is_err() {
+                    extracting = false;
+                }
+            }
+
+            Ok::<_, RunnerProtoError<S, T, P>>(())
+        }
+        .await;
+
+        // Closing the channel signals the end of the archive to the extractor.
+        drop(tx);
+        let extract_result = extract_task
+            .await
+            .expect("extract profile task was cancelled or panicked");
+
+        recv_result?;
+        verify_profile_digest(profile, hasher)?;
+
+        Ok(extract_result)
+    }
+
+    /// Receive the next chunk of the profile archive.
+    ///
+    /// `received` is the number of bytes of the archive received so far and
+    /// `size` is the size of the archive.
+    async fn recv_profile_chunk(
+        &mut self,
+        received: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, RunnerProtoError<S, T, P>> {
+        let ProfileChunk { offset, data } = self.recv().await?;
+
+        if offset != received {
+            return Err(RunnerProtoError::ProfileChunkOffset {
+                expected: received,
+                received: offset,
+            });
+        }
+
+        if received + data.len() as u64 > size {
+            return Err(RunnerProtoError::ProfileTooLarge(size));
+        }
+
+        Ok(data)
     }
 
     /// Run the given Firefox binary with the specified profile.
@@ -518,7 +1917,8 @@ where
         {
             info!(self.log, "opening firefox process...");
             let firefox_launcher_handle =
-                open_process(firefox_launcher.id(), winapi::um::winnt::PROCESS_ALL_ACCESS)?;
+                open_process(firefox_launcher.id(), winapi::um::winnt::PROCESS_ALL_ACCESS)
+                    .map_err(RunnerProtoError::StopFirefox)?;
 
             let mut terminated = false;
 
@@ -526,7 +1926,9 @@ where
             for firefox_main_handle in child_processes(
                 firefox_launcher_handle,
                 winapi::um::winnt::PROCESS_TERMINATE,
-            )? {
+            )
+            .map_err(RunnerProtoError::StopFirefox)?
+            {
                 let firefox_main_handle = match firefox_main_handle {
                     Ok(handle) => handle,
                     Err(e) => {
@@ -568,12 +1970,16 @@ where
 
     /// Send the given message to the runner.
     ///
-    /// If the underlying proto is None, this will panic.
+    /// If there is no recorder connected (i.e., the runner is starting a
+    /// queued job), the message is discarded.
     async fn send<M>(&mut self, m: M) -> Result<(), ProtoError<RecorderMessageKind>>
     where
         for<'de> M: MessageContent<'de, RunnerMessage, RunnerMessageKind>,
     {
-        self.inner.as_mut().unwrap().send(m).await
+        match self.inner.as_mut() {
+            Some(proto) => proto.send(m).await,
+            None => Ok(()),
+        }
     }
 
     /// Receive a given kind of message from the runner.
@@ -597,12 +2003,57 @@ where
     #[error("An empty profile was received")]
     EmptyProfile,
 
-    #[error("No firefox.exe in build artifact")]
+    #[error(
+        "Profile checksum mismatch: expected {}, but received {}",
+        expected,
+        actual
+    )]
+    ProfileChecksum { expected: String, actual: String },
+
+    #[error(
+        "Expected a profile chunk at offset {}, but received one at offset {}",
+        expected,
+        received
+    )]
+    ProfileChunkOffset { expected: u64, received: u64 },
+
+    #[error("Received more than the expected {} bytes of profile", .0)]
+    ProfileTooLarge(u64),
+
+    #[error(
+        "Build is {} bytes, which is more than the limit of {} bytes",
+        size,
+        limit
+    )]
+    BuildTooLarge { size: u64, limit: u64 },
+
+    #[error("No Firefox binary in build artifact")]
     MissingFirefox,
 
+    #[error("No build archive at `{}'", .0.display())]
+    MissingBuild(PathBuf),
+
+    #[error("Builds on the runner are not allowed")]
+    RunnerBuildsDisabled,
+
+    #[error("Build `{}' is not in the runner's build directory", .0.display())]
+    RunnerBuildOutsideDir(PathBuf),
+
+    #[error("A session must have at least one run")]
+    NoRuns,
+
+    #[error("Sideloaded builds cannot be queued")]
+    SideloadedJob,
+
+    #[error("Session `{}' does not exist", .0)]
+    NoSuchSession(String),
+
     #[error(transparent)]
     Proto(#[from] ProtoError<RecorderMessageKind>),
 
+    #[error("I/O error at `{}': {}", path.display(), source)]
+    Io { path: PathBuf, source: io::Error },
+
     #[error(transparent)]
     Shutdown(S::Error),
 
@@ -624,20 +2075,113 @@ where
     #[error(transparent)]
     ResumeSession(#[from] ResumeSessionError),
 
+    #[error(transparent)]
+    Registry(#[from] RegistryError),
+
     #[error(transparent)]
     EnsureProfile(io::Error),
 
     #[error("Could not start Firefox: {}", .0)]
     StartFirefox(#[source] io::Error),
+
+    #[error("Could not stop Firefox: {}", .0)]
+    StopFirefox(#[source] io::Error),
+
+    #[error("Could not display splash: {}", .0)]
+    Splash(#[source] io::Error),
 }
 
-impl<S, T, P> From<io::Error> for RunnerProtoError<S, T, P>
+impl<S, T, P> RunnerProtoError<S, T, P>
 where
     S: ShutdownProvider,
     T: Taskcluster,
     P: PerfProvider,
 {
-    fn from(e: io::Error) -> Self {
-        RunnerProtoError::Proto(ProtoError::Io(e))
+    /// Create an error for a local I/O failure at the given path.
+    fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        RunnerProtoError::Io {
+            path: path.as_ref().to_owned(),
+            source,
+        }
+    }
+}
+
+/// A reader over chunks of bytes received from a channel.
+///
+/// This allows archives received asynchronously to be extracted on a blocking
+/// thread. The reader reaches its end when the channel is closed.
+struct ChunkReader {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChunkReader {
+    fn new(rx: Receiver<Vec<u8>>) -> Self {
+        ChunkReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match block_on(self.rx.recv()) {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Verify that the digest of the received profile archive matches the one
+/// the recorder sent.
+fn verify_profile_digest<S, T, P>(
+    profile: &ProfileInfo,
+    hasher: Sha256,
+) -> Result<(), RunnerProtoError<S, T, P>>
+where
+    S: ShutdownProvider,
+    T: Taskcluster,
+    P: PerfProvider + 'static,
+{
+    let actual = format!("{:x}", hasher.finalize());
+
+    if !actual.eq_ignore_ascii_case(&profile.sha256) {
+        return Err(RunnerProtoError::ProfileChecksum {
+            expected: profile.sha256.clone(),
+            actual,
+        });
     }
+
+    Ok(())
+}
+
+/// Whether or not the error was caused by the recorder disconnecting.
+///
+/// Only errors on the connection count; local I/O errors are reported as
+/// `RunnerProtoError::Io`.
+fn is_disconnect<S, T, P>(e: &RunnerProtoError<S, T, P>) -> bool
+where
+    S: ShutdownProvider,
+    T: Taskcluster,
+    P: PerfProvider + 'static,
+{
+    matches!(
+        e,
+        RunnerProtoError::Proto(ProtoError::Io(..))
+            | RunnerProtoError::Proto(ProtoError::EndOfStream)
+    )
 }
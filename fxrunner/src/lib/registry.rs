@@ -0,0 +1,605 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libfxrecord::net::{IdleReadings, IdleRecord, SessionState, SessionSummary};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::fs::write_atomic;
+
+/// The name of the file that tracks the sessions in the session directory.
+pub const REGISTRY_NAME: &str = "sessions.json";
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("IO error: {}", .0)]
+    Io(#[from] io::Error),
+
+    #[error("could not read or write session registry: {}", .0)]
+    Index(#[from] serde_json::Error),
+}
+
+/// A persistent record of the sessions in the session directory.
+///
+/// Sessions that have not been used for longer than the registry's TTL expire
+/// and are removed by [`reap`](#method.reap), along with any session
+/// directories that the registry does not know about.
+#[derive(Clone, Debug)]
+pub struct SessionRegistry {
+    /// The directory containing the sessions.
+    dir: PathBuf,
+
+    /// How long a session may go unused before it expires.
+    ttl: Duration,
+}
+
+/// The index of the sessions in the registry.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct RegistryIndex {
+    /// The registered sessions, by ID.
+    sessions: HashMap<String, SessionRecord>,
+
+    /// The readings taken the last time the runner became idle.
+    #[serde(default)]
+    last_idle: Option<IdleRecord>,
+}
+
+/// A session in the registry.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionRecord {
+    /// When the session was created (in seconds since the Unix epoch).
+    pub created: u64,
+
+    /// When the session was last used (in seconds since the Unix epoch).
+    pub updated: u64,
+
+    /// The build under test, once it is known.
+    pub build: Option<String>,
+
+    /// The SHA-256 digest of the profile archive, if a profile was sent.
+    pub profile_sha256: Option<String>,
+
+    /// The state of the session.
+    pub state: SessionState,
+
+    /// The address of the recorder that created the session.
+    pub owner: Option<String>,
+
+    /// Why the session failed, if it is `Failed`.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl SessionRecord {
+    fn new(now: u64) -> Self {
+        SessionRecord {
+            created: now,
+            updated: now,
+            build: None,
+            profile_sha256: None,
+            state: SessionState::Created,
+            owner: None,
+            error: None,
+        }
+    }
+
+    /// Summarize the session with the given ID for the recorder.
+    fn summary(&self, session_id: &str, ttl: Duration, size: u64) -> SessionSummary {
+        SessionSummary {
+            session_id: session_id.into(),
+            created: self.created,
+            updated: self.updated,
+            expires: self.updated + ttl.as_secs(),
+            build: self.build.clone(),
+            profile_sha256: self.profile_sha256.clone(),
+            state: self.state,
+            owner: self.owner.clone(),
+            size,
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl SessionRegistry {
+    /// Create a registry for the sessions in the given directory that expire
+    /// after being unused for `ttl`.
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        SessionRegistry { dir, ttl }
+    }
+
+    /// How long a session may go unused before it expires.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// The directory containing the sessions.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Add a newly created session to the registry.
+    pub fn register(&self, session_id: &str) -> Result<(), RegistryError> {
+        let mut index = self.read_index()?;
+        index
+            .sessions
+            .insert(session_id.into(), SessionRecord::new(now()));
+
+        self.write_index(&index)
+    }
+
+    /// Update the record of the given session and mark it as used.
+    ///
+    /// Returns whether or not the session was registered.
+    pub fn update<F>(&self, session_id: &str, f: F) -> Result<bool, RegistryError>
+    where
+        F: FnOnce(&mut SessionRecord),
+    {
+        let mut index = self.read_index()?;
+
+        match index.sessions.get_mut(session_id) {
+            Some(record) => {
+                f(record);
+                record.updated = now();
+            }
+            None => return Ok(false),
+        }
+
+        self.write_index(&index)?;
+        Ok(true)
+    }
+
+    /// Return the record of the given session, if it is registered.
+    pub fn get(&self, session_id: &str) -> Result<Option<SessionRecord>, RegistryError> {
+        Ok(self.read_index()?.sessions.remove(session_id))
+    }
+
+    /// Summarize the given session, if it is registered.
+    pub fn summary(&self, session_id: &str) -> Result<Option<SessionSummary>, RegistryError> {
+        match self.get(session_id)? {
+            Some(record) => {
+                let size = dir_size(&self.dir.join(session_id))?;
+                Ok(Some(record.summary(session_id, self.ttl, size)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Summarize all registered sessions, oldest first.
+    pub fn summaries(&self) -> Result<Vec<SessionSummary>, RegistryError> {
+        let mut summaries = self
+            .read_index()?
+            .sessions
+            .iter()
+            .map(|(session_id, record)| {
+                let size = dir_size(&self.dir.join(session_id))?;
+                Ok(record.summary(session_id, self.ttl, size))
+            })
+            .collect::<Result<Vec<_>, RegistryError>>()?;
+
+        summaries.sort_by(|a, b| {
+            a.created
+                .cmp(&b.created)
+                .then_with(|| a.session_id.cmp(&b.session_id))
+        });
+        Ok(summaries)
+    }
+
+    /// Summarize the most recently used session that is in progress, if any.
+    ///
+    /// Sessions that are queued, finished, or failed are not in progress.
+    pub fn current(&self) -> Result<Option<SessionSummary>, RegistryError> {
+        let index = self.read_index()?;
+
+        match index
+            .sessions
+            .iter()
+            .filter(|(_, record)| {
+                !matches!(
+                    record.state,
+                    SessionState::Queued | SessionState::Finished | SessionState::Failed
+                )
+            })
+            .max_by(|(a_id, a), (b_id, b)| a.updated.cmp(&b.updated).then_with(|| a_id.cmp(b_id)))
+        {
+            Some((session_id, record)) => {
+                let size = dir_size(&self.dir.join(session_id))?;
+                Ok(Some(record.summary(session_id, self.ttl, size)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Return the ID of the job that should be started next, if any.
+    ///
+    /// Jobs are started in the order they were submitted, but only once no
+    /// other session is waiting to run or running.
+    pub fn next_job(&self) -> Result<Option<String>, RegistryError> {
+        let index = self.read_index()?;
+
+        let busy = index.sessions.values().any(|record| {
+            matches!(
+                record.state,
+                SessionState::RestartPending | SessionState::Running
+            )
+        });
+        if busy {
+            return Ok(None);
+        }
+
+        Ok(index
+            .sessions
+            .iter()
+            .filter(|(_, record)| record.state == SessionState::Queued)
+            .min_by(|(a_id, a), (b_id, b)| a.created.cmp(&b.created).then_with(|| a_id.cmp(b_id)))
+            .map(|(session_id, _)| session_id.clone()))
+    }
+
+    /// Record the readings taken when the runner became idle for the given
+    /// session.
+    pub fn record_idle(
+        &self,
+        session_id: &str,
+        readings: IdleReadings,
+    ) -> Result<(), RegistryError> {
+        let mut index = self.read_index()?;
+        index.last_idle = Some(IdleRecord {
+            recorded: now(),
+            session_id: session_id.into(),
+            readings,
+        });
+
+        self.write_index(&index)
+    }
+
+    /// Return the readings taken the last time the runner became idle, if it
+    /// has.
+    ///
+    /// The readings are kept after their session is removed.
+    pub fn last_idle(&self) -> Result<Option<IdleRecord>, RegistryError> {
+        Ok(self.read_index()?.last_idle)
+    }
+
+    /// Remove the given session.
+    ///
+    /// Returns whether or not the session was registered. Only registered
+    /// sessions are removed.
+    pub fn delete(&self, session_id: &str) -> Result<bool, RegistryError> {
+        let mut index = self.read_index()?;
+
+        if index.sessions.remove(session_id).is_none() {
+            return Ok(false);
+        }
+
+        remove_dir_if_exists(&self.dir.join(session_id))?;
+        self.write_index(&index)?;
+        Ok(true)
+    }
+
+    /// Remove every session, registered or not.
+    ///
+    /// Returns the IDs of the removed sessions.
+    pub fn purge(&self) -> Result<Vec<String>, RegistryError> {
+        self.reap_at(u64::MAX)
+    }
+
+    /// Remove expired sessions and session directories that are not
+    /// registered.
+    ///
+    /// Records of sessions whose directories have already been removed are
+    /// dropped.
+    ///
+    /// Returns the IDs of the removed sessions.
+    pub fn reap(&self) -> Result<Vec<String>, RegistryError> {
+        self.reap_at(now())
+    }
+
+    fn reap_at(&self, now: u64) -> Result<Vec<String>, RegistryError> {
+        let mut index = self.read_index()?;
+        let mut reaped = Vec::new();
+
+        let ttl = self.ttl.as_secs();
+        let expired: Vec<String> = index
+            .sessions
+            .iter()
+            .filter(|(id, record)| {
+                record.updated.saturating_add(ttl) <= now || !self.dir.join(id).is_dir()
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for session_id in expired {
+            remove_dir_if_exists(&self.dir.join(&session_id))?;
+            index.sessions.remove(&session_id);
+            reaped.push(session_id);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let session_id = entry.file_name().to_string_lossy().into_owned();
+            if !index.sessions.contains_key(&session_id) {
+                fs::remove_dir_all(entry.path())?;
+                reaped.push(session_id);
+            }
+        }
+
+        self.write_index(&index)?;
+        Ok(reaped)
+    }
+
+    fn read_index(&self) -> Result<RegistryIndex, RegistryError> {
+        match File::open(self.dir.join(REGISTRY_NAME)) {
+            Ok(f) => Ok(serde_json::from_reader(BufReader::new(f))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RegistryIndex::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_index(&self, index: &RegistryIndex) -> Result<(), RegistryError> {
+        write_atomic(&self.dir.join(REGISTRY_NAME), &serde_json::to_vec(index)?)?;
+
+        Ok(())
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The total size of the files in the given directory (in bytes).
+///
+/// Symbolic links are not followed.
+fn dir_size(path: &Path) -> Result<u64, io::Error> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = fs::symlink_metadata(entry.path())?;
+
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn session(registry: &SessionRegistry, session_id: &str) {
+        fs::create_dir(registry.dir.join(session_id)).unwrap();
+        registry.register(session_id).unwrap();
+    }
+
+    #[test]
+    fn test_registry() {
+        let dir = TempDir::new().unwrap();
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+
+        assert_eq!(registry.get("foo").unwrap(), None);
+        assert!(!registry.update("foo", |_| ()).unwrap());
+
+        session(&registry, "foo");
+
+        let record = registry.get("foo").unwrap().unwrap();
+        assert_eq!(record.state, SessionState::Created);
+        assert_eq!(record.build, None);
+
+        assert!(registry
+            .update("foo", |record| {
+                record.build = Some("task_id".into());
+                record.state = SessionState::RestartPending;
+            })
+            .unwrap());
+
+        let record = registry.get("foo").unwrap().unwrap();
+        assert_eq!(record.build.as_deref(), Some("task_id"));
+        assert_eq!(record.state, SessionState::RestartPending);
+
+        fs::create_dir(dir.path().join("foo").join("profile")).unwrap();
+        fs::write(
+            dir.path().join("foo").join("profile").join("prefs.js"),
+            "foo",
+        )
+        .unwrap();
+        fs::write(dir.path().join("foo").join("runs"), "1").unwrap();
+
+        let summary = registry.summary("foo").unwrap().unwrap();
+        assert_eq!(summary.session_id, "foo");
+        assert_eq!(summary.expires, record.updated + 60);
+        assert_eq!(summary.size, 4);
+        assert_eq!(registry.summary("bar").unwrap(), None);
+
+        // The registry persists between instances.
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+        assert_eq!(registry.get("foo").unwrap(), Some(record));
+    }
+
+    #[test]
+    fn test_reap() {
+        let dir = TempDir::new().unwrap();
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+
+        session(&registry, "live");
+        session(&registry, "removed");
+        fs::remove_dir(dir.path().join("removed")).unwrap();
+        fs::create_dir(dir.path().join("orphan")).unwrap();
+
+        let mut reaped = registry.reap().unwrap();
+        reaped.sort();
+        assert_eq!(reaped, vec!["orphan", "removed"]);
+
+        assert!(dir.path().join("live").is_dir());
+        assert!(!dir.path().join("orphan").exists());
+        assert!(dir.path().join(REGISTRY_NAME).is_file());
+        assert!(registry.get("live").unwrap().is_some());
+        assert!(registry.get("removed").unwrap().is_none());
+
+        // Sessions expire once they have gone unused for the TTL.
+        let updated = registry.get("live").unwrap().unwrap().updated;
+        assert!(registry.reap_at(updated + 59).unwrap().is_empty());
+        assert_eq!(registry.reap_at(updated + 60).unwrap(), vec!["live"]);
+
+        assert!(!dir.path().join("live").exists());
+        assert!(registry.get("live").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_current_and_last_idle() {
+        let dir = TempDir::new().unwrap();
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+
+        assert_eq!(registry.current().unwrap(), None);
+        assert_eq!(registry.last_idle().unwrap(), None);
+
+        session(&registry, "foo");
+        session(&registry, "bar");
+        registry
+            .update("foo", |record| record.state = SessionState::Finished)
+            .unwrap();
+
+        assert_eq!(registry.current().unwrap().unwrap().session_id, "bar");
+
+        // Queued and failed jobs are not in progress, even if they were
+        // updated more recently.
+        session(&registry, "queued");
+        registry
+            .update("queued", |record| record.state = SessionState::Queued)
+            .unwrap();
+        session(&registry, "failed");
+        registry
+            .update("failed", |record| record.state = SessionState::Failed)
+            .unwrap();
+
+        assert_eq!(registry.current().unwrap().unwrap().session_id, "bar");
+        assert!(registry.delete("queued").unwrap());
+        assert!(registry.delete("failed").unwrap());
+
+        let readings = IdleReadings {
+            cpu_idle: 0.99,
+            disks: vec![],
+            samples: 3,
+            elapsed_ms: 300,
+        };
+        registry.record_idle("bar", readings.clone()).unwrap();
+
+        // The readings outlive their session.
+        assert!(registry.delete("bar").unwrap());
+        assert_eq!(registry.current().unwrap(), None);
+
+        let last_idle = registry.last_idle().unwrap().unwrap();
+        assert_eq!(last_idle.session_id, "bar");
+        assert_eq!(last_idle.readings, readings);
+
+        registry.purge().unwrap();
+        assert_eq!(registry.last_idle().unwrap(), Some(last_idle));
+    }
+
+    #[test]
+    fn test_next_job() {
+        let dir = TempDir::new().unwrap();
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+        let set_state = |session_id, state| {
+            registry
+                .update(session_id, |record| record.state = state)
+                .unwrap();
+        };
+
+        assert_eq!(registry.next_job().unwrap(), None);
+
+        session(&registry, "foo");
+        session(&registry, "bar");
+        assert_eq!(registry.next_job().unwrap(), None);
+
+        // Jobs are started in the order they were submitted.
+        for (session_id, created) in &[("foo", 2), ("bar", 1)] {
+            registry
+                .update(session_id, |record| {
+                    record.created = *created;
+                    record.state = SessionState::Queued;
+                })
+                .unwrap();
+        }
+        assert_eq!(registry.next_job().unwrap().as_deref(), Some("bar"));
+
+        // Jobs wait for the session that is running to finish.
+        set_state("bar", SessionState::RestartPending);
+        assert_eq!(registry.next_job().unwrap(), None);
+
+        set_state("bar", SessionState::Running);
+        assert_eq!(registry.next_job().unwrap(), None);
+
+        set_state("bar", SessionState::Finished);
+        assert_eq!(registry.next_job().unwrap().as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn test_delete_and_purge() {
+        let dir = TempDir::new().unwrap();
+        let registry = SessionRegistry::new(dir.path().into(), TTL);
+
+        session(&registry, "foo");
+        session(&registry, "bar");
+        session(&registry, "baz");
+        fs::create_dir(dir.path().join("orphan")).unwrap();
+
+        assert!(registry.delete("foo").unwrap());
+        assert!(!dir.path().join("foo").exists());
+        assert!(registry.get("foo").unwrap().is_none());
+
+        // Only registered sessions can be deleted.
+        assert!(!registry.delete("foo").unwrap());
+        assert!(!registry.delete("orphan").unwrap());
+        assert!(dir.path().join("orphan").is_dir());
+
+        let ids: Vec<String> = registry
+            .summaries()
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.session_id)
+            .collect();
+        assert_eq!(ids, vec!["bar", "baz"]);
+
+        let mut purged = registry.purge().unwrap();
+        purged.sort();
+        assert_eq!(purged, vec!["bar", "baz", "orphan"]);
+
+        assert!(registry.summaries().unwrap().is_empty());
+        assert_eq!(
+            fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>(),
+            vec![REGISTRY_NAME]
+        );
+    }
+}
@@ -6,37 +6,152 @@ use std::borrow::Cow;
 use std::io;
 use std::iter;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use async_trait::async_trait;
+use libfxrecord::net::{
+    IdleReadings, IdleRecord, NewSessionRequest, ProfileInfo, SessionState, SessionSummary,
+};
+use libfxrecord::prefs::PrefValue;
 use rand::distributions::Alphanumeric;
 use rand::prelude::*;
 use scopeguard::{guard, ScopeGuard};
-use slog::error;
+use serde::{Deserialize, Serialize};
+use slog::{error, warn};
 use thiserror::Error;
-use tokio::fs::create_dir;
+use tokio::fs::{create_dir, read_to_string, remove_dir, remove_file};
+use tokio::task::spawn_blocking;
 
-use crate::fs::PathExt;
+use crate::fs::{write_atomic_async, PathExt};
+use crate::registry::{RegistryError, SessionRegistry};
 
 const REQUEST_ID_LEN: usize = 32;
 
-#[derive(Clone)]
+/// The names of the Firefox binary in an extracted build, in order of
+/// preference.
+///
+/// Windows builds contain `firefox.exe`, whereas Linux builds (e.g., those
+/// archived as `.tar.bz2` or `.tar.xz`) contain `firefox`.
+const FIREFOX_BINARY_NAMES: &[&str] = &["firefox.exe", "firefox"];
+
+#[derive(Clone, Debug)]
 pub struct SessionInfo<'a> {
     pub id: Cow<'a, str>,
     pub path: PathBuf,
 }
 
 impl<'a> SessionInfo<'a> {
+    /// The path of the Firefox binary in the extracted build.
+    ///
+    /// If the build has not been extracted, this is the path of a Windows
+    /// binary.
     pub fn firefox_path(&self) -> PathBuf {
-        self.path.join("firefox").join("firefox.exe")
+        let firefox_dir = self.path.join("firefox");
+
+        FIREFOX_BINARY_NAMES
+            .iter()
+            .map(|name| firefox_dir.join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| firefox_dir.join(FIREFOX_BINARY_NAMES[0]))
     }
     pub fn profile_path(&self) -> PathBuf {
         self.path.join("profile")
     }
+    pub fn runs_path(&self) -> PathBuf {
+        self.path.join("runs")
+    }
+    pub fn profile_archive_path(&self) -> PathBuf {
+        self.path.join("profile.zip")
+    }
+    pub fn unzipped_profile_path(&self) -> PathBuf {
+        self.path.join("unzipped_profile")
+    }
+    pub fn pending_profile_path(&self) -> PathBuf {
+        self.path.join("pending_profile.json")
+    }
+    pub fn state_path(&self) -> PathBuf {
+        self.path.join("state.json")
+    }
+    pub fn job_path(&self) -> PathBuf {
+        self.path.join("job.json")
+    }
+
+    /// Read the state of the session.
+    pub async fn state(&self) -> Result<SessionState, io::Error> {
+        serde_json::from_str(&read_to_string(self.state_path()).await?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Persist the state of the session.
+    pub async fn set_state(&self, state: SessionState) -> Result<(), io::Error> {
+        let json = serde_json::to_vec(&state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic_async(self.state_path(), json).await
+    }
+
+    /// Read the number of runs remaining in the session, including the
+    /// current run.
+    pub async fn remaining_runs(&self) -> Result<u32, io::Error> {
+        read_to_string(self.runs_path())
+            .await?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Persist the number of runs remaining in the session.
+    pub async fn set_remaining_runs(&self, runs: u32) -> Result<(), io::Error> {
+        write_atomic_async(self.runs_path(), runs.to_string()).await
+    }
+
+    /// Read the profile transfer that is pending for the session.
+    pub async fn pending_profile(&self) -> Result<PendingProfile, io::Error> {
+        serde_json::from_str(&read_to_string(self.pending_profile_path()).await?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Persist the profile transfer that is pending for the session.
+    pub async fn set_pending_profile(&self, pending: &PendingProfile) -> Result<(), io::Error> {
+        let json = serde_json::to_vec(pending)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic_async(self.pending_profile_path(), json).await
+    }
+
+    /// Read the job that the session was submitted as.
+    pub async fn job(&self) -> Result<NewSessionRequest, io::Error> {
+        serde_json::from_str(&read_to_string(self.job_path()).await?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Persist the job that the session was submitted as.
+    pub async fn set_job(&self, job: &NewSessionRequest) -> Result<(), io::Error> {
+        let json =
+            serde_json::to_vec(job).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic_async(self.job_path(), json).await
+    }
+
+    /// Mark the pending profile transfer for the session as complete.
+    pub async fn clear_pending_profile(&self) -> Result<(), io::Error> {
+        remove_file(self.pending_profile_path()).await
+    }
+}
+
+/// A profile transfer for a new session that has not yet completed.
+///
+/// This records enough of the new session request to finish the session if the
+/// transfer is interrupted and later resumed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingProfile {
+    /// The profile being transferred.
+    pub profile: ProfileInfo,
+
+    /// Prefs to override in the profile once it has been received.
+    pub prefs: Vec<(String, PrefValue)>,
 }
 
 /// A trait for creating and validating session.
 #[async_trait]
-pub trait SessionManager {
+pub trait SessionManager: Sync {
     /// Create a new session.
     async fn new_session(&self) -> Result<SessionInfo<'static>, NewSessionError>;
 
@@ -49,26 +164,167 @@ pub trait SessionManager {
         session_id: &'a str,
     ) -> Result<SessionInfo<'a>, ResumeSessionError>;
 
+    /// Attempt to resume the interrupted profile transfer of the session with
+    /// the given ID.
+    ///
+    /// Unlike [`resume_session`](#tymethod.resume_session), the session is not
+    /// cleaned up if it cannot be resumed.
+    async fn resume_profile<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError>;
+
+    /// Attempt to start the queued job of the session with the given ID.
+    ///
+    /// Like [`resume_profile`](#tymethod.resume_profile), the session is not
+    /// cleaned up if it cannot be started.
+    async fn start_job<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError>;
+
     /// Ensure the profile directory for the given request exists and is valid
     /// (i.e., contains everything to do a recordering).
     async fn ensure_valid_profile_dir<'a>(
         &self,
         session_info: &SessionInfo<'a>,
     ) -> Result<PathBuf, io::Error>;
+
+    /// The registry of sessions created by this manager.
+    fn registry(&self) -> &SessionRegistry;
+
+    /// Describe the session with the given ID, if it exists.
+    async fn query_session(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionSummary>, RegistryError> {
+        let registry = self.registry().clone();
+        let session_id = session_id.to_owned();
+
+        spawn_blocking(move || registry.summary(&session_id))
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Describe all sessions, oldest first.
+    async fn list_sessions(&self) -> Result<Vec<SessionSummary>, RegistryError> {
+        let registry = self.registry().clone();
+
+        spawn_blocking(move || registry.summaries())
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Return the ID of the queued job that should be started next, if any.
+    async fn next_job(&self) -> Result<Option<String>, RegistryError> {
+        let registry = self.registry().clone();
+
+        spawn_blocking(move || registry.next_job())
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Describe the most recently used session that has not finished, if any.
+    async fn current_session(&self) -> Result<Option<SessionSummary>, RegistryError> {
+        let registry = self.registry().clone();
+
+        spawn_blocking(move || registry.current())
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Record the readings taken when the runner became idle for the session
+    /// with the given ID.
+    async fn record_idle(
+        &self,
+        session_id: &str,
+        readings: IdleReadings,
+    ) -> Result<(), RegistryError> {
+        let registry = self.registry().clone();
+        let session_id = session_id.to_owned();
+
+        spawn_blocking(move || registry.record_idle(&session_id, readings))
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Return the readings taken the last time the runner became idle, if it
+    /// has.
+    async fn last_idle(&self) -> Result<Option<IdleRecord>, RegistryError> {
+        let registry = self.registry().clone();
+
+        spawn_blocking(move || registry.last_idle())
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Delete the session with the given ID.
+    ///
+    /// Returns whether or not the session existed.
+    async fn delete_session(&self, session_id: &str) -> Result<bool, RegistryError> {
+        let registry = self.registry().clone();
+        let session_id = session_id.to_owned();
+
+        spawn_blocking(move || registry.delete(&session_id))
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
+
+    /// Delete all sessions.
+    ///
+    /// Returns the IDs of the deleted sessions.
+    async fn purge_sessions(&self) -> Result<Vec<String>, RegistryError> {
+        let registry = self.registry().clone();
+
+        spawn_blocking(move || registry.purge())
+            .await
+            .expect("registry task was cancelled or panicked")
+    }
 }
 
 pub struct DefaultSessionManager {
     log: slog::Logger,
     path: PathBuf,
+    registry: SessionRegistry,
 }
 
 impl DefaultSessionManager {
-    pub fn new(log: slog::Logger, path: &Path) -> Self {
+    /// Create a session manager for the sessions in the given directory, which
+    /// expire after being unused for `ttl`.
+    pub fn new(log: slog::Logger, path: &Path, ttl: Duration) -> Self {
         DefaultSessionManager {
             log,
             path: path.into(),
+            registry: SessionRegistry::new(path.into(), ttl),
         }
     }
+
+    /// Find the directory of the existing session with the given ID.
+    async fn existing_session<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError> {
+        if !validate_session_id(session_id) {
+            return Err(ResumeSessionError {
+                kind: ResumeSessionErrorKind::InvalidId,
+                session_id: session_id.into(),
+            });
+        }
+
+        let path = self.path.join(session_id);
+
+        if !path.is_dir_async().await {
+            return Err(ResumeSessionError {
+                kind: ResumeSessionErrorKind::DoesNotExist,
+                session_id: session_id.into(),
+            });
+        }
+
+        Ok(SessionInfo {
+            path,
+            id: Cow::Borrowed(session_id),
+        })
+    }
 }
 
 #[async_trait]
@@ -106,6 +362,22 @@ impl SessionManager for DefaultSessionManager {
                 }
             }
 
+            let result = spawn_blocking({
+                let registry = self.registry.clone();
+                let session_id = session_id.clone();
+                move || registry.register(&session_id)
+            })
+            .await
+            .expect("registry task was cancelled or panicked");
+
+            if let Err(e) = result {
+                // The directory would otherwise be reaped as unknown.
+                if let Err(remove_err) = remove_dir(&path).await {
+                    error!(self.log, "Could not cleanup request"; "session_id" => %session_id, "error" => %remove_err);
+                }
+                return Err(e.into());
+            }
+
             return Ok(SessionInfo {
                 path,
                 id: Cow::Owned(session_id),
@@ -119,32 +391,103 @@ impl SessionManager for DefaultSessionManager {
         &self,
         session_id: &'a str,
     ) -> Result<SessionInfo<'a>, ResumeSessionError> {
-        if !validate_session_id(session_id) {
+        let session_info = self.existing_session(session_id).await?;
+
+        if session_info.pending_profile_path().is_file_async().await {
             return Err(ResumeSessionError {
-                kind: ResumeSessionErrorKind::InvalidId,
+                kind: ResumeSessionErrorKind::PendingProfile,
                 session_id: session_id.into(),
             });
         }
 
-        let path = self.path.join(session_id);
+        let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
 
-        if !path.is_dir_async().await {
+        let state = match session_info.state().await {
+            Ok(state) => state,
+            Err(e) => {
+                error!(self.log, "Could not read session state"; "session_id" => session_id, "error" => %e);
+                return Err(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::MissingState,
+                    session_id: session_id.into(),
+                });
+            }
+        };
+
+        match state {
+            SessionState::RestartPending => {}
+
+            // The runner crashed or lost power during the run. The run count is
+            // only decremented once a run finishes, so the run can be retried.
+            SessionState::Running => {
+                warn!(
+                    self.log,
+                    "Session was interrupted while running; retrying the run";
+                    "session_id" => session_id,
+                );
+            }
+
+            SessionState::Finished => {
+                return Err(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::Finished,
+                    session_id: session_id.into(),
+                });
+            }
+
+            // The job has yet to be started, so the session must be kept.
+            SessionState::Queued => {
+                drop(ScopeGuard::into_inner(cleanup));
+                return Err(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::Queued,
+                    session_id: session_id.into(),
+                });
+            }
+
+            SessionState::Failed => {
+                return Err(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::Failed,
+                    session_id: session_id.into(),
+                });
+            }
+
+            SessionState::Created
+            | SessionState::BuildExtracted
+            | SessionState::ProfilePending
+            | SessionState::ProfileReady
+            | SessionState::PrefsWritten => {
+                return Err(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::Incomplete(state),
+                    session_id: session_id.into(),
+                });
+            }
+        }
+
+        if !session_info.profile_path().is_dir_async().await {
             return Err(ResumeSessionError {
-                kind: ResumeSessionErrorKind::DoesNotExist,
+                kind: ResumeSessionErrorKind::MissingProfile,
                 session_id: session_id.into(),
             });
         }
 
-        let session_info = SessionInfo {
-            path,
-            id: Cow::Borrowed(session_id),
-        };
+        if !session_info.firefox_path().is_file_async().await {
+            return Err(ResumeSessionError {
+                kind: ResumeSessionErrorKind::MissingFirefox,
+                session_id: session_id.into(),
+            });
+        }
 
-        let cleanup = guard(self.log.clone(), |log| cleanup_session(log, &session_info));
+        drop(ScopeGuard::into_inner(cleanup));
+        Ok(session_info)
+    }
 
-        if !session_info.profile_path().is_dir_async().await {
+    async fn resume_profile<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError> {
+        let session_info = self.existing_session(session_id).await?;
+
+        if !session_info.pending_profile_path().is_file_async().await {
             return Err(ResumeSessionError {
-                kind: ResumeSessionErrorKind::MissingProfile,
+                kind: ResumeSessionErrorKind::NoPendingProfile,
                 session_id: session_id.into(),
             });
         }
@@ -156,10 +499,30 @@ impl SessionManager for DefaultSessionManager {
             });
         }
 
-        drop(ScopeGuard::into_inner(cleanup));
         Ok(session_info)
     }
 
+    async fn start_job<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError> {
+        let session_info = self.existing_session(session_id).await?;
+
+        let kind = match session_info.state().await {
+            Ok(SessionState::Queued) => return Ok(session_info),
+            Ok(state) => ResumeSessionErrorKind::NotQueued(state),
+            Err(e) => {
+                error!(self.log, "Could not read session state"; "session_id" => session_id, "error" => %e);
+                ResumeSessionErrorKind::MissingState
+            }
+        };
+
+        Err(ResumeSessionError {
+            kind,
+            session_id: session_id.into(),
+        })
+    }
+
     async fn ensure_valid_profile_dir<'a>(
         &self,
         session_info: &SessionInfo<'a>,
@@ -168,6 +531,10 @@ impl SessionManager for DefaultSessionManager {
         create_dir(&profile_path).await?;
         Ok(profile_path)
     }
+
+    fn registry(&self) -> &SessionRegistry {
+        &self.registry
+    }
 }
 
 #[derive(Clone, Debug, Eq, Error, PartialEq)]
@@ -183,6 +550,30 @@ pub enum ResumeSessionErrorKind {
 
     #[error("missing a Firefox binary")]
     MissingFirefox,
+
+    #[error("has an interrupted profile transfer")]
+    PendingProfile,
+
+    #[error("has no interrupted profile transfer")]
+    NoPendingProfile,
+
+    #[error("has no recorded state")]
+    MissingState,
+
+    #[error("was not completely set up (it only reached the {} state)", .0)]
+    Incomplete(SessionState),
+
+    #[error("has already finished")]
+    Finished,
+
+    #[error("is queued and has not been started yet")]
+    Queued,
+
+    #[error("is not queued (it is in the {} state)", .0)]
+    NotQueued(SessionState),
+
+    #[error("could not be started")]
+    Failed,
 }
 
 #[derive(Debug, Eq, Error, PartialEq)]
@@ -199,6 +590,9 @@ pub enum NewSessionError {
 
     #[error("Could not create a request directory after {} attempts", .0)]
     TooManyAttempts(u64),
+
+    #[error("Could not register session: {}", .0)]
+    Registry(#[from] RegistryError),
 }
 
 /// Validate the given session ID is of the proper form.
@@ -220,3 +614,181 @@ pub fn cleanup_session(log: slog::Logger, session_info: &SessionInfo<'_>) {
         error!(log, "Could not cleanup request"; "session_id" => %session_info.id, "error" => %e);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use libfxrecord::net::BuildSource;
+    use slog::{o, Discard, Logger};
+    use tempfile::TempDir;
+    use tokio::fs::write;
+
+    use super::*;
+
+    /// Create a session that is otherwise ready to be resumed in the given
+    /// state.
+    async fn session_in_state(
+        manager: &DefaultSessionManager,
+        state: Option<SessionState>,
+    ) -> SessionInfo<'static> {
+        let session_info = manager.new_session().await.unwrap();
+
+        create_dir(session_info.profile_path()).await.unwrap();
+        create_dir(session_info.path.join("firefox")).await.unwrap();
+        write(session_info.firefox_path(), "").await.unwrap();
+
+        if let Some(state) = state {
+            session_info.set_state(state).await.unwrap();
+        }
+
+        session_info
+    }
+
+    #[tokio::test]
+    async fn test_resume_session_state() {
+        let dir = TempDir::new().unwrap();
+        let manager = DefaultSessionManager::new(
+            Logger::root(Discard, o!()),
+            dir.path(),
+            Duration::from_secs(60),
+        );
+
+        // A session interrupted while running can be retried.
+        for state in &[SessionState::RestartPending, SessionState::Running] {
+            let session_info = session_in_state(&manager, Some(*state)).await;
+            assert!(manager.resume_session(&session_info.id).await.is_ok());
+        }
+
+        let cases = vec![
+            (None, ResumeSessionErrorKind::MissingState),
+            (
+                Some(SessionState::Finished),
+                ResumeSessionErrorKind::Finished,
+            ),
+            (
+                Some(SessionState::PrefsWritten),
+                ResumeSessionErrorKind::Incomplete(SessionState::PrefsWritten),
+            ),
+            (Some(SessionState::Failed), ResumeSessionErrorKind::Failed),
+        ];
+
+        for (state, kind) in cases {
+            let session_info = session_in_state(&manager, state).await;
+            assert_eq!(
+                manager.resume_session(&session_info.id).await.unwrap_err(),
+                ResumeSessionError {
+                    session_id: session_info.id.clone().into_owned(),
+                    kind,
+                }
+            );
+
+            // Sessions that cannot be resumed are cleaned up.
+            assert!(!session_info.path.exists());
+        }
+
+        // Queued sessions are kept until their job is started.
+        let session_info = session_in_state(&manager, Some(SessionState::Queued)).await;
+        assert_eq!(
+            manager.resume_session(&session_info.id).await.unwrap_err(),
+            ResumeSessionError {
+                session_id: session_info.id.clone().into_owned(),
+                kind: ResumeSessionErrorKind::Queued,
+            }
+        );
+        assert!(session_info.path.exists());
+
+        assert_eq!(
+            ResumeSessionErrorKind::Incomplete(SessionState::BuildExtracted).to_string(),
+            "was not completely set up (it only reached the BuildExtracted state)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_files() {
+        let dir = TempDir::new().unwrap();
+        let session_info = SessionInfo {
+            id: Cow::Borrowed("session"),
+            path: dir.path().into(),
+        };
+
+        session_info.set_state(SessionState::Queued).await.unwrap();
+        session_info.set_remaining_runs(3).await.unwrap();
+        session_info
+            .set_job(&NewSessionRequest {
+                build: BuildSource::TaskId("task_id".into()),
+                profile: None,
+                runs: 3,
+                prefs: vec![],
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(session_info.state().await.unwrap(), SessionState::Queued);
+        assert_eq!(session_info.remaining_runs().await.unwrap(), 3);
+        assert_eq!(session_info.job().await.unwrap().runs, 3);
+
+        // The files are written in place of the originals.
+        session_info.set_remaining_runs(2).await.unwrap();
+        assert_eq!(session_info.remaining_runs().await.unwrap(), 2);
+
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["job.json", "runs", "state.json"]);
+    }
+
+    #[test]
+    fn test_firefox_path() {
+        let dir = TempDir::new().unwrap();
+        let session_info = SessionInfo {
+            id: Cow::Borrowed("session"),
+            path: dir.path().into(),
+        };
+        let firefox_dir = dir.path().join("firefox");
+
+        assert_eq!(session_info.firefox_path(), firefox_dir.join("firefox.exe"));
+
+        std::fs::create_dir(&firefox_dir).unwrap();
+        std::fs::write(firefox_dir.join("firefox"), "").unwrap();
+        assert_eq!(session_info.firefox_path(), firefox_dir.join("firefox"));
+
+        std::fs::write(firefox_dir.join("firefox.exe"), "").unwrap();
+        assert_eq!(session_info.firefox_path(), firefox_dir.join("firefox.exe"));
+    }
+
+    #[tokio::test]
+    async fn test_start_job() {
+        let dir = TempDir::new().unwrap();
+        let manager = DefaultSessionManager::new(
+            Logger::root(Discard, o!()),
+            dir.path(),
+            Duration::from_secs(60),
+        );
+
+        let session_info = session_in_state(&manager, Some(SessionState::Queued)).await;
+        assert!(manager.start_job(&session_info.id).await.is_ok());
+
+        let cases = vec![
+            (None, ResumeSessionErrorKind::MissingState),
+            (
+                Some(SessionState::RestartPending),
+                ResumeSessionErrorKind::NotQueued(SessionState::RestartPending),
+            ),
+        ];
+
+        for (state, kind) in cases {
+            let session_info = session_in_state(&manager, state).await;
+            assert_eq!(
+                manager.start_job(&session_info.id).await.unwrap_err(),
+                ResumeSessionError {
+                    session_id: session_info.id.clone().into_owned(),
+                    kind,
+                }
+            );
+
+            // Sessions that cannot be started are kept.
+            assert!(session_info.path.exists());
+        }
+    }
+}
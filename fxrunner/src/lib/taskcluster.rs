@@ -2,22 +2,50 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Debug;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use futures::prelude::*;
 use futures::try_join;
-use reqwest::{Client, StatusCode, Url};
+use libfxrecord::net::DownloadProgress;
+use reqwest::header::{AUTHORIZATION, CONTENT_RANGE, RANGE};
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
-use tokio::fs::File;
+use tokio::fs::{metadata, File, OpenOptions};
 use tokio::prelude::*;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::{Credentials, TaskclusterConfig};
+
+mod hawk;
+
+/// The root URL of the Firefox CI Taskcluster deployment.
+pub const DEFAULT_ROOT_URL: &str = "https://firefox-ci-tc.services.mozilla.com";
 
 /// The name of the artifact containing the result of a build job.
 pub const BUILD_ARTIFACT_NAME: &str = "public/build/target.zip";
 
+/// The name of the artifact containing the chain of trust metadata of a task.
+///
+/// This includes the SHA-256 hash of each artifact the task produced.
+pub const CHAIN_OF_TRUST_ARTIFACT_NAME: &str = "public/chain-of-trust.json";
+
+/// The number of times a download will be attempted before giving up.
+const DOWNLOAD_ATTEMPTS: usize = 3;
+
+/// The minimum time between download progress reports.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A channel for reporting the progress of a download.
+pub type ProgressSender = UnboundedSender<DownloadProgress>;
+
 /// An error from Firefox CI.
 #[derive(Debug, Error)]
 pub enum FirefoxCiError {
@@ -36,92 +64,367 @@ pub enum FirefoxCiError {
 
     #[error("an error occurred while downloading the artifact: {}", .0)]
     StatusError(StatusCode),
+
+    #[error("could not find indexed task: {}", .0)]
+    FindIndexedTask(#[source] reqwest::Error),
+
+    #[error("no task is indexed at `{}'", .0)]
+    IndexNotFound(String),
+
+    #[error("an error occurred while finding the indexed task: {}", .0)]
+    IndexStatusError(StatusCode),
+
+    #[error("could not retrieve chain of trust: {}", .0)]
+    ChainOfTrust(#[source] reqwest::Error),
+
+    #[error("downloaded artifact is {} bytes but expected {} bytes", .actual, .expected)]
+    SizeMismatch { expected: u64, actual: u64 },
+
+    #[error("downloaded artifact has SHA-256 {} but expected {}", .actual, .expected)]
+    HashMismatch { expected: String, actual: String },
 }
 
 #[async_trait]
 pub trait Taskcluster: Debug {
     type Error: Error + 'static;
 
+    /// The name of the build artifact that will be downloaded.
+    fn build_artifact_name(&self) -> &str;
+
+    /// Resolve a path in the Taskcluster index to a task ID.
+    async fn find_indexed_task(&mut self, index_path: &str) -> Result<String, Self::Error>;
+
+    /// Download the build artifact from the given task.
+    ///
+    /// Progress is periodically reported on the given channel.
     async fn download_build_artifact(
         &mut self,
         task_id: &str,
         download_dir: &Path,
+        progress: ProgressSender,
     ) -> Result<PathBuf, Self::Error>;
 }
 
 /// An API client to download Taskcluster build artifacts.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FirefoxCi {
     /// The reqwest Client used for all requests.
     client: Client,
 
     /// The URL for the Taskcluster Queue API.
     queue_url: Url,
+
+    /// The URL for the Taskcluster Index API.
+    index_url: Url,
+
+    /// The name of the build artifact to download.
+    artifact_name: String,
+
+    /// The credentials used to authenticate requests, if any.
+    credentials: Option<Credentials>,
 }
 
 impl Default for FirefoxCi {
     fn default() -> Self {
-        FirefoxCi {
-            queue_url: Url::parse("https://firefox-ci-tc.services.mozilla.com/api/queue/v1/")
-                .unwrap(),
-            client: Client::new(),
-        }
+        FirefoxCi::new(&TaskclusterConfig::default()).unwrap()
     }
 }
 
 impl FirefoxCi {
-    #[cfg(test)]
-    pub(crate) fn with_queue_url(queue_url: Url) -> Self {
-        FirefoxCi {
+    /// Create a client for the Taskcluster deployment in the given
+    /// configuration.
+    pub fn new(config: &TaskclusterConfig) -> Result<Self, FirefoxCiError> {
+        let mut root_url = Url::parse(&config.root_url)?;
+
+        // Without a trailing slash, joining would replace the last segment of
+        // the path.
+        if !root_url.path().ends_with('/') {
+            let path = format!("{}/", root_url.path());
+            root_url.set_path(&path);
+        }
+
+        Ok(FirefoxCi {
             client: Client::new(),
-            queue_url,
+            queue_url: root_url.join("api/queue/v1/")?,
+            index_url: root_url.join("api/index/v1/")?,
+            artifact_name: config.artifact_name.clone(),
+            credentials: config.credentials.clone(),
+        })
+    }
+
+    /// Build a GET request, authenticating it if we have credentials.
+    fn get(&self, url: Url) -> RequestBuilder {
+        match self.credentials {
+            Some(ref credentials) => {
+                let authorization = hawk::authorization(credentials, "GET", &url);
+                self.client.get(url).header(AUTHORIZATION, authorization)
+            }
+            None => self.client.get(url),
         }
     }
-}
 
-#[async_trait]
-impl Taskcluster for FirefoxCi {
-    type Error = FirefoxCiError;
+    fn artifact_url(&self, task_id: &str, artifact_name: &str) -> Result<Url, FirefoxCiError> {
+        Ok(self
+            .queue_url
+            .join(&format!("task/{}/artifacts/{}", task_id, artifact_name))?)
+    }
 
-    /// Download the build artifact from a Taskcluster task.
-    async fn download_build_artifact(
-        &mut self,
+    /// Retrieve the expected SHA-256 hash of an artifact from the chain of
+    /// trust of its task.
+    ///
+    /// Returns `None` if the task has no chain of trust or if the artifact is
+    /// not listed in it.
+    async fn artifact_sha256(
+        &self,
         task_id: &str,
-        download_dir: &Path,
-    ) -> Result<PathBuf, FirefoxCiError> {
-        let url = self.queue_url.join(&format!(
-            "task/{}/artifacts/{}",
-            task_id, BUILD_ARTIFACT_NAME
-        ))?;
-
-        let path = download_dir.join("firefox.zip");
+        artifact_name: &str,
+    ) -> Result<Option<String>, FirefoxCiError> {
+        let url = self.artifact_url(task_id, CHAIN_OF_TRUST_ARTIFACT_NAME)?;
 
-        let mut request = self
-            .client
+        let response = self
             .get(url)
             .send()
             .await
-            .map_err(FirefoxCiError::DownloadArtifact)?;
+            .map_err(FirefoxCiError::ChainOfTrust)?;
 
-        if !request.status().is_success() {
-            return Err(FirefoxCiError::StatusError(request.status()));
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => return Err(FirefoxCiError::StatusError(status)),
+            _ => {}
         }
 
-        let mut file = File::create(&path).await.map_err(FirefoxCiError::Io)?;
+        let chain_of_trust: ChainOfTrust = response
+            .json()
+            .await
+            .map_err(FirefoxCiError::ChainOfTrust)?;
+
+        Ok(chain_of_trust
+            .artifacts
+            .get(artifact_name)
+            .map(|artifact| artifact.sha256.to_lowercase()))
+    }
+
+    /// Download the artifact at the given URL to `path`.
+    ///
+    /// If the download is interrupted, or if `path` already contains part of
+    /// the artifact, the download is resumed with a range request.
+    async fn download(
+        &self,
+        url: &Url,
+        path: &Path,
+        progress: &ProgressSender,
+    ) -> Result<(), FirefoxCiError> {
+        let mut attempt = 1;
+
+        loop {
+            match self.download_attempt(url, path, progress).await {
+                Err(FirefoxCiError::DownloadArtifact(..)) if attempt < DOWNLOAD_ATTEMPTS => {}
+                Err(FirefoxCiError::SizeMismatch { expected, actual })
+                    if actual < expected && attempt < DOWNLOAD_ATTEMPTS => {}
+                result => return result,
+            }
+
+            attempt += 1;
+        }
+    }
+
+    async fn download_attempt(
+        &self,
+        url: &Url,
+        path: &Path,
+        progress: &ProgressSender,
+    ) -> Result<(), FirefoxCiError> {
+        let offset = match metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut request = self.get(url.clone());
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        let mut response = request
+            .send()
+            .await
+            .map_err(FirefoxCiError::DownloadArtifact)?;
+
+        let (mut file, expected_size, mut received) = match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let expected_size = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(content_range_size);
+
+                let file = OpenOptions::new().append(true).open(path).await?;
+
+                (file, expected_size, offset)
+            }
+
+            // The server ignored the range request, so we have to start over.
+            status if status.is_success() => {
+                (File::create(path).await?, response.content_length(), 0)
+            }
+
+            status => return Err(FirefoxCiError::StatusError(status)),
+        };
 
         // Stream the first chunk ...
-        let mut chunk = request
+        let mut chunk = response
             .chunk()
             .await
             .map_err(FirefoxCiError::DownloadArtifact)?;
 
+        let start = Instant::now();
+        let start_offset = received;
+        let mut last_report = start;
+
+        let report = |received: u64| {
+            let elapsed = start.elapsed().as_secs_f64();
+            let bytes_per_sec = if elapsed > 0.0 {
+                ((received - start_offset) as f64 / elapsed) as u64
+            } else {
+                0
+            };
+
+            // The receiver going away is not a reason to stop downloading.
+            let _ = progress.send(DownloadProgress {
+                received,
+                total: expected_size,
+                bytes_per_sec,
+            });
+        };
+
         // Then write the previous chunk to disk while streaming the next chunk.
         while let Some(content) = chunk {
             chunk = try_join!(
-                request.chunk().map_err(FirefoxCiError::DownloadArtifact),
+                response.chunk().map_err(FirefoxCiError::DownloadArtifact),
                 file.write_all(&content).map_err(FirefoxCiError::Io),
             )?
             .0;
+
+            received += content.len() as u64;
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                report(received);
+            }
+        }
+
+        file.flush().await?;
+        report(received);
+
+        let actual = metadata(path).await?.len();
+        match expected_size {
+            Some(expected) if expected != actual => {
+                Err(FirefoxCiError::SizeMismatch { expected, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse the complete size of an artifact from a `Content-Range` header of the
+/// form `bytes start-end/size`.
+fn content_range_size(content_range: &str) -> Option<u64> {
+    content_range.rsplit('/').next()?.parse().ok()
+}
+
+/// Compute the SHA-256 hash of the file at the given path.
+async fn sha256_file(path: &Path) -> Result<String, io::Error> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The chain of trust metadata of a task.
+#[derive(Debug, Deserialize)]
+struct ChainOfTrust {
+    artifacts: HashMap<String, ArtifactMetadata>,
+}
+
+/// The metadata of an artifact in a chain of trust.
+#[derive(Debug, Deserialize)]
+struct ArtifactMetadata {
+    sha256: String,
+}
+
+/// A task in the Taskcluster index.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedTask {
+    task_id: String,
+}
+
+#[async_trait]
+impl Taskcluster for FirefoxCi {
+    type Error = FirefoxCiError;
+
+    fn build_artifact_name(&self) -> &str {
+        &self.artifact_name
+    }
+
+    /// Find the task at the given path in the Taskcluster index.
+    async fn find_indexed_task(&mut self, index_path: &str) -> Result<String, FirefoxCiError> {
+        let url = self.index_url.join(&format!("task/{}", index_path))?;
+
+        let response = self
+            .get(url)
+            .send()
+            .await
+            .map_err(FirefoxCiError::FindIndexedTask)?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => return Err(FirefoxCiError::IndexNotFound(index_path.into())),
+            status if !status.is_success() => return Err(FirefoxCiError::IndexStatusError(status)),
+            _ => {}
+        }
+
+        let task: IndexedTask = response
+            .json()
+            .await
+            .map_err(FirefoxCiError::FindIndexedTask)?;
+
+        Ok(task.task_id)
+    }
+
+    /// Download the build artifact from a Taskcluster task.
+    ///
+    /// The size of the artifact is verified against the size reported by the
+    /// server and, if the task has a chain of trust, its SHA-256 hash is
+    /// verified against the hash recorded there.
+    async fn download_build_artifact(
+        &mut self,
+        task_id: &str,
+        download_dir: &Path,
+        progress: ProgressSender,
+    ) -> Result<PathBuf, FirefoxCiError> {
+        let url = self.artifact_url(task_id, &self.artifact_name)?;
+        let path = download_dir.join("firefox.zip");
+
+        let expected_sha256 = self.artifact_sha256(task_id, &self.artifact_name).await?;
+
+        self.download(&url, &path, &progress).await?;
+
+        if let Some(expected) = expected_sha256 {
+            let actual = sha256_file(&path).await?;
+
+            if actual != expected {
+                return Err(FirefoxCiError::HashMismatch { expected, actual });
+            }
         }
 
         Ok(path)
@@ -133,89 +436,361 @@ mod test {
     use std::env::current_dir;
 
     use assert_matches::assert_matches;
+    use mockito::Matcher;
     use reqwest::StatusCode;
+    use serde_json::json;
     use tempfile::TempDir;
+    use tokio::sync::mpsc::unbounded_channel;
 
     use super::*;
 
     fn firefox_ci() -> FirefoxCi {
-        FirefoxCi::with_queue_url(
-            Url::parse(&mockito::server_url())
+        FirefoxCi::new(&TaskclusterConfig {
+            root_url: mockito::server_url(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_find_indexed_task() {
+        let index_path = "gecko.v2.mozilla-central.latest.firefox.win64-shippable-opt";
+
+        let index_rsp = mockito::mock("GET", &*format!("/api/index/v1/task/{}", index_path))
+            .with_body(
+                json!({
+                    "namespace": index_path,
+                    "taskId": "foo",
+                    "rank": 0,
+                    "data": {},
+                    "expires": "2021-01-01T00:00:00.000Z",
+                })
+                .to_string(),
+            )
+            .create();
+
+        assert_eq!(
+            firefox_ci().find_indexed_task(index_path).await.unwrap(),
+            "foo"
+        );
+
+        index_rsp.assert();
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_find_indexed_task_404() {
+        let index_rsp = mockito::mock("GET", "/api/index/v1/task/gecko.v2.missing")
+            .with_status(404)
+            .with_body("not found")
+            .create();
+
+        assert_matches!(
+            firefox_ci()
+                .find_indexed_task("gecko.v2.missing")
+                .await
+                .unwrap_err(),
+            FirefoxCiError::IndexNotFound(path) => {
+                assert_eq!(path, "gecko.v2.missing");
+            }
+        );
+
+        index_rsp.assert();
+    }
+
+    fn test_zip() -> Vec<u8> {
+        std::fs::read(
+            current_dir()
                 .unwrap()
-                .join("/api/queue/v1/")
-                .unwrap(),
+                .parent()
+                .unwrap()
+                .join("test")
+                .join("test.zip"),
         )
+        .unwrap()
+    }
+
+    fn artifact_path(task_id: &str, artifact_name: &str) -> String {
+        format!("/api/queue/v1/task/{}/artifacts/{}", task_id, artifact_name)
+    }
+
+    /// Mock the chain of trust of the given task.
+    ///
+    /// If no hash is given, the task will not have a chain of trust.
+    fn mock_chain_of_trust(task_id: &str, sha256: Option<&str>) -> mockito::Mock {
+        let mock = mockito::mock(
+            "GET",
+            &*artifact_path(task_id, CHAIN_OF_TRUST_ARTIFACT_NAME),
+        );
+
+        match sha256 {
+            Some(sha256) => mock.with_body(
+                json!({
+                    "artifacts": {
+                        BUILD_ARTIFACT_NAME: {
+                            "sha256": sha256,
+                        },
+                    },
+                })
+                .to_string(),
+            ),
+            None => mock.with_status(404).with_body("not found"),
+        }
+        .create()
+    }
+
+    fn progress() -> ProgressSender {
+        unbounded_channel().0
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(bytes))
     }
 
     #[tokio::test]
     async fn test_firefox_ci() {
-        let zip_path = current_dir()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("test")
-            .join("test.zip");
+        let zip = test_zip();
+
+        let cot_rsp = mock_chain_of_trust("foo", None);
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("foo", BUILD_ARTIFACT_NAME))
+            .with_body(&zip)
+            .create();
 
+        let download_dir = TempDir::new().unwrap();
+
+        let path = firefox_ci()
+            .download_build_artifact("foo", download_dir.path(), progress())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(path).unwrap(), zip);
+
+        cot_rsp.assert();
+        artifact_rsp.assert();
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_config() {
+        let zip = test_zip();
+        let artifact_name = "public/build/target.tar.bz2";
+
+        let cot_rsp = mockito::mock(
+            "GET",
+            &*format!(
+                "/staging/api/queue/v1/task/private/artifacts/{}",
+                CHAIN_OF_TRUST_ARTIFACT_NAME
+            ),
+        )
+        .match_header(
+            "authorization",
+            Matcher::Regex(r#"^Hawk id="client", "#.into()),
+        )
+        .with_status(404)
+        .with_body("not found")
+        .create();
         let artifact_rsp = mockito::mock(
             "GET",
-            &*format!("/api/queue/v1/task/foo/artifacts/{}", BUILD_ARTIFACT_NAME),
+            &*format!(
+                "/staging/api/queue/v1/task/private/artifacts/{}",
+                artifact_name
+            ),
+        )
+        .match_header(
+            "authorization",
+            Matcher::Regex(r#"^Hawk id="client", "#.into()),
         )
-        .with_body_from_file(zip_path)
+        .with_body(&zip)
         .create();
 
+        let mut firefox_ci = FirefoxCi::new(&TaskclusterConfig {
+            root_url: format!("{}/staging", mockito::server_url()),
+            artifact_name: artifact_name.into(),
+            credentials: Some(Credentials {
+                client_id: "client".into(),
+                access_token: "token".into(),
+            }),
+        })
+        .unwrap();
+
+        assert_eq!(firefox_ci.build_artifact_name(), artifact_name);
+
+        let download_dir = TempDir::new().unwrap();
+        let path = firefox_ci
+            .download_build_artifact("private", download_dir.path(), progress())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(path).unwrap(), zip);
+
+        cot_rsp.assert();
+        artifact_rsp.assert();
+
+        assert_matches!(
+            FirefoxCi::new(&TaskclusterConfig {
+                root_url: "not a url".into(),
+                ..Default::default()
+            }),
+            Err(FirefoxCiError::UrlParse(..))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_verify_hash() {
+        let zip = test_zip();
+
+        let cot_rsp = mock_chain_of_trust("verified", Some(&sha256(&zip)));
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("verified", BUILD_ARTIFACT_NAME))
+            .with_body(&zip)
+            .create();
+
         let download_dir = TempDir::new().unwrap();
 
         firefox_ci()
-            .download_build_artifact("foo", download_dir.path())
+            .download_build_artifact("verified", download_dir.path(), progress())
             .await
             .unwrap();
 
+        cot_rsp.assert();
+        artifact_rsp.assert();
+
+        let cot_rsp = mock_chain_of_trust("corrupt", Some(&sha256(b"something else")));
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("corrupt", BUILD_ARTIFACT_NAME))
+            .with_body(&zip)
+            .create();
+
+        let download_dir = TempDir::new().unwrap();
+
+        assert_matches!(
+            firefox_ci()
+                .download_build_artifact("corrupt", download_dir.path(), progress())
+                .await
+                .unwrap_err(),
+            FirefoxCiError::HashMismatch { expected, actual } => {
+                assert_eq!(expected, sha256(b"something else"));
+                assert_eq!(actual, sha256(&zip));
+            }
+        );
+
+        cot_rsp.assert();
+        artifact_rsp.assert();
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_resume() {
+        let zip = test_zip();
+        let (head, tail) = zip.split_at(10);
+
+        let cot_rsp = mock_chain_of_trust("resume", Some(&sha256(&zip)));
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("resume", BUILD_ARTIFACT_NAME))
+            .match_header("range", "bytes=10-")
+            .with_status(206)
+            .with_header(
+                "content-range",
+                &format!("bytes 10-{}/{}", zip.len() - 1, zip.len()),
+            )
+            .with_body(tail)
+            .create();
+
+        // A previous download was interrupted.
+        let download_dir = TempDir::new().unwrap();
+        std::fs::write(download_dir.path().join("firefox.zip"), head).unwrap();
+
+        let (progress_tx, mut progress_rx) = unbounded_channel();
+        let path = firefox_ci()
+            .download_build_artifact("resume", download_dir.path(), progress_tx)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(path).unwrap(), zip);
+
+        let mut last_progress = None;
+        while let Some(progress) = progress_rx.recv().await {
+            last_progress = Some(progress);
+        }
+
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.received, zip.len() as u64);
+        assert_eq!(last_progress.total, Some(zip.len() as u64));
+
+        cot_rsp.assert();
+        artifact_rsp.assert();
+    }
+
+    #[tokio::test]
+    async fn test_firefox_ci_size_mismatch() {
+        let zip = test_zip();
+        let (head, tail) = zip.split_at(10);
+
+        let cot_rsp = mock_chain_of_trust("size", None);
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("size", BUILD_ARTIFACT_NAME))
+            .match_header("range", "bytes=10-")
+            .with_status(206)
+            .with_header(
+                "content-range",
+                &format!("bytes 10-{}/{}", zip.len() - 2, zip.len() - 1),
+            )
+            .with_body(tail)
+            .create();
+
+        let download_dir = TempDir::new().unwrap();
+        std::fs::write(download_dir.path().join("firefox.zip"), head).unwrap();
+
+        assert_matches!(
+            firefox_ci()
+                .download_build_artifact("size", download_dir.path(), progress())
+                .await
+                .unwrap_err(),
+            FirefoxCiError::SizeMismatch { expected, actual } => {
+                assert_eq!(expected, zip.len() as u64 - 1);
+                assert_eq!(actual, zip.len() as u64);
+            }
+        );
+
+        cot_rsp.assert();
         artifact_rsp.assert();
     }
 
     #[tokio::test]
     async fn test_firefox_ci_404() {
-        let artifact_rsp = mockito::mock(
-            "GET",
-            &*format!("/api/queue/v1/task/foo/artifacts/{}", BUILD_ARTIFACT_NAME),
-        )
-        .with_status(404)
-        .with_body("not found")
-        .create();
+        let cot_rsp = mock_chain_of_trust("missing", None);
+        let artifact_rsp = mockito::mock("GET", &*artifact_path("missing", BUILD_ARTIFACT_NAME))
+            .with_status(404)
+            .with_body("not found")
+            .create();
 
         let download_dir = TempDir::new().unwrap();
 
         assert_matches!(
             firefox_ci()
-                .download_build_artifact("foo", download_dir.path())
+                .download_build_artifact("missing", download_dir.path(), progress())
                 .await
                 .unwrap_err(),
             FirefoxCiError::StatusError(StatusCode::NOT_FOUND)
         );
 
+        cot_rsp.assert();
         artifact_rsp.assert();
     }
 
     #[tokio::test]
     async fn test_firefox_ci_503() {
-        let artifact_rsp = mockito::mock(
-            "GET",
-            &*format!("/api/queue/v1/task/foo/artifacts/{}", BUILD_ARTIFACT_NAME),
-        )
-        .with_status(503)
-        .with_body("not found")
-        .create();
+        let cot_rsp = mock_chain_of_trust("unavailable", None);
+        let artifact_rsp =
+            mockito::mock("GET", &*artifact_path("unavailable", BUILD_ARTIFACT_NAME))
+                .with_status(503)
+                .with_body("not found")
+                .create();
 
         let download_dir = TempDir::new().unwrap();
 
         assert_matches!(
             firefox_ci()
-                .download_build_artifact("foo", download_dir.path())
+                .download_build_artifact("unavailable", download_dir.path(), progress())
                 .await
                 .unwrap_err(),
             FirefoxCiError::StatusError(StatusCode::SERVICE_UNAVAILABLE)
         );
 
+        cot_rsp.assert();
         artifact_rsp.assert();
     }
 }
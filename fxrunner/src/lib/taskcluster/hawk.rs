@@ -0,0 +1,125 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hawk authentication for Taskcluster requests.
+//!
+//! See https://github.com/mozilla/hawk/blob/main/API.md for the details of the
+//! scheme.
+
+use std::iter::repeat_with;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use reqwest::Url;
+use sha2::Sha256;
+
+use crate::config::Credentials;
+
+/// The length of the nonce included in each request.
+const NONCE_LEN: usize = 8;
+
+/// Generate the value of the `Authorization` header for a request.
+pub(super) fn authorization(credentials: &Credentials, method: &str, url: &Url) -> String {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut rng = thread_rng();
+    let nonce: String = repeat_with(|| rng.sample(Alphanumeric))
+        .take(NONCE_LEN)
+        .collect();
+
+    authorization_with(credentials, method, url, ts, &nonce, None)
+}
+
+fn authorization_with(
+    credentials: &Credentials,
+    method: &str,
+    url: &Url,
+    ts: u64,
+    nonce: &str,
+    ext: Option<&str>,
+) -> String {
+    let mac = mac(credentials, method, url, ts, nonce, ext);
+
+    match ext {
+        Some(ext) => format!(
+            r#"Hawk id="{}", ts="{}", nonce="{}", ext="{}", mac="{}""#,
+            credentials.client_id, ts, nonce, ext, mac
+        ),
+        None => format!(
+            r#"Hawk id="{}", ts="{}", nonce="{}", mac="{}""#,
+            credentials.client_id, ts, nonce, mac
+        ),
+    }
+}
+
+/// Compute the MAC of the normalized request string.
+fn mac(
+    credentials: &Credentials,
+    method: &str,
+    url: &Url,
+    ts: u64,
+    nonce: &str,
+    ext: Option<&str>,
+) -> String {
+    let resource = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+
+    let normalized = format!(
+        "hawk.1.header\n{}\n{}\n{}\n{}\n{}\n{}\n\n{}\n",
+        ts,
+        nonce,
+        method.to_uppercase(),
+        resource,
+        url.host_str().unwrap_or_default().to_lowercase(),
+        url.port_or_known_default().unwrap_or_default(),
+        ext.unwrap_or_default(),
+    );
+
+    // HMAC accepts keys of any length.
+    let mut hmac = Hmac::<Sha256>::new_varkey(credentials.access_token.as_bytes()).unwrap();
+    hmac.update(normalized.as_bytes());
+
+    base64::encode(hmac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            client_id: "dh37fgj492je".into(),
+            access_token: "werxhqb98rpaxn39848xrunpaw3489ruxnpa98w4rxn".into(),
+        }
+    }
+
+    #[test]
+    fn test_authorization() {
+        // The example from the Hawk specification.
+        let url = Url::parse("http://example.com:8000/resource/1?b=1&a=2").unwrap();
+
+        assert_eq!(
+            authorization_with(
+                &credentials(),
+                "GET",
+                &url,
+                1353832234,
+                "j4h3g2",
+                Some("some-app-ext-data"),
+            ),
+            r#"Hawk id="dh37fgj492je", ts="1353832234", nonce="j4h3g2", ext="some-app-ext-data", mac="6R4rV5iE+NPoym+WwjeHzjAGXUtLNIxmo1vpMofpLAE=""#
+        );
+
+        let header = authorization(&credentials(), "GET", &url);
+        assert!(header.starts_with(r#"Hawk id="dh37fgj492je", ts=""#));
+        assert!(header.contains(r#"mac=""#));
+    }
+}
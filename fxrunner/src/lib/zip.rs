@@ -2,26 +2,262 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::fs::{create_dir_all, File};
-use std::io;
-use std::path::{Path, PathBuf};
+//! Extraction of zip and tar archives.
+//!
+//! Archives may come from untrusted sources, so entries that would escape the
+//! target directory and links are rejected, and the size of the extracted
+//! contents is limited.
 
+use std::fs::{create_dir_all, metadata, File};
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+use bzip2::read::MultiBzDecoder;
+use filetime::{set_file_mtime, FileTime};
+use flate2::read::MultiGzDecoder;
+use tar::{Archive as TarArchive, EntryType};
 use thiserror::Error;
-use zip::ZipArchive;
+use xz2::read::XzDecoder;
+use zip::{DateTime, ZipArchive};
+
+use crate::config::ExtractLimits;
 
-/// Statistics about an unzip operation.
-#[derive(Default)]
+/// Statistics about an extraction operation.
+#[derive(Debug, Default)]
 pub struct ZipStats {
     /// The number of extracted files.
     pub extracted: usize,
 
-    /// The top-level directory of the zip file.
+    /// The top-level directory of the archive.
     pub top_level_dir: Option<PathBuf>,
 }
 
+/// The format of an archive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarBz2,
+    TarXz,
+    TarGz,
+}
+
+/// The offset of the magic bytes in the header of a tar entry.
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// The file type bits of a Unix mode.
+const S_IFMT: u32 = 0o170_000;
+
+/// The file type of a symbolic link in a Unix mode.
+const S_IFLNK: u32 = 0o120_000;
+
+/// The number of bytes at the start of an archive needed to detect its format.
+const HEADER_LEN: usize = TAR_MAGIC_OFFSET + 5;
+
+impl ArchiveFormat {
+    /// Detect the format of the archive at the given path from its magic bytes.
+    ///
+    /// Returns `None` if the format is not recognized.
+    pub fn detect(archive: &Path) -> Result<Option<ArchiveFormat>, io::Error> {
+        let header = read_header(&mut File::open(archive)?)?;
+
+        Ok(Self::from_magic(&header))
+    }
+
+    fn from_magic(header: &[u8]) -> Option<ArchiveFormat> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.starts_with(b"BZh") {
+            Some(ArchiveFormat::TarBz2)
+        } else if header.starts_with(b"\xfd7zXZ\x00") {
+            Some(ArchiveFormat::TarXz)
+        } else if header.starts_with(b"\x1f\x8b") {
+            Some(ArchiveFormat::TarGz)
+        } else if header.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extract the archive at the given location to the target location.
+///
+/// The format of the archive is detected from its contents.
+pub fn extract(
+    archive: &Path,
+    target: &Path,
+    limits: &ExtractLimits,
+) -> Result<ZipStats, ZipError> {
+    let format = ArchiveFormat::detect(archive)
+        .map_err(|source| ZipError::OpenArchive {
+            archive: archive.into(),
+            source,
+        })?
+        .ok_or_else(|| ZipError::UnknownFormat(archive.into()))?;
+
+    if format == ArchiveFormat::Zip {
+        return unzip(archive, target, limits);
+    }
+
+    let archive_size = archive_size(archive)?;
+    let file = File::open(archive).map_err(|source| ZipError::OpenArchive {
+        archive: archive.into(),
+        source,
+    })?;
+
+    untar(
+        archive,
+        format,
+        BufReader::new(file),
+        archive_size,
+        target,
+        limits,
+    )
+}
+
+/// Extract the tar archive read from `reader` to the target location as it is
+/// read.
+///
+/// The archive may be compressed, which is detected from its contents. Zip
+/// archives cannot be extracted this way, as their index is at the end of the
+/// archive.
+///
+/// `name` identifies the archive in errors and `size` is the size of the
+/// archive (in bytes), which is used to enforce the compression ratio limit.
+pub fn extract_stream<R: Read>(
+    name: &Path,
+    mut reader: R,
+    size: u64,
+    target: &Path,
+    limits: &ExtractLimits,
+) -> Result<ZipStats, ZipError> {
+    let header = read_header(&mut reader).map_err(|source| ZipError::ReadTar {
+        archive: name.into(),
+        source,
+    })?;
+
+    let format = match ArchiveFormat::from_magic(&header) {
+        Some(ArchiveFormat::Zip) => return Err(ZipError::ZipStream(name.into())),
+        Some(format) => format,
+        None => return Err(ZipError::UnknownFormat(name.into())),
+    };
+
+    untar(
+        name,
+        format,
+        Cursor::new(header).chain(reader),
+        size,
+        target,
+        limits,
+    )
+}
+
+/// Read the start of an archive for detecting its format.
+///
+/// Fewer bytes are returned if the archive is shorter than the header.
+fn read_header<R: Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    reader
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .map(|_| header)
+}
+
+/// Extract the tar archive of the given format read from `reader` to the
+/// target location.
+///
+/// `archive` is the path of the archive, for error reporting, and
+/// `archive_size` is used to enforce the compression ratio limit.
+fn untar<R: Read>(
+    archive: &Path,
+    format: ArchiveFormat,
+    reader: R,
+    archive_size: u64,
+    target: &Path,
+    limits: &ExtractLimits,
+) -> Result<ZipStats, ZipError> {
+    match format {
+        ArchiveFormat::Zip => unreachable!(),
+        ArchiveFormat::Tar => untar_entries(archive, reader, archive_size, target, limits),
+        ArchiveFormat::TarBz2 => untar_entries(
+            archive,
+            MultiBzDecoder::new(reader),
+            archive_size,
+            target,
+            limits,
+        ),
+        ArchiveFormat::TarXz => untar_entries(
+            archive,
+            XzDecoder::new_multi_decoder(reader),
+            archive_size,
+            target,
+            limits,
+        ),
+        ArchiveFormat::TarGz => untar_entries(
+            archive,
+            MultiGzDecoder::new(reader),
+            archive_size,
+            target,
+            limits,
+        ),
+    }
+}
+
+/// Extract the entries of the uncompressed tar archive read from `reader` to
+/// the target location.
+fn untar_entries<R: Read>(
+    archive: &Path,
+    reader: R,
+    archive_size: u64,
+    target: &Path,
+    limits: &ExtractLimits,
+) -> Result<ZipStats, ZipError> {
+    let mut extractor = Extractor::new(archive, archive_size, target, limits);
+
+    let read_error = |source: io::Error| ZipError::ReadTar {
+        archive: archive.into(),
+        source,
+    };
+
+    let mut tar = TarArchive::new(reader);
+    for entry in tar.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let entry_type = entry.header().entry_type();
+
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            return Err(ZipError::Link {
+                archive: archive.into(),
+                entry: name,
+            });
+        }
+
+        // Other entry types (e.g., global extended headers) have nothing to
+        // extract.
+        if !entry_type.is_dir() && !entry_type.is_file() && entry_type != EntryType::Continuous {
+            continue;
+        }
+
+        let path = match extractor.entry(&name)? {
+            Some(path) => path,
+            None => continue,
+        };
+
+        if entry_type.is_dir() {
+            extractor.dir(&path)?;
+        } else {
+            let mtime = entry.header().mtime().ok().map(|mtime| mtime as i64);
+            extractor.file(&path, &mut entry, mtime)?;
+        }
+    }
+
+    Ok(extractor.stats)
+}
+
 /// Unzip the archive at the given location to the target location.
-pub fn unzip(archive: &Path, target: &Path) -> Result<ZipStats, ZipError> {
-    let mut stats = ZipStats::default();
+pub fn unzip(archive: &Path, target: &Path, limits: &ExtractLimits) -> Result<ZipStats, ZipError> {
+    let mut extractor = Extractor::new(archive, archive_size(archive)?, target, limits);
 
     let zip_file = File::open(archive).map_err(|source| ZipError::OpenArchive {
         archive: archive.into(),
@@ -39,51 +275,232 @@ pub fn unzip(archive: &Path, target: &Path) -> Result<ZipStats, ZipError> {
             source,
         })?;
 
-        let name = zipped.sanitized_name();
-        let path = target.join(&name);
+        let name = zipped.name().to_owned();
 
-        if i == 0 {
-            stats.top_level_dir = Some(name.clone());
-        } else if let Some(top_level_dir) = stats.top_level_dir.take() {
-            stats.top_level_dir = common_stem(&top_level_dir, &name);
+        if zipped.unix_mode().map(|mode| mode & S_IFMT) == Some(S_IFLNK) {
+            return Err(ZipError::Link {
+                archive: archive.into(),
+                entry: name,
+            });
         }
 
+        let path = match extractor.entry(&name)? {
+            Some(path) => path,
+            None => continue,
+        };
+
         if zipped.is_dir() {
-            create_dir_all(&path).map_err(|source| ZipError::MakeDir { path, source })?;
+            extractor.dir(&path)?;
             continue;
         }
 
         debug_assert!(zipped.is_file());
 
+        let mtime = dos_to_unix_time(&zipped.last_modified());
+        extractor.file(&path, &mut zipped, Some(mtime))?;
+    }
+
+    Ok(extractor.stats)
+}
+
+/// The state of an extraction, which enforces the extraction limits.
+struct Extractor<'a> {
+    archive: &'a Path,
+    target: &'a Path,
+    limits: &'a ExtractLimits,
+
+    /// The size of the archive.
+    archive_size: u64,
+
+    /// The number of entries seen so far.
+    entries: u64,
+
+    /// Whether or not an entry that names something has been seen.
+    named_entry_seen: bool,
+
+    /// The number of bytes extracted so far.
+    written: u64,
+
+    stats: ZipStats,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(
+        archive: &'a Path,
+        archive_size: u64,
+        target: &'a Path,
+        limits: &'a ExtractLimits,
+    ) -> Self {
+        Extractor {
+            archive,
+            target,
+            limits,
+            archive_size,
+            entries: 0,
+            named_entry_seen: false,
+            written: 0,
+            stats: ZipStats::default(),
+        }
+    }
+
+    /// Record an entry with the given name.
+    ///
+    /// Returns the path of the entry relative to the target, or `None` if the
+    /// entry does not name anything (e.g., `./`).
+    fn entry(&mut self, name: &str) -> Result<Option<PathBuf>, ZipError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(ZipError::TooManyEntries {
+                archive: self.archive.into(),
+                limit: self.limits.max_entries,
+            });
+        }
+
+        let path = entry_path(name).ok_or_else(|| ZipError::UnsafePath {
+            archive: self.archive.into(),
+            entry: name.into(),
+        })?;
+
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+
+        if !self.named_entry_seen {
+            self.stats.top_level_dir = Some(path.clone());
+            self.named_entry_seen = true;
+        } else if let Some(top_level_dir) = self.stats.top_level_dir.take() {
+            self.stats.top_level_dir = common_stem(&top_level_dir, &path);
+        }
+
+        Ok(Some(path))
+    }
+
+    /// Create the directory at the given path relative to the target.
+    fn dir(&self, path: &Path) -> Result<(), ZipError> {
+        let path = self.target.join(path);
+        create_dir_all(&path).map_err(|source| ZipError::MakeDir { path, source })
+    }
+
+    /// Write the file at the given path relative to the target from `reader`.
+    ///
+    /// The modification time of the file is set to `mtime` (in seconds since
+    /// the Unix epoch), if provided.
+    fn file<R: Read>(
+        &mut self,
+        path: &Path,
+        reader: &mut R,
+        mtime: Option<i64>,
+    ) -> Result<(), ZipError> {
+        let path = self.target.join(path);
+
         let parent = path.parent().expect("path has no parent directory");
         create_dir_all(&parent).map_err(|source| ZipError::MakeDir {
             path: parent.into(),
             source,
         })?;
 
-        let mut writer = File::create(&path).map_err(|source| ZipError::Io {
+        let archive = self.archive;
+        let io_error = |source| ZipError::Io {
             archive: archive.into(),
             file_name: path.clone(),
             source,
-        })?;
+        };
+
+        let max_size = self.limits.max_size();
+        let max_ratio_size = self
+            .archive_size
+            .saturating_mul(self.limits.max_compression_ratio);
+
+        // Read at most one byte more than the limits allow so that we can tell
+        // when they are exceeded without trusting the sizes the archive
+        // claims.
+        let remaining = max_size.min(max_ratio_size).saturating_sub(self.written);
+
+        {
+            let mut writer = File::create(&path).map_err(io_error)?;
+            self.written += io::copy(&mut reader.take(remaining.saturating_add(1)), &mut writer)
+                .map_err(io_error)?;
+        }
+
+        if self.written > max_size {
+            return Err(ZipError::TooLarge {
+                archive: self.archive.into(),
+                limit: max_size,
+            });
+        } else if self.written > max_ratio_size {
+            return Err(ZipError::CompressionRatio {
+                archive: self.archive.into(),
+                limit: self.limits.max_compression_ratio,
+            });
+        }
+
+        if let Some(mtime) = mtime {
+            set_file_mtime(&path, FileTime::from_unix_time(mtime, 0)).map_err(io_error)?;
+        }
+
+        self.stats.extracted += 1;
+
+        Ok(())
+    }
+}
 
-        io::copy(&mut zipped, &mut writer).map_err(|source| ZipError::Io {
+/// The size of the archive at the given path (in bytes).
+fn archive_size(archive: &Path) -> Result<u64, ZipError> {
+    metadata(archive)
+        .map(|metadata| metadata.len())
+        .map_err(|source| ZipError::OpenArchive {
             archive: archive.into(),
-            file_name: path,
             source,
-        })?;
+        })
+}
+
+/// Convert the name of an archive entry into a relative path.
+///
+/// Returns `None` if the name is absolute or refers to a parent directory.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    if name.starts_with('/') || name.starts_with('\\') {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split(&['/', '\\'][..]) {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+
+            // Drive prefixes (and NTFS alternate data streams).
+            c if c.contains(':') => return None,
 
-        stats.extracted += 1;
+            c => path.push(c),
+        }
     }
 
-    Ok(stats)
+    Some(path)
+}
+
+/// Convert an MS-DOS timestamp to seconds since the Unix epoch.
+///
+/// MS-DOS timestamps have no time zone, so they are treated as UTC.
+fn dos_to_unix_time(datetime: &DateTime) -> i64 {
+    // Days since the epoch of the given civil date; see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (month, day) = (i64::from(datetime.month()), i64::from(datetime.day()));
+    let year = i64::from(datetime.year()) - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    days * 86_400
+        + i64::from(datetime.hour()) * 3_600
+        + i64::from(datetime.minute()) * 60
+        + i64::from(datetime.second())
 }
 
 fn common_stem(p1: &Path, p2: &Path) -> Option<PathBuf> {
     let mut common = None;
 
-    use std::path::Component;
-
     for (c1, c2) in Iterator::zip(p1.components(), p2.components()) {
         if let Component::Normal(c1) = c1 {
             if let Component::Normal(c2) = c2 {
@@ -119,6 +536,54 @@ pub enum ZipError {
         source: zip::result::ZipError,
     },
 
+    #[error(
+        "could not read tar archive `{}': {}",
+        .archive.display(),
+        .source
+    )]
+    ReadTar { archive: PathBuf, source: io::Error },
+
+    #[error("unrecognized archive format: `{}'", .0.display())]
+    UnknownFormat(PathBuf),
+
+    #[error("zip archive `{}' cannot be extracted as it is received", .0.display())]
+    ZipStream(PathBuf),
+
+    #[error(
+        "archive `{}' contains an entry with an unsafe path: `{}'",
+        .archive.display(),
+        .entry
+    )]
+    UnsafePath { archive: PathBuf, entry: String },
+
+    #[error(
+        "archive `{}' contains a link: `{}'",
+        .archive.display(),
+        .entry
+    )]
+    Link { archive: PathBuf, entry: String },
+
+    #[error(
+        "archive `{}' contains more than {} entries",
+        .archive.display(),
+        .limit
+    )]
+    TooManyEntries { archive: PathBuf, limit: u64 },
+
+    #[error(
+        "archive `{}' extracts to more than {} bytes",
+        .archive.display(),
+        .limit
+    )]
+    TooLarge { archive: PathBuf, limit: u64 },
+
+    #[error(
+        "archive `{}' exceeds the maximum compression ratio of {}",
+        .archive.display(),
+        .limit
+    )]
+    CompressionRatio { archive: PathBuf, limit: u64 },
+
     #[error(
         "IO error while extracting file `{}' from archive `{}': {}",
         .file_name.display(),
@@ -142,24 +607,417 @@ pub enum ZipError {
 #[cfg(test)]
 mod test {
     use std::env::current_dir;
+    use std::fs;
+    use std::io::Write;
     use std::path::{Path, PathBuf};
 
+    use assert_matches::assert_matches;
+    use tar::{Builder, EntryType, Header};
     use tempfile::TempDir;
 
-    use super::{common_stem, unzip};
+    use super::*;
+
+    /// The modification time of the entries in test tar archives.
+    const MTIME: u64 = 1_500_000_000;
+
+    /// Build a tar archive from `(name, contents)` pairs, where directories
+    /// have no contents.
+    fn tar(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        tar_with_types(
+            &entries
+                .iter()
+                .map(|&(name, contents)| match contents {
+                    Some(contents) => (name, EntryType::Regular, contents),
+                    None => (name, EntryType::Directory, &b""[..]),
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Build a tar archive from `(name, type, contents)` triples.
+    ///
+    /// Names are written verbatim, so that archives with unsafe paths can be
+    /// built.
+    fn tar_with_types(entries: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        for &(name, entry_type, contents) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(entry_type);
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(MTIME);
+
+            if entry_type == EntryType::Symlink {
+                header.set_link_name("target").unwrap();
+            }
+
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn mtime(path: &Path) -> i64 {
+        FileTime::from_last_modification_time(&fs::metadata(path).unwrap()).unix_seconds()
+    }
+
+    fn compress(format: ArchiveFormat, tar: &[u8]) -> Vec<u8> {
+        match format {
+            ArchiveFormat::Zip => unreachable!(),
+            ArchiveFormat::Tar => tar.to_vec(),
+            ArchiveFormat::TarBz2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            }
+            ArchiveFormat::TarXz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            }
+            ArchiveFormat::TarGz => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_tar() {
+        let archive_dir = TempDir::new().unwrap();
+
+        let flat = tar(&[
+            ("./", None),
+            ("dir/", None),
+            ("dir/test.txt", Some(b"test")),
+            ("empty/", None),
+        ]);
+        let nested = tar(&[
+            ("profile/", None),
+            ("profile/prefs.js", Some(b"prefs")),
+            ("profile/user.js", Some(b"user")),
+        ]);
+
+        for &format in &[
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarBz2,
+            ArchiveFormat::TarXz,
+            ArchiveFormat::TarGz,
+        ] {
+            {
+                let archive = archive_dir.path().join("flat");
+                fs::write(&archive, compress(format, &flat)).unwrap();
+                assert_eq!(ArchiveFormat::detect(&archive).unwrap(), Some(format));
+
+                let tempdir = TempDir::new().unwrap();
+                let stats = extract(&archive, tempdir.path(), &ExtractLimits::default()).unwrap();
+
+                let dir_path = tempdir.path().join("dir");
+                assert!(dir_path.is_dir());
+                assert_eq!(fs::read(dir_path.join("test.txt")).unwrap(), b"test");
+                assert_eq!(mtime(&dir_path.join("test.txt")), MTIME as i64);
+                assert!(tempdir.path().join("empty").is_dir());
+
+                assert_eq!(stats.extracted, 1);
+                assert_eq!(stats.top_level_dir, None);
+            }
+
+            {
+                let archive = archive_dir.path().join("nested");
+                fs::write(&archive, compress(format, &nested)).unwrap();
+
+                let tempdir = TempDir::new().unwrap();
+                let stats = extract(&archive, tempdir.path(), &ExtractLimits::default()).unwrap();
+
+                let profile_dir = tempdir.path().join("profile");
+                assert!(profile_dir.join("prefs.js").is_file());
+                assert!(profile_dir.join("user.js").is_file());
+
+                assert_eq!(stats.extracted, 2);
+                assert_eq!(stats.top_level_dir, Some(PathBuf::from("profile")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_unknown_format() {
+        let archive_dir = TempDir::new().unwrap();
+        let archive = archive_dir.path().join("archive");
+        fs::write(&archive, b"not an archive").unwrap();
+
+        assert_eq!(ArchiveFormat::detect(&archive).unwrap(), None);
+        assert_matches!(
+            extract(&archive, archive_dir.path(), &ExtractLimits::default()),
+            Err(ZipError::UnknownFormat(path)) => {
+                assert_eq!(path, archive);
+            }
+        );
+
+        // Truncated archives are detected but cannot be read.
+        let tar_bz2 = compress(ArchiveFormat::TarBz2, &tar(&[("file", Some(b"contents"))]));
+        fs::write(&archive, &tar_bz2[..tar_bz2.len() / 2]).unwrap();
+
+        assert_eq!(
+            ArchiveFormat::detect(&archive).unwrap(),
+            Some(ArchiveFormat::TarBz2)
+        );
+        assert!(extract(
+            &archive,
+            &archive_dir.path().join("out"),
+            &ExtractLimits::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_extract_unsafe() {
+        let archive_dir = TempDir::new().unwrap();
+        let archive = archive_dir.path().join("archive.tar");
+        let target = archive_dir.path().join("out");
+
+        for name in &[
+            "../evil",
+            "dir/../../evil",
+            "/etc/evil",
+            "C:/evil",
+            "\\evil",
+        ] {
+            fs::write(
+                &archive,
+                tar_with_types(&[(name, EntryType::Regular, b"evil")]),
+            )
+            .unwrap();
+
+            assert_matches!(
+                extract(&archive, &target, &ExtractLimits::default()),
+                Err(ZipError::UnsafePath { entry, .. }) => {
+                    assert_eq!(entry, *name);
+                }
+            );
+        }
+
+        for &entry_type in &[EntryType::Symlink, EntryType::Link] {
+            fs::write(&archive, tar_with_types(&[("link", entry_type, b"")])).unwrap();
+
+            assert_matches!(
+                extract(&archive, &target, &ExtractLimits::default()),
+                Err(ZipError::Link { entry, .. }) => {
+                    assert_eq!(entry, "link");
+                }
+            );
+        }
+
+        assert!(!archive_dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        let archive_dir = TempDir::new().unwrap();
+        let archive = archive_dir.path().join("archive.tar.gz");
+
+        let contents = vec![0u8; 64 * 1024];
+        fs::write(
+            &archive,
+            compress(
+                ArchiveFormat::TarGz,
+                &tar(&[("a", Some(&contents)), ("b", Some(&contents))]),
+            ),
+        )
+        .unwrap();
+
+        let extract_with =
+            |limits: ExtractLimits| extract(&archive, TempDir::new().unwrap().path(), &limits);
+
+        let limits = ExtractLimits {
+            max_compression_ratio: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(extract_with(limits.clone()).unwrap().extracted, 2);
+
+        // Effectively unlimited archives must not overflow the size limits.
+        assert_eq!(
+            extract_with(ExtractLimits {
+                max_size_mb: u64::MAX,
+                ..limits.clone()
+            })
+            .unwrap()
+            .extracted,
+            2
+        );
+
+        assert_matches!(
+            extract_with(ExtractLimits {
+                max_entries: 1,
+                ..limits.clone()
+            }),
+            Err(ZipError::TooManyEntries { limit: 1, .. })
+        );
+
+        assert_matches!(
+            extract_with(ExtractLimits {
+                max_size_mb: 0,
+                ..limits.clone()
+            }),
+            Err(ZipError::TooLarge { limit: 0, .. })
+        );
+
+        // Zeroes compress extremely well.
+        assert_matches!(
+            extract_with(ExtractLimits {
+                max_compression_ratio: 10,
+                ..limits
+            }),
+            Err(ZipError::CompressionRatio { limit: 10, .. })
+        );
+    }
+
+    #[test]
+    fn test_extract_stream() {
+        let name = Path::new("stream");
+        let nested = tar(&[
+            ("profile/", None),
+            ("profile/prefs.js", Some(b"prefs")),
+            ("profile/user.js", Some(b"user")),
+        ]);
+
+        for &format in &[
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarBz2,
+            ArchiveFormat::TarXz,
+            ArchiveFormat::TarGz,
+        ] {
+            let archive = compress(format, &nested);
+            let tempdir = TempDir::new().unwrap();
+
+            let stats = extract_stream(
+                name,
+                &archive[..],
+                archive.len() as u64,
+                tempdir.path(),
+                &ExtractLimits::default(),
+            )
+            .unwrap();
+
+            let profile_dir = tempdir.path().join("profile");
+            assert_eq!(fs::read(profile_dir.join("prefs.js")).unwrap(), b"prefs");
+            assert_eq!(fs::read(profile_dir.join("user.js")).unwrap(), b"user");
+            assert_eq!(mtime(&profile_dir.join("user.js")), MTIME as i64);
+
+            assert_eq!(stats.extracted, 2);
+            assert_eq!(stats.top_level_dir, Some(PathBuf::from("profile")));
+        }
+
+        let tempdir = TempDir::new().unwrap();
+        let zip = fs::read(
+            current_dir()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .join("test")
+                .join("test.zip"),
+        )
+        .unwrap();
+
+        assert_matches!(
+            extract_stream(name, &zip[..], zip.len() as u64, tempdir.path(), &ExtractLimits::default()),
+            Err(ZipError::ZipStream(path)) => {
+                assert_eq!(path, name);
+            }
+        );
+
+        assert_matches!(
+            extract_stream(name, &b"not an archive"[..], 14, tempdir.path(), &ExtractLimits::default()),
+            Err(ZipError::UnknownFormat(path)) => {
+                assert_eq!(path, name);
+            }
+        );
+
+        // The compression ratio is enforced against the given size.
+        let archive = tar(&[("a", Some(&[0u8; 1024]))]);
+        assert_matches!(
+            extract_stream(
+                name,
+                &archive[..],
+                1,
+                tempdir.path(),
+                &ExtractLimits {
+                    max_compression_ratio: 100,
+                    ..Default::default()
+                }
+            ),
+            Err(ZipError::CompressionRatio { limit: 100, .. })
+        );
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(
+            entry_path("foo/bar"),
+            Some(PathBuf::from("foo").join("bar"))
+        );
+        assert_eq!(
+            entry_path("./foo//bar/"),
+            Some(PathBuf::from("foo").join("bar"))
+        );
+        assert_eq!(
+            entry_path("foo\\bar"),
+            Some(PathBuf::from("foo").join("bar"))
+        );
+        assert_eq!(entry_path("./"), Some(PathBuf::new()));
+
+        assert_eq!(entry_path("../foo"), None);
+        assert_eq!(entry_path("foo/../bar"), None);
+        assert_eq!(entry_path("/foo"), None);
+        assert_eq!(entry_path("\\foo"), None);
+        assert_eq!(entry_path("C:foo"), None);
+        assert_eq!(entry_path("foo:stream"), None);
+    }
+
+    #[test]
+    fn test_dos_to_unix_time() {
+        let datetime = |year, month, day, hour, minute, second| {
+            DateTime::from_date_and_time(year, month, day, hour, minute, second).unwrap()
+        };
+
+        assert_eq!(
+            dos_to_unix_time(&datetime(1980, 1, 1, 0, 0, 0)),
+            315_532_800
+        );
+        assert_eq!(
+            dos_to_unix_time(&datetime(2020, 2, 29, 12, 34, 56)),
+            1_582_979_696
+        );
+    }
 
     #[test]
     fn test_zip() {
         let test_dir = current_dir().unwrap().parent().unwrap().join("test");
+        assert_eq!(
+            ArchiveFormat::detect(&test_dir.join("test.zip")).unwrap(),
+            Some(ArchiveFormat::Zip)
+        );
+
         {
             let zip = test_dir.join("test.zip");
             let tempdir = TempDir::new().unwrap();
 
-            let stats = unzip(&zip, tempdir.path()).unwrap();
+            let stats = unzip(&zip, tempdir.path(), &ExtractLimits::default()).unwrap();
 
             let dir_path = tempdir.path().join("dir");
             assert!(dir_path.is_dir());
             assert!(dir_path.join("test.txt").is_file());
+
+            // The modification time is taken from the archive.
+            let mut archive = ZipArchive::new(File::open(&zip).unwrap()).unwrap();
+            let expected_mtime =
+                dos_to_unix_time(&archive.by_name("dir/test.txt").unwrap().last_modified());
+            assert_eq!(mtime(&dir_path.join("test.txt")), expected_mtime);
             assert!(tempdir.path().join("empty").is_dir());
 
             assert_eq!(stats.extracted, 1);
@@ -170,7 +1028,7 @@ mod test {
             let zip = test_dir.join("profile.zip");
             let tempdir = TempDir::new().unwrap();
 
-            let stats = unzip(&zip, tempdir.path()).unwrap();
+            let stats = unzip(&zip, tempdir.path(), &ExtractLimits::default()).unwrap();
 
             assert!(tempdir.path().join("places.sqlite").is_file());
             assert!(tempdir.path().join("prefs.js").is_file());
@@ -184,7 +1042,7 @@ mod test {
             let zip = test_dir.join("profile_nested.zip");
             let tempdir = TempDir::new().unwrap();
 
-            let stats = unzip(&zip, tempdir.path()).unwrap();
+            let stats = unzip(&zip, tempdir.path(), &ExtractLimits::default()).unwrap();
             let profile_dir = tempdir.path().join("profile");
 
             assert!(profile_dir.is_dir());
@@ -7,16 +7,21 @@ use std::cell::RefCell;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use libfxrecord::error::ErrorMessage;
+use libfxrecord::net::{DownloadProgress, NewSessionRequest, SessionState};
 use libfxrecorder::recorder::Recorder;
+use libfxrunner::config::ExtractLimits;
 use libfxrunner::osapi::{CpuTimes, IoCounters, PerfProvider, ShutdownProvider};
+use libfxrunner::registry::SessionRegistry;
 use libfxrunner::session::{
-    NewSessionError, ResumeSessionError, ResumeSessionErrorKind, SessionInfo, SessionManager,
+    NewSessionError, PendingProfile, ResumeSessionError, ResumeSessionErrorKind, SessionInfo,
+    SessionManager,
 };
 use libfxrunner::splash::Splash;
-use libfxrunner::taskcluster::Taskcluster;
+use libfxrunner::taskcluster::{ProgressSender, Taskcluster, BUILD_ARTIFACT_NAME};
 use tempfile::TempDir;
 use tokio::fs;
 
@@ -25,6 +30,12 @@ use crate::util::{firefox_zip_path, test_dir, AssertInvoked};
 /// The only valid session ID for TestSessionManager.
 pub const VALID_SESSION_ID: &str = "REQUESTID";
 
+/// How long sessions in the TestSessionManager's registry take to expire.
+pub const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The free disk space reported by TestPerfProvider.
+pub const FREE_DISK_SPACE: u64 = 64 * 1024 * 1024 * 1024;
+
 #[derive(Debug, Default)]
 pub struct TestShutdownProvider {
     error: Option<&'static str>,
@@ -57,6 +68,7 @@ pub enum TaskclusterFailureMode {
     BadZip,
     NotZip,
     Generic(&'static str),
+    MissingIndex(&'static str),
 }
 
 impl TestTaskcluster {
@@ -71,10 +83,22 @@ impl TestTaskcluster {
 impl Taskcluster for TestTaskcluster {
     type Error = ErrorMessage<&'static str>;
 
+    fn build_artifact_name(&self) -> &str {
+        BUILD_ARTIFACT_NAME
+    }
+
+    async fn find_indexed_task(&mut self, _index_path: &str) -> Result<String, Self::Error> {
+        match self.failure_mode {
+            Some(TaskclusterFailureMode::MissingIndex(e)) => Err(ErrorMessage(e)),
+            _ => Ok("indexed_task_id".into()),
+        }
+    }
+
     async fn download_build_artifact(
         &mut self,
         _task_id: &str,
         download_dir: &Path,
+        progress: ProgressSender,
     ) -> Result<PathBuf, Self::Error> {
         let zip_path = match self.failure_mode {
             Some(TaskclusterFailureMode::Generic(e)) => {
@@ -82,14 +106,22 @@ impl Taskcluster for TestTaskcluster {
             }
             Some(TaskclusterFailureMode::BadZip) => test_dir().join("test.zip"),
             Some(TaskclusterFailureMode::NotZip) => test_dir().join("README.md"),
-            None => firefox_zip_path(),
+            None | Some(TaskclusterFailureMode::MissingIndex(_)) => firefox_zip_path(),
         };
 
         let dest = download_dir.join("firefox.zip");
 
         assert!(zip_path.exists());
 
-        fs::copy(&zip_path, &dest).await.unwrap();
+        let size = fs::copy(&zip_path, &dest).await.unwrap();
+
+        progress
+            .send(DownloadProgress {
+                received: size,
+                total: Some(size),
+                bytes_per_sec: size,
+            })
+            .unwrap();
 
         Ok(dest)
     }
@@ -161,8 +193,6 @@ impl PerfProvider for TestPerfProvider {
     type DiskIoError = ErrorMessage<&'static str>;
     type CpuTimeError = ErrorMessage<&'static str>;
 
-    const ATTEMPT_COUNT: usize = 1;
-
     fn get_disk_io_counters(&self) -> Result<IoCounters, Self::DiskIoError> {
         self.invoked();
 
@@ -196,6 +226,15 @@ impl PerfProvider for TestPerfProvider {
             }
         }
     }
+
+    fn get_free_disk_space(&self, _path: &Path) -> Result<Option<u64>, Self::DiskIoError> {
+        self.invoked();
+
+        match self.failure_mode {
+            Some(PerfFailureMode::DiskIoError(s)) => Err(ErrorMessage(s)),
+            _ => Ok(Some(FREE_DISK_SPACE)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -217,12 +256,16 @@ pub enum SessionFailureMode {
 /// [TestSessionManager]: struct.TestSessionManager.html.
 pub struct TestSessionManagerHandle {
     tempdir: TempDir,
+    registry: SessionRegistry,
     last_session_info: Mutex<Option<SessionInfo<'static>>>,
 }
 
 pub struct TestSessionManager {
     failure_mode: Option<SessionFailureMode>,
 
+    /// The number of runs remaining in sessions resumed by this manager.
+    remaining_runs: u32,
+
     // Internal details of the session manager that need to be kept alive after
     // the `TestSessionMangaer` is consumed.
     handle: Arc<TestSessionManagerHandle>,
@@ -232,15 +275,22 @@ impl TestSessionManagerHandle {
     pub fn last_session_info(&self) -> Option<SessionInfo<'static>> {
         self.last_session_info.lock().unwrap().take()
     }
+
+    pub fn registry(&self) -> &SessionRegistry {
+        &self.registry
+    }
 }
 
 impl Default for TestSessionManager {
     fn default() -> Self {
         let tempdir = TempDir::new().expect("could not create tempdir for TestSessionManager");
+        let registry = SessionRegistry::new(tempdir.path().into(), SESSION_TTL);
         Self {
             failure_mode: None,
+            remaining_runs: 1,
             handle: Arc::new(TestSessionManagerHandle {
                 tempdir,
+                registry,
                 last_session_info: Mutex::new(None),
             }),
         }
@@ -254,9 +304,99 @@ impl TestSessionManager {
         manager
     }
 
+    pub fn with_remaining_runs(remaining_runs: u32) -> Self {
+        let mut manager = Self::default();
+        manager.remaining_runs = remaining_runs;
+        manager
+    }
+
+    /// Create a session manager with a session whose profile transfer was
+    /// interrupted after receiving `received` bytes of the profile archive at
+    /// `profile_path`.
+    pub fn with_pending_profile(
+        pending: &PendingProfile,
+        profile_path: &Path,
+        received: usize,
+    ) -> Self {
+        let manager = Self::default();
+        let session_info = manager.session_info();
+
+        std::fs::create_dir(&session_info.path).unwrap();
+        libfxrunner::zip::unzip(
+            &firefox_zip_path(),
+            &session_info.path,
+            &ExtractLimits::default(),
+        )
+        .unwrap();
+        std::fs::write(session_info.runs_path(), "1").unwrap();
+        std::fs::write(
+            session_info.state_path(),
+            serde_json::to_vec(&SessionState::ProfilePending).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            session_info.pending_profile_path(),
+            serde_json::to_vec(pending).unwrap(),
+        )
+        .unwrap();
+
+        let profile = std::fs::read(profile_path).unwrap();
+        std::fs::write(session_info.profile_archive_path(), &profile[..received]).unwrap();
+
+        let registry = &manager.handle.registry;
+        registry.register(VALID_SESSION_ID).unwrap();
+        registry
+            .update(VALID_SESSION_ID, |record| {
+                record.profile_sha256 = Some(pending.profile.sha256.clone());
+                record.state = SessionState::ProfilePending;
+            })
+            .unwrap();
+
+        manager
+    }
+
+    /// Create a session manager with a queued job for the given request.
+    ///
+    /// If the request has a profile, the archive at `profile_path` is stored
+    /// with the job.
+    pub fn with_queued_job(job: &NewSessionRequest, profile_path: Option<&Path>) -> Self {
+        let manager = Self::default();
+        let session_info = manager.session_info();
+
+        std::fs::create_dir(&session_info.path).unwrap();
+        std::fs::write(session_info.job_path(), serde_json::to_vec(job).unwrap()).unwrap();
+        std::fs::write(
+            session_info.state_path(),
+            serde_json::to_vec(&SessionState::Queued).unwrap(),
+        )
+        .unwrap();
+
+        if let Some(profile_path) = profile_path {
+            std::fs::copy(profile_path, session_info.profile_archive_path()).unwrap();
+        }
+
+        let registry = &manager.handle.registry;
+        registry.register(VALID_SESSION_ID).unwrap();
+        registry
+            .update(VALID_SESSION_ID, |record| {
+                record.profile_sha256 = job.profile.as_ref().map(|p| p.sha256.clone());
+                record.state = SessionState::Queued;
+            })
+            .unwrap();
+
+        manager
+    }
+
     pub fn handle(&self) -> Arc<TestSessionManagerHandle> {
         self.handle.clone()
     }
+
+    fn session_info(&self) -> SessionInfo<'static> {
+        SessionInfo {
+            id: Cow::Borrowed(VALID_SESSION_ID),
+            path: self.handle.tempdir.path().join(VALID_SESSION_ID),
+        }
+    }
 }
 
 #[async_trait]
@@ -267,10 +407,11 @@ impl SessionManager for TestSessionManager {
             _ => {
                 let session_info = SessionInfo {
                     id: Cow::Borrowed(VALID_SESSION_ID),
-                    path: self.handle.tempdir.path().join("session"),
+                    path: self.handle.tempdir.path().join(VALID_SESSION_ID),
                 };
 
                 fs::create_dir(&session_info.path).await.unwrap();
+                self.handle.registry.register(VALID_SESSION_ID).unwrap();
 
                 *self.handle.last_session_info.lock().unwrap() = Some(session_info.clone());
                 Ok(session_info)
@@ -296,7 +437,7 @@ impl SessionManager for TestSessionManager {
 
         let session_info = SessionInfo {
             id: Cow::Borrowed(VALID_SESSION_ID),
-            path: self.handle.tempdir.path().join("session"),
+            path: self.handle.tempdir.path().join(VALID_SESSION_ID),
         };
 
         fs::create_dir(&session_info.path).await.unwrap();
@@ -304,12 +445,60 @@ impl SessionManager for TestSessionManager {
             .await
             .unwrap();
 
-        libfxrunner::zip::unzip(&firefox_zip_path(), &session_info.path).unwrap();
+        libfxrunner::zip::unzip(
+            &firefox_zip_path(),
+            &session_info.path,
+            &ExtractLimits::default(),
+        )
+        .unwrap();
+        session_info
+            .set_remaining_runs(self.remaining_runs)
+            .await
+            .unwrap();
+
+        *self.handle.last_session_info.lock().unwrap() = Some(session_info.clone());
+        Ok(session_info)
+    }
+
+    async fn resume_profile<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError> {
+        if session_id != VALID_SESSION_ID {
+            return Err(ResumeSessionError {
+                session_id: session_id.into(),
+                kind: ResumeSessionErrorKind::InvalidId,
+            });
+        }
+
+        let session_info = self.session_info();
+        if !session_info.pending_profile_path().exists() {
+            return Err(ResumeSessionError {
+                session_id: session_id.into(),
+                kind: ResumeSessionErrorKind::NoPendingProfile,
+            });
+        }
 
         *self.handle.last_session_info.lock().unwrap() = Some(session_info.clone());
         Ok(session_info)
     }
 
+    async fn start_job<'a>(
+        &self,
+        session_id: &'a str,
+    ) -> Result<SessionInfo<'a>, ResumeSessionError> {
+        if session_id != VALID_SESSION_ID {
+            return Err(ResumeSessionError {
+                session_id: session_id.into(),
+                kind: ResumeSessionErrorKind::InvalidId,
+            });
+        }
+
+        let session_info = self.session_info();
+        *self.handle.last_session_info.lock().unwrap() = Some(session_info.clone());
+        Ok(session_info)
+    }
+
     async fn ensure_valid_profile_dir<'a>(
         &self,
         session_info: &SessionInfo<'a>,
@@ -334,11 +523,16 @@ impl SessionManager for TestSessionManager {
         fs::create_dir(&profile_path).await.unwrap();
         Ok(profile_path)
     }
+
+    fn registry(&self) -> &SessionRegistry {
+        &self.handle.registry
+    }
 }
 
 fn clone_new_session_err(err: &NewSessionError) -> NewSessionError {
     match err {
         NewSessionError::TooManyAttempts(a) => NewSessionError::TooManyAttempts(*a),
+        NewSessionError::Registry(..) => unimplemented!("registry errors are not mocked"),
         NewSessionError::Io(inner) => {
             // io::Error does not impl Clone, so we do a *good enough* clone. In
             // practice, since we are only going to be testing this with custom
@@ -7,20 +7,22 @@ mod mocks;
 mod util;
 
 use std::convert::TryInto;
-use std::fs::File;
+use std::fs::{self, File};
 use std::future::Future;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use assert_matches::assert_matches;
 use futures::join;
 use indoc::indoc;
 use libfxrecord::net::*;
-use libfxrecorder::proto::{RecorderProto, RecorderProtoError};
-use libfxrunner::config::Size;
+use libfxrecorder::proto::{Build, RecorderProto, RecorderProtoError};
+use libfxrunner::cache::BuildCache;
+use libfxrunner::config::{DiskTolerance, ExtractLimits, IdlePolicy, Size};
 use libfxrunner::osapi::WaitForIdleError;
 use libfxrunner::proto::{RunnerProto, RunnerProtoError};
 use libfxrunner::session::{
-    NewSessionError, ResumeSessionError, ResumeSessionErrorKind, SessionInfo,
+    NewSessionError, PendingProfile, ResumeSessionError, ResumeSessionErrorKind, SessionInfo,
 };
 use libfxrunner::zip::ZipError;
 use serde_json::{json, Value};
@@ -45,6 +47,78 @@ type TestRecorderProto = RecorderProto<TestRecorder>;
 
 const DISPLAY_SIZE: Size = Size { x: 640, y: 480 };
 
+/// A build identified by its task ID.
+fn task_build() -> Build {
+    BuildSource::TaskId("task_id".into()).into()
+}
+
+/// An idle policy that takes a single sample without delay.
+fn test_idle_policy() -> IdlePolicy {
+    IdlePolicy {
+        sample_interval_ms: 0,
+        timeout_ms: 0,
+        ..Default::default()
+    }
+}
+
+/// Runner settings that are not provided by mocks.
+struct RunnerSettings {
+    idle_policy: IdlePolicy,
+    build_cache: Option<BuildCache>,
+    extract_limits: ExtractLimits,
+    runner_build_dir: Option<PathBuf>,
+    started: Instant,
+}
+
+impl Default for RunnerSettings {
+    fn default() -> Self {
+        RunnerSettings {
+            idle_policy: test_idle_policy(),
+            build_cache: None,
+            extract_limits: ExtractLimits::default(),
+            runner_build_dir: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl RunnerSettings {
+    fn with_idle_policy(idle_policy: IdlePolicy) -> Self {
+        RunnerSettings {
+            idle_policy,
+            ..Default::default()
+        }
+    }
+
+    fn with_build_cache(build_cache: BuildCache) -> Self {
+        RunnerSettings {
+            build_cache: Some(build_cache),
+            ..Default::default()
+        }
+    }
+
+    fn with_extract_limits(extract_limits: ExtractLimits) -> Self {
+        RunnerSettings {
+            extract_limits,
+            ..Default::default()
+        }
+    }
+
+    fn with_runner_build_dir(runner_build_dir: PathBuf) -> Self {
+        RunnerSettings {
+            runner_build_dir: Some(runner_build_dir),
+            ..Default::default()
+        }
+    }
+
+    fn started_at(started: Instant) -> Self {
+        RunnerSettings {
+            started,
+            ..Default::default()
+        }
+    }
+}
+
 struct RunnerInfo {
     result: Result<bool, TestRunnerProtoError>,
     session_info: Option<SessionInfo<'static>>,
@@ -61,6 +135,34 @@ async fn run_proto_test<'a, Fut>(
     runner_fn: impl FnOnce(RunnerInfo),
 ) where
     Fut: Future<Output = ()>,
+{
+    run_proto_test_with_settings(
+        listener,
+        shutdown_provider,
+        tc,
+        perf_provider,
+        RunnerSettings::default(),
+        session_manager,
+        recorder_fn,
+        runner_fn,
+    )
+    .await
+}
+
+/// Run a test with both the recorder and runner protocols, where the runner
+/// uses the given settings.
+#[allow(clippy::too_many_arguments)]
+async fn run_proto_test_with_settings<'a, Fut>(
+    listener: &mut TcpListener,
+    shutdown_provider: TestShutdownProvider,
+    tc: TestTaskcluster,
+    perf_provider: TestPerfProvider,
+    settings: RunnerSettings,
+    session_manager: TestSessionManager,
+    recorder_fn: impl FnOnce(TestRecorderProto, PathBuf) -> Fut,
+    runner_fn: impl FnOnce(RunnerInfo),
+) where
+    Fut: Future<Output = ()>,
 {
     let addr = listener.local_addr().unwrap();
 
@@ -78,7 +180,12 @@ async fn run_proto_test<'a, Fut>(
             shutdown_provider,
             tc,
             perf_provider,
+            settings.idle_policy,
             session_manager,
+            settings.build_cache,
+            settings.extract_limits,
+            settings.runner_build_dir,
+            settings.started,
         )
         .await;
 
@@ -112,7 +219,10 @@ async fn test_new_session_ok() {
         TestSessionManager::default(),
         |mut recorder, _tempdir| async move {
             assert_eq!(
-                recorder.new_session("task_id", None, &[]).await.unwrap(),
+                recorder
+                    .new_session(&task_build(), 1, None, &[])
+                    .await
+                    .unwrap(),
                 VALID_SESSION_ID
             );
         },
@@ -148,6 +258,8 @@ async fn test_new_session_ok() {
             let profile_dir = session_info.profile_path();
             assert!(profile_dir.is_dir());
             assert!(directory_is_empty(&profile_dir));
+
+            assert_file_contents_eq(&session_info.runs_path(), "1");
         },
     )
     .await;
@@ -161,7 +273,7 @@ async fn test_new_session_ok() {
         |mut recorder, _tempdir| async move {
             assert_eq!(
                 recorder
-                    .new_session("task_id", Some(&test_dir().join("profile.zip")), &[])
+                    .new_session(&task_build(), 1, Some(&test_dir().join("profile.zip")), &[])
                     .await
                     .unwrap(),
                 VALID_SESSION_ID
@@ -192,7 +304,8 @@ async fn test_new_session_ok() {
         |mut recorder, _tempdir| async move {
             let session_id = recorder
                 .new_session(
-                    "task_id",
+                    &task_build(),
+                    1,
                     Some(&test_dir().join("profile.zip")),
                     &[
                         (
@@ -241,7 +354,8 @@ async fn test_new_session_ok() {
         |mut recorder, _tempdir| async move {
             let session_id = recorder
                 .new_session(
-                    "task_id",
+                    &task_build(),
+                    1,
                     None,
                     &[
                         (
@@ -255,27 +369,1330 @@ async fn test_new_session_ok() {
                 .await
                 .unwrap();
 
-            assert_eq!(session_id, VALID_SESSION_ID);
+            assert_eq!(session_id, VALID_SESSION_ID);
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let session_info = session_info.unwrap();
+            assert!(session_info.firefox_path().is_file());
+
+            let profile_dir = session_info.profile_path();
+            assert_file_contents_eq(
+                &profile_dir.join("user.js"),
+                indoc!(
+                    r#"pref("foo", "bar");
+                    pref("bar", true);
+                    pref("baz", 1);
+                    "#
+                ),
+            );
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_session_cached() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let build_cache = BuildCache::new(cache_dir.path().into(), 1024 * 1024 * 1024);
+
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_build_cache(build_cache.clone()),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            recorder
+                .new_session(&task_build(), 1, None, &[])
+                .await
+                .unwrap();
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), true);
+        },
+    )
+    .await;
+
+    // The build is not downloaded again.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::default(),
+        RunnerSettings::with_build_cache(build_cache),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            recorder
+                .new_session(&task_build(), 1, None, &[])
+                .await
+                .unwrap();
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+            assert!(session_info.unwrap().firefox_path().is_file());
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_session_index() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    for build in &[
+        BuildSource::revision(DEFAULT_BRANCH, "abcdef"),
+        BuildSource::latest(DEFAULT_BRANCH),
+    ] {
+        run_proto_test(
+            &mut listener,
+            TestShutdownProvider::default(),
+            TestTaskcluster::default(),
+            TestPerfProvider::default(),
+            TestSessionManager::default(),
+            |mut recorder, _tempdir| async move {
+                assert_eq!(
+                    recorder
+                        .new_session(&build.clone().into(), 1, None, &[])
+                        .await
+                        .unwrap(),
+                    VALID_SESSION_ID
+                );
+            },
+            |RunnerInfo {
+                 result,
+                 session_info,
+             }| {
+                assert_eq!(result.unwrap(), true);
+                assert!(session_info.unwrap().firefox_path().is_file());
+            },
+        )
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn test_new_session_sideload() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .new_session(
+                        &Build::Sideload(firefox_zip_path()),
+                        1,
+                        Some(&test_dir().join("profile.zip")),
+                        &[]
+                    )
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let session_info = session_info.unwrap();
+            assert!(session_info.firefox_path().is_file());
+            assert_populated_profile(&session_info.profile_path());
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_session_runner_path() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let build_dir = firefox_zip_path().parent().unwrap().to_owned();
+
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::default(),
+        RunnerSettings::with_runner_build_dir(build_dir.clone()),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            let build = BuildSource::RunnerPath(firefox_zip_path().to_str().unwrap().into());
+
+            assert_eq!(
+                recorder
+                    .new_session(&build.into(), 1, None, &[])
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+            assert!(session_info.unwrap().firefox_path().is_file());
+            assert!(firefox_zip_path().is_file());
+        },
+    )
+    .await;
+
+    let missing_path = build_dir.join("missing.zip");
+
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_runner_build_dir(build_dir.clone()),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| {
+            let missing_path = missing_path.clone();
+            async move {
+                let build = BuildSource::RunnerPath(missing_path.to_str().unwrap().into());
+
+                assert_matches!(
+                    recorder
+                        .new_session(&build.into(), 1, None, &[])
+                        .await
+                        .unwrap_err(),
+                    RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                        assert_eq!(
+                            e.to_string(),
+                            TestRunnerProtoError::MissingBuild(missing_path).to_string()
+                        );
+                    }
+                );
+            }
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(result.unwrap_err(), RunnerProtoError::MissingBuild(..));
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+
+    let outside_path = test_dir().join("profile.zip");
+
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_runner_build_dir(build_dir.clone()),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| {
+            let outside_path = outside_path.clone();
+            async move {
+                let build = BuildSource::RunnerPath(outside_path.to_str().unwrap().into());
+
+                assert_matches!(
+                    recorder
+                        .new_session(&build.into(), 1, None, &[])
+                        .await
+                        .unwrap_err(),
+                    RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                        assert_eq!(
+                            e.to_string(),
+                            TestRunnerProtoError::RunnerBuildOutsideDir(outside_path).to_string()
+                        );
+                    }
+                );
+            }
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::RunnerBuildOutsideDir(..)
+            );
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+
+    // Builds on the runner cannot be used without a build directory.
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            let build = BuildSource::RunnerPath(firefox_zip_path().to_str().unwrap().into());
+
+            assert_matches!(
+                recorder
+                    .new_session(&build.into(), 1, None, &[])
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(
+                        e.to_string(),
+                        TestRunnerProtoError::RunnerBuildsDisabled.to_string()
+                    );
+                }
+            );
+        },
+        |RunnerInfo { result, .. }| {
+            assert_matches!(result.unwrap_err(), RunnerProtoError::RunnerBuildsDisabled);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_session_profile_stream() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .new_session(
+                        &task_build(),
+                        1,
+                        Some(&test_dir().join("profile_nested.tar.gz")),
+                        &[("foo".into(), Value::Bool(true).try_into().unwrap())],
+                    )
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let session_info = session_info.unwrap();
+            let profile_dir = session_info.profile_path();
+            assert_populated_profile(&profile_dir);
+            assert_file_contents_eq(&profile_dir.join("user.js"), "pref(\"foo\", true);\n");
+
+            // The nested directory is installed as the profile.
+            assert!(!profile_dir.join("profile").exists());
+
+            // The profile is never written to disk as an archive.
+            assert!(!session_info.path.join("profile.zip").exists());
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .new_session(
+                        &task_build(),
+                        1,
+                        Some(&test_dir().join("profile_nested.zip")),
+                        &[],
+                    )
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let profile_dir = session_info.unwrap().profile_path();
+            assert_populated_profile(&profile_dir);
+            assert!(!profile_dir.join("profile").exists());
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_new_session_profile_interrupted() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (runner_logger, _) = build_test_loggers();
+    let session_manager = TestSessionManager::default();
+    let handle = session_manager.handle();
+
+    let runner = async {
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let result = TestRunnerProto::handle_request(
+            runner_logger,
+            DISPLAY_SIZE,
+            stream,
+            TestShutdownProvider::default(),
+            TestTaskcluster::default(),
+            TestPerfProvider::asserting_not_invoked(),
+            test_idle_policy(),
+            session_manager,
+            None,
+            ExtractLimits::default(),
+            None,
+            Instant::now(),
+        )
+        .await;
+
+        assert_matches!(
+            result.unwrap_err(),
+            RunnerProtoError::Proto(ProtoError::EndOfStream)
+        );
+
+        // The session is kept so that the transfer can be resumed.
+        let session_info = handle.last_session_info().unwrap();
+        assert!(session_info.firefox_path().is_file());
+        assert!(session_info.pending_profile_path().is_file());
+        assert_session_state(&session_info, SessionState::ProfilePending);
+        assert_eq!(
+            fs::metadata(session_info.profile_archive_path())
+                .unwrap()
+                .len(),
+            100
+        );
+        assert_eq!(
+            handle
+                .registry()
+                .get(VALID_SESSION_ID)
+                .unwrap()
+                .unwrap()
+                .state,
+            SessionState::ProfilePending
+        );
+    };
+
+    let recorder = async {
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let mut proto: Proto<
+            RunnerMessage,
+            RecorderMessage,
+            RunnerMessageKind,
+            RecorderMessageKind,
+        > = Proto::new(stream);
+
+        proto
+            .send(Handshake {
+                version: VersionInfo::new("fxrecorder", "0.0.0"),
+            })
+            .await
+            .unwrap();
+        proto
+            .recv::<HandshakeResponse>()
+            .await
+            .unwrap()
+            .result
+            .unwrap();
+
+        let profile_path = test_dir().join("profile.zip");
+        proto
+            .send::<Session>(
+                NewSessionRequest {
+                    build: BuildSource::TaskId("task_id".into()),
+                    profile: Some(profile_info(&profile_path)),
+                    runs: 1,
+                    prefs: vec![],
+                }
+                .into(),
+            )
+            .await
+            .unwrap();
+
+        proto
+            .recv::<NewSessionResponse>()
+            .await
+            .unwrap()
+            .session_id
+            .unwrap();
+        proto.recv::<ResolveBuild>().await.unwrap().result.unwrap();
+        while proto.recv::<DownloadBuild>().await.unwrap().result.unwrap()
+            != DownloadStatus::Extracted
+        {}
+        proto
+            .recv::<DisableUpdates>()
+            .await
+            .unwrap()
+            .result
+            .unwrap();
+        assert_eq!(
+            proto.recv::<RecvProfile>().await.unwrap().result.unwrap(),
+            DownloadStatus::Downloading
+        );
+
+        proto
+            .send(ProfileChunk {
+                offset: 0,
+                data: fs::read(&profile_path).unwrap()[..100].to_vec(),
+            })
+            .await
+            .unwrap();
+    };
+
+    join!(runner, recorder);
+}
+
+#[tokio::test]
+async fn test_session_registry() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let session_manager = TestSessionManager::default();
+    let handle = session_manager.handle();
+    let profile_path = test_dir().join("profile.zip");
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            recorder
+                .new_session(&task_build(), 1, Some(&test_dir().join("profile.zip")), &[])
+                .await
+                .unwrap();
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+            assert_session_state(&session_info.unwrap(), SessionState::RestartPending);
+        },
+    )
+    .await;
+
+    let record = handle.registry().get(VALID_SESSION_ID).unwrap().unwrap();
+    assert_eq!(record.state, SessionState::RestartPending);
+    assert_eq!(record.build.as_deref(), Some("task_id"));
+    assert_eq!(
+        record.profile_sha256,
+        Some(profile_info(&profile_path).sha256)
+    );
+    assert_eq!(record.owner.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn test_query_session() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![],
+    };
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::with_pending_profile(&pending, &profile_path, 100),
+        |mut recorder, _tempdir| async move {
+            let summary = recorder
+                .query_session(VALID_SESSION_ID)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(summary.session_id, VALID_SESSION_ID);
+            assert_eq!(summary.state, SessionState::ProfilePending);
+            assert_eq!(
+                summary.profile_sha256,
+                Some(profile_info(&test_dir().join("profile.zip")).sha256)
+            );
+            assert_eq!(summary.expires, summary.updated + SESSION_TTL.as_secs());
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            // Querying a session neither restarts the runner nor touches the
+            // session.
+            assert_eq!(result.unwrap(), false);
+            assert!(session_info.is_none());
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder.query_session(VALID_SESSION_ID).await.unwrap(),
+                None
+            );
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_list_sessions() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![],
+    };
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::with_pending_profile(&pending, &profile_path, 100),
+        |mut recorder, _tempdir| async move {
+            let sessions = recorder.list_sessions().await.unwrap();
+
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].session_id, VALID_SESSION_ID);
+            assert_eq!(sessions[0].state, SessionState::ProfilePending);
+
+            // The session contains the extracted build and part of the profile.
+            assert!(sessions[0].size > 100);
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(recorder.list_sessions().await.unwrap(), vec![]);
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_delete_session() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![],
+    };
+    let session_manager = TestSessionManager::with_pending_profile(&pending, &profile_path, 100);
+    let handle = session_manager.handle();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            recorder.delete_session(VALID_SESSION_ID).await.unwrap();
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+
+    assert!(handle.registry().summaries().unwrap().is_empty());
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder.delete_session(VALID_SESSION_ID).await.unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(
+                        e.to_string(),
+                        format!("Session `{}' does not exist", VALID_SESSION_ID)
+                    );
+                }
+            );
+        },
+        |RunnerInfo { result, .. }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::NoSuchSession(session_id) => {
+                    assert_eq!(session_id, VALID_SESSION_ID);
+                }
+            );
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_purge_sessions() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![],
+    };
+    let session_manager = TestSessionManager::with_pending_profile(&pending, &profile_path, 100);
+    let handle = session_manager.handle();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder.purge_sessions().await.unwrap(),
+                vec![VALID_SESSION_ID.to_owned()]
+            );
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+
+    assert!(handle.registry().summaries().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_status() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![],
+    };
+    let session_manager = TestSessionManager::with_pending_profile(&pending, &profile_path, 100);
+
+    let readings = IdleReadings {
+        cpu_idle: 0.99,
+        disks: vec![],
+        samples: 1,
+        elapsed_ms: 0,
+    };
+    session_manager
+        .handle()
+        .registry()
+        .record_idle(VALID_SESSION_ID, readings.clone())
+        .unwrap();
+
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_invoked(),
+        RunnerSettings::started_at(Instant::now() - Duration::from_secs(60)),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            let status = recorder.status().await.unwrap();
+
+            assert_eq!(status.version.name, "fxrunner");
+            assert_eq!(status.version.protocol_version, PROTOCOL_VERSION);
+            assert!(status.uptime_secs >= 60);
+            assert_eq!(status.free_disk, Some(FREE_DISK_SPACE));
+
+            let current_session = status.current_session.unwrap();
+            assert_eq!(current_session.session_id, VALID_SESSION_ID);
+            assert_eq!(current_session.state, SessionState::ProfilePending);
+
+            let last_idle = status.last_idle.unwrap();
+            assert_eq!(last_idle.session_id, VALID_SESSION_ID);
+            assert_eq!(last_idle.readings, readings);
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            // Status requests do not start a session.
+            assert_eq!(result.unwrap(), false);
+            assert!(session_info.is_none());
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            let status = recorder.status().await.unwrap();
+
+            assert_eq!(status.current_session, None);
+            assert_eq!(status.last_idle, None);
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::with_failure(PerfFailureMode::DiskIoError("could not stat disk")),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            // The status is reported without the free disk space.
+            let status = recorder.status().await.unwrap();
+
+            assert_eq!(status.free_disk, None);
+            assert_eq!(status.current_session, None);
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_resume_profile() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let profile_path = test_dir().join("profile.zip");
+
+    let pending = PendingProfile {
+        profile: profile_info(&profile_path),
+        prefs: vec![("foo".into(), Value::Bool(true).try_into().unwrap())],
+    };
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::default(),
+        TestSessionManager::with_pending_profile(&pending, &profile_path, 100),
+        |mut recorder, _tempdir| async move {
+            recorder
+                .resume_profile(VALID_SESSION_ID, &test_dir().join("profile.zip"))
+                .await
+                .unwrap();
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let session_info = session_info.unwrap();
+            let profile_dir = session_info.profile_path();
+            assert_populated_profile(&profile_dir);
+            assert_file_contents_eq(&profile_dir.join("user.js"), "pref(\"foo\", true);\n");
+            assert!(!session_info.pending_profile_path().exists());
+        },
+    )
+    .await;
+
+    // The archive is verified against the digest in the original request.
+    let pending = PendingProfile {
+        profile: ProfileInfo {
+            sha256: "0".repeat(64),
+            ..profile_info(&profile_path)
+        },
+        prefs: vec![],
+    };
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::with_pending_profile(&pending, &profile_path, 100),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .resume_profile(VALID_SESSION_ID, &test_dir().join("profile.zip"))
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert!(e.to_string().starts_with("Profile checksum mismatch"));
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::ProfileChecksum { expected, .. } => {
+                    assert_eq!(expected, "0".repeat(64));
+                }
+            );
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .resume_profile(VALID_SESSION_ID, &test_dir().join("profile.zip"))
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(
+                        e.to_string(),
+                        ResumeSessionError {
+                            session_id: VALID_SESSION_ID.into(),
+                            kind: ResumeSessionErrorKind::NoPendingProfile,
+                        }
+                        .to_string()
+                    );
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::ResumeSession(ResumeSessionError {
+                    kind: ResumeSessionErrorKind::NoPendingProfile,
+                    ..
+                })
+            );
+            assert!(session_info.is_none());
+        },
+    )
+    .await;
+}
+
+/// Start the next queued job with the given session manager.
+async fn start_next_job(
+    tc: TestTaskcluster,
+    session_manager: TestSessionManager,
+) -> Result<bool, TestRunnerProtoError> {
+    let (runner_logger, _) = build_test_loggers();
+    let settings = RunnerSettings::default();
+
+    TestRunnerProto::start_next_job(
+        runner_logger,
+        DISPLAY_SIZE,
+        TestShutdownProvider::default(),
+        tc,
+        TestPerfProvider::default(),
+        settings.idle_policy,
+        session_manager,
+        settings.build_cache,
+        settings.extract_limits,
+        settings.runner_build_dir,
+        settings.started,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_submit_job() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let session_manager = TestSessionManager::default();
+    let handle = session_manager.handle();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::asserting_not_invoked(),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .submit_job(&BuildSource::TaskId("task_id".into()), 2, None, &[])
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            // Submitting a job does not start it.
+            assert_eq!(result.unwrap(), false);
+
+            let session_info = session_info.unwrap();
+            assert_session_state(&session_info, SessionState::Queued);
+            assert!(session_info.job_path().is_file());
+            assert!(!session_info.firefox_path().exists());
+        },
+    )
+    .await;
+
+    let record = handle.registry().get(VALID_SESSION_ID).unwrap().unwrap();
+    assert_eq!(record.state, SessionState::Queued);
+    assert_eq!(record.owner.as_deref(), Some("127.0.0.1"));
+
+    let profile_path = test_dir().join("profile.zip");
+    let session_manager = TestSessionManager::default();
+    let handle = session_manager.handle();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("build was downloaded")),
+        TestPerfProvider::asserting_not_invoked(),
+        session_manager,
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .submit_job(
+                        &BuildSource::TaskId("task_id".into()),
+                        1,
+                        Some(&test_dir().join("profile.zip")),
+                        &[],
+                    )
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), false);
+
+            let session_info = session_info.unwrap();
+            assert_session_state(&session_info, SessionState::Queued);
+            assert!(session_info.profile_archive_path().is_file());
+            assert!(!session_info.profile_path().exists());
+        },
+    )
+    .await;
+
+    let record = handle.registry().get(VALID_SESSION_ID).unwrap().unwrap();
+    assert_eq!(record.state, SessionState::Queued);
+    assert_eq!(
+        record.profile_sha256,
+        Some(profile_info(&profile_path).sha256)
+    );
+}
+
+#[tokio::test]
+async fn test_submit_job_err() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .submit_job(&BuildSource::TaskId("task_id".into()), 0, None, &[])
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(e.to_string(), TestRunnerProtoError::NoRuns.to_string());
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(result.unwrap_err(), RunnerProtoError::NoRuns);
+            assert!(session_info.is_none());
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::asserting_not_invoked(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .submit_job(
+                        &BuildSource::TaskId("task_id".into()),
+                        1,
+                        Some(&test_dir().join("README.md")),
+                        &[],
+                    )
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(..))
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert!(result.is_err());
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_start_next_job() {
+    assert_eq!(
+        start_next_job(TestTaskcluster::default(), TestSessionManager::default())
+            .await
+            .unwrap(),
+        false
+    );
+
+    let job = NewSessionRequest {
+        build: BuildSource::TaskId("task_id".into()),
+        profile: None,
+        runs: 2,
+        prefs: vec![("foo".into(), Value::Bool(true).try_into().unwrap())],
+    };
+    let session_manager = TestSessionManager::with_queued_job(&job, None);
+    let handle = session_manager.handle();
+
+    assert_eq!(
+        start_next_job(TestTaskcluster::default(), session_manager)
+            .await
+            .unwrap(),
+        true
+    );
+
+    let session_info = handle.last_session_info().unwrap();
+    assert_session_state(&session_info, SessionState::RestartPending);
+    assert!(session_info.firefox_path().is_file());
+    assert!(session_info.profile_path().is_dir());
+    assert_file_contents_eq(&session_info.runs_path(), "2");
+    assert_file_contents_eq(
+        &session_info.profile_path().join("user.js"),
+        "pref(\"foo\", true);\n",
+    );
+
+    let record = handle.registry().get(VALID_SESSION_ID).unwrap().unwrap();
+    assert_eq!(record.state, SessionState::RestartPending);
+    assert_eq!(record.build.as_deref(), Some("task_id"));
+
+    let profile_path = test_dir().join("profile.zip");
+    let job = NewSessionRequest {
+        build: BuildSource::TaskId("task_id".into()),
+        profile: Some(profile_info(&profile_path)),
+        runs: 1,
+        prefs: vec![],
+    };
+    let session_manager = TestSessionManager::with_queued_job(&job, Some(&profile_path));
+    let handle = session_manager.handle();
+
+    assert_eq!(
+        start_next_job(TestTaskcluster::default(), session_manager)
+            .await
+            .unwrap(),
+        true
+    );
+
+    let session_info = handle.last_session_info().unwrap();
+    assert_session_state(&session_info, SessionState::RestartPending);
+    assert_populated_profile(&session_info.profile_path());
+
+    let job = NewSessionRequest {
+        build: BuildSource::TaskId("task_id".into()),
+        profile: None,
+        runs: 1,
+        prefs: vec![],
+    };
+    let session_manager = TestSessionManager::with_queued_job(&job, None);
+    let handle = session_manager.handle();
+
+    assert_matches!(
+        start_next_job(
+            TestTaskcluster::with_failure(TaskclusterFailureMode::Generic("404 Not Found")),
+            session_manager,
+        )
+        .await
+        .unwrap_err(),
+        RunnerProtoError::Taskcluster(..)
+    );
+
+    let session_info = handle.last_session_info().unwrap();
+    assert_session_state(&session_info, SessionState::Failed);
+
+    let record = handle.registry().get(VALID_SESSION_ID).unwrap().unwrap();
+    assert_eq!(record.state, SessionState::Failed);
+    assert_eq!(record.error.as_deref(), Some("404 Not Found"));
+
+    // Failed jobs are not retried.
+    assert_eq!(handle.registry().next_job().unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_new_session_err_extract_limits() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    // The build has two entries, but the profile has three.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_extract_limits(ExtractLimits {
+            max_entries: 2,
+            ..Default::default()
+        }),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .new_session(&task_build(), 1, Some(&test_dir().join("profile.zip")), &[])
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert!(e.to_string().ends_with("contains more than 2 entries"));
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::Zip(ZipError::TooManyEntries { limit: 2, .. })
+            );
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+
+    // Sideloaded builds are rejected before they are received.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_extract_limits(ExtractLimits {
+            max_size_mb: 0,
+            ..Default::default()
+        }),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .new_session(&Build::Sideload(firefox_zip_path()), 1, None, &[])
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert!(e.to_string().ends_with("more than the limit of 0 bytes"));
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::BuildTooLarge { limit: 0, .. }
+            );
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+
+    // Streamed profiles are still received in full when extraction fails, so
+    // the error is reported to the recorder.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_extract_limits(ExtractLimits {
+            max_entries: 2,
+            ..Default::default()
+        }),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .new_session(
+                        &task_build(),
+                        1,
+                        Some(&test_dir().join("profile_nested.tar.gz")),
+                        &[]
+                    )
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(
+                        e.to_string(),
+                        "archive `profile stream' contains more than 2 entries"
+                    );
+                }
+            );
         },
         |RunnerInfo {
              result,
              session_info,
          }| {
-            assert_eq!(result.unwrap(), true);
-
-            let session_info = session_info.unwrap();
-            assert!(session_info.firefox_path().is_file());
-
-            let profile_dir = session_info.profile_path();
-            assert_file_contents_eq(
-                &profile_dir.join("user.js"),
-                indoc!(
-                    r#"pref("foo", "bar");
-                    pref("bar", true);
-                    pref("baz", 1);
-                    "#
-                ),
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::Zip(ZipError::TooManyEntries { limit: 2, .. })
             );
+            assert!(!session_info.unwrap().path.exists());
         },
     )
     .await;
@@ -295,7 +1712,7 @@ async fn test_new_session_err_request_manager() {
         )),
         |mut recorder, _tempdir| async move {
             assert_matches!(
-                recorder.new_session("task_id", None, &[]).await.unwrap_err(),
+                recorder.new_session(&task_build(), 1, None, &[]).await.unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
                     assert_eq!(
                         e.to_string(),
@@ -327,7 +1744,7 @@ async fn test_new_session_err_request_manager() {
         )),
         |mut recorder, _tempdir| async move {
             assert_matches!(
-                recorder.new_session("task_id", None, &[]).await.unwrap_err(),
+                recorder.new_session(&task_build(), 1, None, &[]).await.unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
                     assert_eq!(
                         e.to_string(),
@@ -367,7 +1784,7 @@ async fn test_new_session_err_downloadbuild() {
         |mut recorder, _tempdir| async move {
             assert_matches!(
                 recorder
-                    .new_session("task_id", None, &[])
+                    .new_session(&task_build(), 1, None, &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
@@ -398,7 +1815,7 @@ async fn test_new_session_err_downloadbuild() {
         |mut recorder, _tempdir| async move {
             assert_matches!(
                 recorder
-                    .new_session("task_id", None, &[])
+                    .new_session(&task_build(), 1, None, &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
@@ -427,35 +1844,69 @@ async fn test_new_session_err_downloadbuild() {
     run_proto_test(
         &mut listener,
         TestShutdownProvider::default(),
-        TestTaskcluster::with_failure(TaskclusterFailureMode::NotZip),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::MissingIndex("index not found")),
         TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder
+                    .new_session(&BuildSource::latest(DEFAULT_BRANCH).into(), 1, None, &[])
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                     assert_eq!(e.to_string(), "index not found");
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::Taskcluster(e) => {
+                    assert_eq!(e.to_string(), "index not found");
+                }
+            );
+
+            let session_info = session_info.unwrap();
+            assert_eq!(session_info.id, VALID_SESSION_ID);
+            assert!(!session_info.path.exists());
+        },
+    )
+    .await;
 
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::with_failure(TaskclusterFailureMode::NotZip),
+        TestPerfProvider::default(),
         TestSessionManager::default(),
         |mut recorder, _tempdir| async move {
             assert_matches!(
                 recorder
-                    .new_session("task_id", None, &[])
+                    .new_session(&task_build(), 1, None, &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
-                    let msg = e.to_string();
-                    assert!(msg.starts_with("could not read zip archive"));
-                    assert!(msg.ends_with("Invalid Zip archive: Could not find central directory end"));
+                    assert!(e.to_string().starts_with("unrecognized archive format"));
                 }
             );
         },
-        |RunnerInfo { result, session_info }| {
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
             let session_info = session_info.unwrap();
             assert_eq!(session_info.id, VALID_SESSION_ID);
 
             assert_matches!(
                 result.unwrap_err(),
-                RunnerProtoError::Zip(e @ ZipError::ReadArchive{ .. }) => {
-
+                RunnerProtoError::Zip(e @ ZipError::UnknownFormat(..)) => {
                     assert_eq!(
                         e.to_string(),
                         format!(
-                            "could not read zip archive `{}': Invalid Zip archive: Could not find central directory end",
+                            "unrecognized archive format: `{}'",
                             session_info.path.join("firefox.zip").display()
                         )
                     );
@@ -477,34 +1928,32 @@ async fn test_new_session_err_recvprofile() {
         TestShutdownProvider::default(),
         TestTaskcluster::default(),
         TestPerfProvider::default(),
-
         TestSessionManager::default(),
         |mut recorder, _tempdir| async move {
             assert_matches!(
                 recorder
-                    .new_session("task_id", Some(&test_dir().join("README.md")), &[])
+                    .new_session(&task_build(), 1, Some(&test_dir().join("README.md")), &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
-                    let msg = e.to_string();
-                    assert!(msg.starts_with("could not read zip archive"));
-                    assert!(msg.ends_with(
-                        "Invalid Zip archive: Could not find central directory end"
-                    ));
+                    assert!(e.to_string().starts_with("unrecognized archive format"));
                 }
             );
         },
-        |RunnerInfo { result, session_info }| {
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
             let session_info = session_info.unwrap();
             assert_eq!(session_info.id, VALID_SESSION_ID);
 
             assert_matches!(
                 result.unwrap_err(),
-                RunnerProtoError::Zip(e @ ZipError::ReadArchive{ .. }) => {
+                RunnerProtoError::Zip(e @ ZipError::UnknownFormat(..)) => {
                     assert_eq!(
                         e.to_string(),
                         format!(
-                            "could not read zip archive `{}': Invalid Zip archive: Could not find central directory end",
+                            "unrecognized archive format: `{}'",
                             session_info.path.join("profile.zip").display()
                         )
                     );
@@ -525,7 +1974,7 @@ async fn test_new_session_err_recvprofile() {
         |mut recorder, _tempdir| async move {
             assert_matches!(
                 recorder
-                    .new_session("task_id", Some(&test_dir().join("empty.zip")), &[])
+                    .new_session(&task_build(), 1, Some(&test_dir().join("empty.zip")), &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
@@ -558,7 +2007,7 @@ async fn test_new_session_err_restarting() {
         TestSessionManager::default(),
         |mut recorder, _tempdir| async move {
             assert_matches!(
-                recorder.new_session("task_id", None, &[])
+                recorder.new_session(&task_build(), 1, None, &[])
                     .await
                     .unwrap_err(),
                 RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
@@ -587,18 +2036,32 @@ async fn test_new_session_err_restarting() {
 #[tokio::test]
 async fn test_resume_session_ok() {
     let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let session_manager = TestSessionManager::default();
+    let handle = session_manager.handle();
 
     run_proto_test(
         &mut listener,
         TestShutdownProvider::default(),
         TestTaskcluster::default(),
         TestPerfProvider::asserting_invoked(),
-        TestSessionManager::default(),
+        session_manager,
         |mut recorder, tempdir| async move {
-            recorder
+            let (_, readings) = recorder
                 .resume_session(VALID_SESSION_ID, Idle::Wait, &tempdir)
                 .await
                 .unwrap();
+
+            let readings = readings.unwrap();
+            assert_eq!(readings.cpu_idle, 0.99);
+            assert_eq!(readings.samples, 1);
+            assert_eq!(
+                readings.disks,
+                vec![DiskReading {
+                    device: "".into(),
+                    reads: 0,
+                    writes: 0,
+                }]
+            );
         },
         |RunnerInfo {
              result,
@@ -610,6 +2073,11 @@ async fn test_resume_session_ok() {
     )
     .await;
 
+    // The readings are kept for status requests.
+    let last_idle = handle.registry().last_idle().unwrap().unwrap();
+    assert_eq!(last_idle.session_id, VALID_SESSION_ID);
+    assert_eq!(last_idle.readings.samples, 1);
+
     run_proto_test(
         &mut listener,
         TestShutdownProvider::default(),
@@ -617,10 +2085,12 @@ async fn test_resume_session_ok() {
         TestPerfProvider::asserting_not_invoked(),
         TestSessionManager::default(),
         |mut recorder, tempdir| async move {
-            recorder
+            let (_, readings) = recorder
                 .resume_session(VALID_SESSION_ID, Idle::Skip, &tempdir)
                 .await
                 .unwrap();
+
+            assert!(readings.is_none());
         },
         |RunnerInfo {
              result,
@@ -633,6 +2103,124 @@ async fn test_resume_session_ok() {
     .await;
 }
 
+#[tokio::test]
+async fn test_new_session_multiple_runs() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_eq!(
+                recorder
+                    .new_session(&task_build(), 3, None, &[])
+                    .await
+                    .unwrap(),
+                VALID_SESSION_ID
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+            assert_file_contents_eq(&session_info.unwrap().runs_path(), "3");
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::default(),
+        |mut recorder, _tempdir| async move {
+            assert_matches!(
+                recorder.new_session(&task_build(), 0, None, &[]).await.unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(e.to_string(), "A session must have at least one run");
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(result.unwrap_err(), RunnerProtoError::NoRuns);
+            assert!(session_info.is_none());
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_resume_session_multiple_runs() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::with_remaining_runs(2),
+        |mut recorder, tempdir| async move {
+            recorder
+                .resume_session(VALID_SESSION_ID, Idle::Skip, &tempdir)
+                .await
+                .unwrap();
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_eq!(result.unwrap(), true);
+
+            let session_info = session_info.unwrap();
+            assert!(session_info.path.is_dir());
+            assert_file_contents_eq(&session_info.runs_path(), "1");
+            assert_session_state(&session_info, SessionState::RestartPending);
+        },
+    )
+    .await;
+
+    run_proto_test(
+        &mut listener,
+        TestShutdownProvider::with_error("could not restart"),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        TestSessionManager::with_remaining_runs(2),
+        |mut recorder, tempdir| async move {
+            assert_matches!(
+                recorder
+                    .resume_session(VALID_SESSION_ID, Idle::Skip, &tempdir)
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(e.to_string(), "could not restart");
+                }
+            );
+        },
+        |RunnerInfo {
+             result,
+             session_info,
+         }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::Shutdown(e) => {
+                    assert_eq!(e.to_string(), "could not restart");
+                }
+            );
+
+            assert!(!session_info.unwrap().path.exists());
+        },
+    )
+    .await;
+}
+
 #[tokio::test]
 async fn test_resume_session_err_request_manager() {
     let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -716,6 +2304,81 @@ async fn test_resume_session_err_request_manager() {
     .await;
 }
 
+#[tokio::test]
+async fn test_resume_session_idle_policy() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    // The disk is never idle, but its activity is within tolerance.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::with_failure(PerfFailureMode::DiskNeverIdle),
+        RunnerSettings::with_idle_policy(IdlePolicy {
+            sample_interval_ms: 1,
+            consecutive_samples: 3,
+            timeout_ms: 3,
+            disk_tolerance: DiskTolerance {
+                reads: 1,
+                writes: 1,
+            },
+            ..Default::default()
+        }),
+        TestSessionManager::default(),
+        |mut recorder, tempdir| async move {
+            let (_, readings) = recorder
+                .resume_session(VALID_SESSION_ID, Idle::Wait, &tempdir)
+                .await
+                .unwrap();
+
+            let readings = readings.unwrap();
+            assert_eq!(readings.samples, 3);
+            assert_eq!(readings.disks[0].reads, 1);
+            assert_eq!(readings.disks[0].writes, 1);
+        },
+        |RunnerInfo { result, .. }| {
+            assert_eq!(result.unwrap(), false);
+        },
+    )
+    .await;
+
+    // Too few samples are taken before the timeout.
+    run_proto_test_with_settings(
+        &mut listener,
+        TestShutdownProvider::default(),
+        TestTaskcluster::default(),
+        TestPerfProvider::default(),
+        RunnerSettings::with_idle_policy(IdlePolicy {
+            sample_interval_ms: 1,
+            consecutive_samples: 3,
+            timeout_ms: 2,
+            ..Default::default()
+        }),
+        TestSessionManager::default(),
+        |mut recorder, tempdir| async move {
+            assert_matches!(
+                recorder
+                    .resume_session(VALID_SESSION_ID, Idle::Wait, &tempdir)
+                    .await
+                    .unwrap_err(),
+                RecorderProtoError::Proto(ProtoError::Foreign(e)) => {
+                    assert_eq!(
+                        e.to_string(),
+                        "timed out waiting for CPU and disk to become idle"
+                    );
+                }
+            );
+        },
+        |RunnerInfo { result, .. }| {
+            assert_matches!(
+                result.unwrap_err(),
+                RunnerProtoError::WaitForIdle(WaitForIdleError::TimeoutError)
+            );
+        },
+    )
+    .await;
+}
+
 #[tokio::test]
 async fn test_resume_session_err_waitforidle() {
     let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -857,3 +2520,65 @@ async fn test_resume_session_err_waitforidle() {
     )
     .await;
 }
+
+#[tokio::test]
+async fn test_handshake_version_mismatch() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (runner_logger, _) = build_test_loggers();
+
+    let runner = async {
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let result = TestRunnerProto::handle_request(
+            runner_logger,
+            DISPLAY_SIZE,
+            stream,
+            TestShutdownProvider::default(),
+            TestTaskcluster::default(),
+            TestPerfProvider::asserting_not_invoked(),
+            test_idle_policy(),
+            TestSessionManager::default(),
+            None,
+            ExtractLimits::default(),
+            None,
+            Instant::now(),
+        )
+        .await;
+
+        assert_matches!(
+            result.unwrap_err(),
+            RunnerProtoError::Proto(ProtoError::VersionMismatch { local, remote }) => {
+                assert_eq!(local.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(remote.protocol_version, 0);
+            }
+        );
+    };
+
+    let recorder = async {
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let mut proto: Proto<
+            RunnerMessage,
+            RecorderMessage,
+            RunnerMessageKind,
+            RecorderMessageKind,
+        > = Proto::new(stream);
+
+        let mut version = VersionInfo::new("fxrecorder", "0.0.0");
+        version.protocol_version = 0;
+
+        proto.send(Handshake { version }).await.unwrap();
+
+        let HandshakeResponse { version, result } = proto.recv().await.unwrap();
+        assert_eq!(version.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(version.name, "fxrunner");
+        assert!(!version.capabilities.is_empty());
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("incompatible protocol versions"));
+        assert!(err.contains("capabilities: profile-stream"));
+    };
+
+    join!(runner, recorder);
+}
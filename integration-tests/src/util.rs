@@ -2,10 +2,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+use libfxrecord::net::{ProfileInfo, ProfileTransfer, SessionState};
+use libfxrunner::session::SessionInfo;
+use sha2::{Digest, Sha256};
+
 /// A test helper that is used to assert an operation either occurred or did not
 /// before being dropped.
 #[derive(Debug)]
@@ -55,6 +59,17 @@ pub fn firefox_zip_path() -> PathBuf {
     PathBuf::from(env!("OUT_DIR")).join("firefox.zip")
 }
 
+/// Describe the profile archive at the given path.
+pub fn profile_info(path: &Path) -> ProfileInfo {
+    let contents = fs::read(path).unwrap();
+
+    ProfileInfo {
+        size: contents.len() as u64,
+        sha256: format!("{:x}", Sha256::digest(&contents)),
+        transfer: ProfileTransfer::Archive,
+    }
+}
+
 pub fn directory_is_empty(path: &Path) -> bool {
     path.read_dir()
         .unwrap()
@@ -76,6 +91,13 @@ pub fn assert_populated_profile(profile_dir: &Path) {
     assert!(profile_dir.join("user.js").is_file());
 }
 
+/// Assert the persisted state of the session.
+pub fn assert_session_state(session_info: &SessionInfo, expected: SessionState) {
+    let state: SessionState =
+        serde_json::from_slice(&fs::read(session_info.state_path()).unwrap()).unwrap();
+    assert_eq!(state, expected);
+}
+
 pub fn assert_file_contents_eq(path: &Path, expected: &'static str) {
     let contents = {
         let mut buf = String::new();
@@ -23,6 +23,22 @@ use thiserror::Error;
 use crate::error::ErrorMessage;
 use crate::prefs::PrefValue;
 
+/// Serialization of binary data as base64 strings, which are much more compact
+/// than JSON arrays of numbers.
+mod base64_data {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(&encoded).map_err(D::Error::custom)
+    }
+}
+
 /// A message is a serializable and deserializable type.
 pub trait Message<'de>: Serialize + Deserialize<'de> + Unpin {
     /// Each message has a kind that uniquely identifies it.
@@ -67,6 +83,42 @@ impl From<ResumeSessionRequest> for Session {
     }
 }
 
+impl From<ResumeProfileRequest> for Session {
+    fn from(req: ResumeProfileRequest) -> Session {
+        Session::ResumeProfile(req)
+    }
+}
+
+impl From<QuerySessionRequest> for Session {
+    fn from(req: QuerySessionRequest) -> Session {
+        Session::QuerySession(req)
+    }
+}
+
+impl From<ListSessionsRequest> for Session {
+    fn from(req: ListSessionsRequest) -> Session {
+        Session::ListSessions(req)
+    }
+}
+
+impl From<DeleteSessionRequest> for Session {
+    fn from(req: DeleteSessionRequest) -> Session {
+        Session::DeleteSession(req)
+    }
+}
+
+impl From<PurgeSessionsRequest> for Session {
+    fn from(req: PurgeSessionsRequest) -> Session {
+        Session::PurgeSessions(req)
+    }
+}
+
+impl From<StatusRequest> for Session {
+    fn from(req: StatusRequest) -> Session {
+        Session::Status(req)
+    }
+}
+
 /// Whether the runner should wait to become idle.
 #[derive(Clone, Copy, Debug, Eq, Deserialize, PartialEq, Serialize)]
 pub enum Idle {
@@ -77,21 +129,156 @@ pub enum Idle {
     Skip,
 }
 
+/// The readings taken when the runner became idle.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdleReadings {
+    /// The fraction of time the CPU was idle during the last sample interval.
+    pub cpu_idle: f64,
+
+    /// The disk activity of each monitored device during the last sample
+    /// interval.
+    pub disks: Vec<DiskReading>,
+
+    /// The number of samples taken before the runner became idle.
+    pub samples: u32,
+
+    /// How long the runner waited to become idle (in milliseconds).
+    pub elapsed_ms: u64,
+}
+
+/// The disk activity of a single device during a sample interval.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DiskReading {
+    /// The name of the device.
+    pub device: String,
+
+    /// The number of reads completed.
+    pub reads: u32,
+
+    /// The number of writes completed.
+    pub writes: u32,
+}
+
+/// The platform of builds that are looked up by revision or branch.
+pub const BUILD_PLATFORM: &str = "win64-shippable-opt";
+
+/// The branch that builds are looked up on by default.
+pub const DEFAULT_BRANCH: &str = "mozilla-central";
+
+/// The build that a session will use.
+#[derive(Clone, Debug, Deserialize, Display, Eq, PartialEq, Serialize)]
+pub enum BuildSource {
+    /// The ID of a build task.
+    #[display(fmt = "task {}", _0)]
+    TaskId(String),
+
+    /// A path in the Taskcluster index that the runner will resolve to a build
+    /// task.
+    #[display(fmt = "index {}", _0)]
+    IndexPath(String),
+
+    /// A build archive of the given size that the recorder will send to the
+    /// runner.
+    ///
+    /// The archive is sent once the runner reports that it is
+    /// [downloading](enum.DownloadStatus.html#variant.Downloading) the build.
+    #[display(fmt = "sideloaded build ({} bytes)", size)]
+    Sideload { size: u64 },
+
+    /// The path to a build archive on the runner, such as on a shared
+    /// directory.
+    #[display(fmt = "runner path {}", _0)]
+    RunnerPath(String),
+}
+
+impl BuildSource {
+    /// The build for the given revision on the given branch.
+    pub fn revision(branch: &str, revision: &str) -> Self {
+        BuildSource::IndexPath(format!(
+            "gecko.v2.{}.revision.{}.firefox.{}",
+            branch, revision, BUILD_PLATFORM
+        ))
+    }
+
+    /// The latest build on the given branch.
+    pub fn latest(branch: &str) -> Self {
+        BuildSource::IndexPath(format!(
+            "gecko.v2.{}.latest.firefox.{}",
+            branch, BUILD_PLATFORM
+        ))
+    }
+
+    /// Whether or not the build comes from Taskcluster.
+    ///
+    /// The runner reports the task that it resolves Taskcluster builds to with
+    /// a [`ResolveBuild`](struct.ResolveBuild.html) message.
+    pub fn is_taskcluster(&self) -> bool {
+        matches!(self, BuildSource::TaskId(..) | BuildSource::IndexPath(..))
+    }
+}
+
+/// A profile archive that will be sent to the runner.
+///
+/// The archive is sent in [`ProfileChunk`](struct.ProfileChunk.html)
+/// messages.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ProfileInfo {
+    /// The size of the archive (in bytes).
+    pub size: u64,
+
+    /// The SHA-256 digest of the archive, as a hexadecimal string.
+    ///
+    /// The runner verifies the archive it receives against this digest.
+    pub sha256: String,
+
+    /// How the runner handles the archive.
+    pub transfer: ProfileTransfer,
+}
+
+/// How the runner handles a profile archive as it is received.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ProfileTransfer {
+    /// The runner writes the archive to disk and extracts it once it has been
+    /// received.
+    ///
+    /// If the transfer is interrupted, it can be resumed with a
+    /// [`ResumeProfile`](enum.Session.html#variant.ResumeProfile) request.
+    Archive,
+
+    /// The runner extracts the archive, which must be a tar archive, as it is
+    /// received.
+    ///
+    /// If the transfer is interrupted, the session is discarded.
+    Stream,
+}
+
 /// A request for a new session.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct NewSessionRequest {
-    /// The task ID of the Taskcluster build task.
-    ///
-    /// The build artifact from this task will be downloaded by the runner.
-    pub build_task_id: String,
+    /// The build to use.
+    pub build: BuildSource,
+
+    /// The profile that will be sent, if any.
+    pub profile: Option<ProfileInfo>,
 
-    /// The size of the profile that will be sent, if any.
-    pub profile_size: Option<u64>,
+    /// The number of times Firefox will be started in the session.
+    ///
+    /// The runner will restart between each run and the recorder must send a
+    /// [`ResumeSession`](enum.Session.html#variant.ResumeSession) for each.
+    pub runs: u32,
 
     /// Prefs to override in the profile.
     pub prefs: Vec<(String, PrefValue)>,
 }
 
+/// A request to resume the interrupted transfer of a profile for a new
+/// session.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResumeProfileRequest {
+    /// The ID of the session the profile is for.
+    pub session_id: String,
+}
+
 /// A request to resume an existing session.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ResumeSessionRequest {
@@ -102,10 +289,161 @@ pub struct ResumeSessionRequest {
     pub idle: Idle,
 }
 
+/// A request for the state of an existing session.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QuerySessionRequest {
+    /// The ID of the session.
+    pub session_id: String,
+}
+
+/// A request for all sessions on the runner.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListSessionsRequest;
+
+/// A request to delete a session.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteSessionRequest {
+    /// The ID of the session.
+    pub session_id: String,
+}
+
+/// A request to delete all sessions on the runner.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PurgeSessionsRequest;
+
+/// A request for the status of the runner.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StatusRequest;
+
+/// The state of a session on the runner.
+///
+/// A session moves through these states in order, except that a session in the
+/// `ProfilePending` state returns to receiving its profile when it is resumed
+/// and a session in the `RestartPending` state returns to it after each run
+/// but the last. A session that the runner started from its job queue may
+/// instead end up `Failed`.
+#[derive(Clone, Copy, Debug, Deserialize, Display, Eq, PartialEq, Serialize)]
+pub enum SessionState {
+    /// The session was submitted as a job and is waiting for the runner to
+    /// start it.
+    Queued,
+
+    /// The session directory has been created.
+    Created,
+
+    /// The build has been extracted into the session directory.
+    BuildExtracted,
+
+    /// The transfer of the profile was interrupted and is waiting to be
+    /// resumed.
+    ProfilePending,
+
+    /// The profile has been received or created.
+    ProfileReady,
+
+    /// Prefs have been written to the profile.
+    PrefsWritten,
+
+    /// The runner is restarting and the session is waiting for the recorder to
+    /// resume it.
+    RestartPending,
+
+    /// Firefox is being run for the session.
+    Running,
+
+    /// The last run of the session has finished.
+    Finished,
+
+    /// The runner could not start the session's job.
+    Failed,
+}
+
+/// A description of a session on the runner.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SessionSummary {
+    /// The ID of the session.
+    pub session_id: String,
+
+    /// When the session was created (in seconds since the Unix epoch).
+    pub created: u64,
+
+    /// When the session was last used (in seconds since the Unix epoch).
+    pub updated: u64,
+
+    /// When the session will expire if it is not used again (in seconds since
+    /// the Unix epoch).
+    pub expires: u64,
+
+    /// The build under test (e.g., the ID of its build task), once it is known.
+    pub build: Option<String>,
+
+    /// The SHA-256 digest of the profile archive, if a profile was sent.
+    pub profile_sha256: Option<String>,
+
+    /// The state of the session.
+    pub state: SessionState,
+
+    /// The address of the recorder that created the session.
+    pub owner: Option<String>,
+
+    /// The size of the session on disk (in bytes).
+    pub size: u64,
+
+    /// Why the session failed, if it is `Failed`.
+    pub error: Option<String>,
+}
+
+/// The status of the runner.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RunnerStatus {
+    /// The version of the runner.
+    pub version: VersionInfo,
+
+    /// How long the runner has been running (in seconds).
+    pub uptime_secs: u64,
+
+    /// The most recently used session that has not finished, if any.
+    pub current_session: Option<SessionSummary>,
+
+    /// The space available in the session directory (in bytes), if the runner
+    /// can determine it.
+    pub free_disk: Option<u64>,
+
+    /// The readings taken the last time the runner became idle, if it has.
+    pub last_idle: Option<IdleRecord>,
+}
+
+/// Idle readings recorded by the runner.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdleRecord {
+    /// When the readings were taken (in seconds since the Unix epoch).
+    pub recorded: u64,
+
+    /// The ID of the session that was waiting for the runner to become idle.
+    pub session_id: String,
+
+    /// The readings.
+    pub readings: IdleReadings,
+}
+
 #[derive(Debug, Display, Eq, PartialEq, Serialize, Deserialize)]
 pub enum DownloadStatus {
     Downloading,
+
+    /// The download is in progress.
+    ///
+    /// This is sent periodically between `Downloading` and `Downloaded`.
+    #[display(fmt = "Progress({})", _0)]
+    Progress(DownloadProgress),
+
     Downloaded,
+
+    /// The download was skipped because the runner had already cached it.
+    Cached,
+
+    /// The download was skipped because the build was already on the runner.
+    Local,
+
     Extracted,
 }
 
@@ -114,14 +452,123 @@ impl DownloadStatus {
     pub fn next(&self) -> Option<DownloadStatus> {
         match self {
             DownloadStatus::Downloading => Some(DownloadStatus::Downloaded),
+            DownloadStatus::Progress(..) => Some(DownloadStatus::Downloaded),
             DownloadStatus::Downloaded => Some(DownloadStatus::Extracted),
+            DownloadStatus::Cached => Some(DownloadStatus::Extracted),
+            DownloadStatus::Local => Some(DownloadStatus::Extracted),
             DownloadStatus::Extracted => None,
         }
     }
 }
 
+/// The progress of a download.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DownloadProgress {
+    /// The number of bytes received so far.
+    pub received: u64,
+
+    /// The total size of the download (in bytes), if known.
+    pub total: Option<u64>,
+
+    /// The average throughput of the download (in bytes per second).
+    pub bytes_per_sec: u64,
+}
+
+impl Display for DownloadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "{}/{} bytes", self.received, total)?,
+            None => write!(f, "{} bytes", self.received)?,
+        }
+
+        write!(f, " at {} bytes/s", self.bytes_per_sec)
+    }
+}
+
 pub type ForeignResult<T> = Result<T, ErrorMessage<String>>;
 
+/// The version of the protocol spoken between the recorder and runner.
+///
+/// This must be incremented whenever a message is added, removed, or has its
+/// contents changed.
+pub const PROTOCOL_VERSION: u32 = 14;
+
+/// The optional features supported by this version of `libfxrecord`.
+///
+/// Peers advertise these during the handshake so that a feature can be
+/// detected without bumping the [protocol version](constant.PROTOCOL_VERSION.html).
+pub const CAPABILITIES: &[&str] = &["profile-stream", "runner-build", "status", "submit-job"];
+
+/// Version information exchanged during the handshake.
+///
+/// The layout of this struct (and of the
+/// [`Handshake`](struct.Handshake.html) and
+/// [`HandshakeResponse`](struct.HandshakeResponse.html) messages) must not change
+/// between protocol versions, or else peers will be unable to report a
+/// mismatch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VersionInfo {
+    /// The protocol version spoken by the peer.
+    pub protocol_version: u32,
+
+    /// The name of the crate on the other side of the connection (i.e.,
+    /// `fxrecorder` or `fxrunner`).
+    pub name: String,
+
+    /// The version of the crate on the other side of the connection.
+    pub version: String,
+
+    /// The version of `libfxrecord` used by the peer.
+    pub libfxrecord_version: String,
+
+    /// Optional features supported by the peer.
+    ///
+    /// Peers that predate capabilities do not send this field.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+impl VersionInfo {
+    /// Create version information for the crate with the given name and
+    /// version, speaking the current protocol version.
+    pub fn new(name: &str, version: &str) -> Self {
+        VersionInfo {
+            protocol_version: PROTOCOL_VERSION,
+            name: name.into(),
+            version: version.into(),
+            libfxrecord_version: env!("CARGO_PKG_VERSION").into(),
+            capabilities: CAPABILITIES.iter().map(|c| (*c).into()).collect(),
+        }
+    }
+
+    /// Whether or not a peer with the given version information can be
+    /// communicated with.
+    pub fn is_compatible(&self, other: &VersionInfo) -> bool {
+        self.protocol_version == other.protocol_version
+    }
+
+    /// Whether or not the peer supports the given capability.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl Display for VersionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} (protocol version {}",
+            self.name, self.version, self.protocol_version
+        )?;
+
+        if !self.capabilities.is_empty() {
+            write!(f, "; capabilities: {}", self.capabilities.join(", "))?;
+        }
+
+        write!(f, ")")
+    }
+}
+
 message_type! {
     /// A message from FxRecorder to FxRunner.
     RecorderMessage,
@@ -129,6 +576,14 @@ message_type! {
     /// The kind of a [`RecorderMessage`](struct.RecorderMessage.html).
     RecorderMessageKind;
 
+    /// The first message sent on every connection.
+    ///
+    /// The runner will respond with a
+    /// [`HandshakeResponse`](struct.HandshakeResponse.html).
+    pub struct Handshake {
+        pub version: VersionInfo,
+    }
+
     /// A request from the recorder to the runner.
     pub enum Session {
         /// A request for a new session.
@@ -141,6 +596,69 @@ message_type! {
         /// A request to resume a [previous
         /// request](enum.RecorderSession.html#variant.NewSession).
         ResumeSession(ResumeSessionRequest),
+
+        /// A request to resume sending the profile for a [new
+        /// session](enum.RecorderSession.html#variant.NewSession) after the
+        /// transfer was interrupted.
+        ///
+        /// The runner will respond with a
+        /// [`ResumeProfileResponse`](struct.ResumeProfileResponse.html).
+        ResumeProfile(ResumeProfileRequest),
+
+        /// A request for the state of an existing session.
+        ///
+        /// This does not modify the session. The runner will respond with a
+        /// [`QuerySessionResponse`](struct.QuerySessionResponse.html).
+        QuerySession(QuerySessionRequest),
+
+        /// A request for all sessions on the runner.
+        ///
+        /// The runner will respond with a
+        /// [`ListSessionsResponse`](struct.ListSessionsResponse.html).
+        ListSessions(ListSessionsRequest),
+
+        /// A request to delete a session.
+        ///
+        /// The runner will respond with a
+        /// [`DeleteSessionResponse`](struct.DeleteSessionResponse.html).
+        DeleteSession(DeleteSessionRequest),
+
+        /// A request to delete all sessions on the runner.
+        ///
+        /// The runner will respond with a
+        /// [`PurgeSessionsResponse`](struct.PurgeSessionsResponse.html).
+        PurgeSessions(PurgeSessionsRequest),
+
+        /// A request for the status of the runner.
+        ///
+        /// This does not start or modify a session. The runner will respond
+        /// with a [`StatusResponse`](struct.StatusResponse.html).
+        Status(StatusRequest),
+
+        /// A request to queue a new session as a job.
+        ///
+        /// The runner will respond with a
+        /// [`SubmitJobResponse`](struct.SubmitJobResponse.html) and then
+        /// receive the profile, if any, but will not set up the session until
+        /// every job submitted before it has finished. The ID of the job is the
+        /// ID of its session, which the recorder should query until the
+        /// session is `RestartPending` and then resume as usual.
+        ///
+        /// Sideloaded builds cannot be queued.
+        SubmitJob(NewSessionRequest),
+    }
+
+    /// A chunk of the profile archive.
+    ///
+    /// Chunks are sent in order once the runner is ready to receive the
+    /// profile.
+    pub struct ProfileChunk {
+        /// The offset of the chunk in the archive.
+        pub offset: u64,
+
+        /// The contents of the chunk.
+        #[serde(with = "base64_data")]
+        pub data: Vec<u8>,
     }
 
     /// Request the runner start Firefox.
@@ -161,6 +679,21 @@ message_type! {
     /// The kind of a [`RunnerMessage`](struct.RunnerMessage.html).
     RunnerMessageKind;
 
+    /// The response to a [`Handshake`](struct.Handshake.html).
+    pub struct HandshakeResponse {
+        /// The version of the runner.
+        pub version: VersionInfo,
+
+        /// Whether or not the runner accepted the handshake.
+        pub result: ForeignResult<()>,
+    }
+
+    /// The ID of the build task that the runner resolved the requested build
+    /// to.
+    pub struct ResolveBuild {
+        pub result: ForeignResult<String>,
+    }
+
     /// The status of the DownloadBuild phase.
     pub struct DownloadBuild {
         pub result: ForeignResult<DownloadStatus>,
@@ -176,6 +709,53 @@ message_type! {
         pub result: ForeignResult<DownloadStatus>,
     }
 
+    /// The response to a
+    /// [`ResumeProfile`](enum.Session.html#variant.ResumeProfile) request.
+    pub struct ResumeProfileResponse {
+        /// The offset in the profile archive from which the recorder should
+        /// continue sending [`ProfileChunk`](struct.ProfileChunk.html)s.
+        pub result: ForeignResult<u64>,
+    }
+
+    /// The response to a
+    /// [`QuerySession`](enum.Session.html#variant.QuerySession) request.
+    pub struct QuerySessionResponse {
+        /// The session, if it exists.
+        pub result: ForeignResult<Option<SessionSummary>>,
+    }
+
+    /// The response to a
+    /// [`ListSessions`](enum.Session.html#variant.ListSessions) request.
+    pub struct ListSessionsResponse {
+        /// The sessions on the runner, oldest first.
+        pub result: ForeignResult<Vec<SessionSummary>>,
+    }
+
+    /// The response to a
+    /// [`DeleteSession`](enum.Session.html#variant.DeleteSession) request.
+    pub struct DeleteSessionResponse {
+        pub result: ForeignResult<()>,
+    }
+
+    /// The response to a
+    /// [`PurgeSessions`](enum.Session.html#variant.PurgeSessions) request.
+    pub struct PurgeSessionsResponse {
+        /// The IDs of the deleted sessions.
+        pub result: ForeignResult<Vec<String>>,
+    }
+
+    /// The response to a [`Status`](enum.Session.html#variant.Status) request.
+    pub struct StatusResponse {
+        pub result: ForeignResult<RunnerStatus>,
+    }
+
+    /// The response to a [`SubmitJob`](enum.Session.html#variant.SubmitJob)
+    /// request.
+    pub struct SubmitJobResponse {
+        /// The ID of the job.
+        pub result: ForeignResult<String>,
+    }
+
     /// The result of the CreateProfile phase.
     pub struct CreateProfile {
         pub result: ForeignResult<()>,
@@ -200,12 +780,18 @@ message_type! {
 
     /// The status of the ResumeResponse phase.
     pub struct ResumeResponse {
-        pub result: ForeignResult<()>,
+        /// The number of runs remaining in the session, including this one.
+        ///
+        /// If more than one run remains, the runner will send a
+        /// [`Restarting`](struct.Restarting.html) after the
+        /// [`SessionFinished`](struct.SessionFinished.html) message.
+        pub result: ForeignResult<u32>,
     }
 
     /// The status of the WaitForIdle phase.
     pub struct WaitForIdle {
-        pub result: ForeignResult<()>,
+        /// The readings taken when the runner became idle.
+        pub result: ForeignResult<IdleReadings>,
     }
 
     /// The status of the StartFirefox phase.
@@ -218,8 +804,39 @@ message_type! {
         pub result: Result<(), Vec<ErrorMessage<String>>>,
     }
 
-    /// The status of any cleanup or teardown before the session finishes.
+    /// The status of any cleanup or teardown before the run finishes.
     pub struct SessionFinished {
         pub result: ForeignResult<()>,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_version_info_capabilities() {
+        let version = VersionInfo::new("fxrunner", "0.1.0");
+        assert!(version.has_capability("profile-stream"));
+        assert!(!version.has_capability("unknown"));
+        assert_eq!(
+            version.to_string(),
+            "fxrunner 0.1.0 (protocol version 14; capabilities: profile-stream, runner-build, status, submit-job)"
+        );
+
+        let version: VersionInfo = serde_json::from_str(
+            r#"{
+                "protocol_version": 13,
+                "name": "fxrecorder",
+                "version": "0.1.0",
+                "libfxrecord_version": "0.1.0"
+            }"#,
+        )
+        .unwrap();
+        assert!(version.capabilities.is_empty());
+        assert_eq!(
+            version.to_string(),
+            "fxrecorder 0.1.0 (protocol version 13)"
+        );
+    }
+}
@@ -12,7 +12,7 @@ use tokio_serde::formats::Json;
 use tokio_util::codec::LengthDelimitedCodec;
 
 use crate::error::ErrorMessage;
-use crate::net::message::{KindMismatch, Message, MessageContent};
+use crate::net::message::{KindMismatch, Message, MessageContent, VersionInfo};
 
 /// A protocol for receiving messages of type `R` and sending messages of type
 /// `S` over a `TcpStream`.
@@ -123,4 +123,15 @@ pub enum ProtoError<K: Debug + Display> {
         .0.actual
     )]
     Unexpected(KindMismatch<K>),
+
+    /// The peer speaks an incompatible version of the protocol.
+    #[error(
+        "incompatible protocol versions: local is {} but remote is {}",
+        .local,
+        .remote
+    )]
+    VersionMismatch {
+        local: Box<VersionInfo>,
+        remote: Box<VersionInfo>,
+    },
 }